@@ -0,0 +1,8 @@
+/* nRF52840 with 1 MB of flash and 256 kB of RAM */
+MEMORY
+{
+  /* The last 34 pages of the flash are reserved for data, see `src/flash/mod.rs`: the 32 pages
+     of the log and the 2 pages of the configuration. The linker places nothing there. */
+  FLASH : ORIGIN = 0x00000000, LENGTH = 0xDE000
+  RAM : ORIGIN = 0x20000000, LENGTH = 256K
+}
@@ -0,0 +1,254 @@
+// BLE advertising payloads with the latest measurement, for phones nearby.
+//
+// The payloads are complete advertising data, a sequence of AD structures
+// `| length u8 | AD type u8 | data |`, to be handed to the BLE stack as is. Both formats start
+// with the flags of a general discoverable device without BR/EDR.
+//
+// Environmental Sensing, one service data structure per characteristic, values as the
+// characteristics define them:
+// | 0x2a6e temperature 0.01 °C i16 | 0x2a6f humidity 0.01 % u16 | 0x2b8c CO2 ppm u16 |
+// | 0x2a19 battery level % u8      |
+// The service UUID list names the Environmental Sensing Service, 0x181a.
+//
+// BTHome v2, service data of UUID 0xfcd2: device information, then objects ordered by their ID:
+// | 0x01 battery % u8 | 0x02 temperature 0.01 °C i16 | 0x03 humidity 0.01 % u16 |
+// | 0x12 CO2 ppm u16  |
+//
+// All values are little endian. The battery level is left out if the device has no battery.
+// Values out of range are clamped to the range of their field.
+//
+// The module only depends on `core` and `SensorData`.
+
+use crate::{co2_sensor::SensorData, number_representation::round};
+
+/// Longest legacy advertising payload
+pub const MAX_PAYLOAD_SIZE: usize = 31;
+
+// AD types
+const FLAGS: u8 = 0x01;
+const COMPLETE_SERVICE_UUIDS_16: u8 = 0x03;
+const SERVICE_DATA_16: u8 = 0x16;
+
+// LE general discoverable, BR/EDR not supported
+const FLAGS_GENERAL_DISCOVERABLE: u8 = 0x06;
+
+// Environmental Sensing Service and the characteristics it advertises
+const ENVIRONMENTAL_SENSING: u16 = 0x181a;
+const TEMPERATURE: u16 = 0x2a6e;
+const HUMIDITY: u16 = 0x2a6f;
+const CO2_CONCENTRATION: u16 = 0x2b8c;
+const BATTERY_LEVEL: u16 = 0x2a19;
+
+const BTHOME: u16 = 0xfcd2;
+// version 2, not encrypted, sends regularly
+const BTHOME_DEVICE_INFO: u8 = 0x40;
+// object IDs
+const BTHOME_BATTERY: u8 = 0x01;
+const BTHOME_TEMPERATURE: u8 = 0x02;
+const BTHOME_HUMIDITY: u8 = 0x03;
+const BTHOME_CO2: u8 = 0x12;
+
+/// Advertising data, at most 31 bytes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Payload {
+    bytes: [u8; MAX_PAYLOAD_SIZE],
+    length: usize,
+}
+
+impl Payload {
+    fn new() -> Self {
+        Payload {
+            bytes: [0; MAX_PAYLOAD_SIZE],
+            length: 0,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.length]
+    }
+
+    // both formats fit, whatever the values are
+    fn push(&mut self, bytes: &[u8]) {
+        self.bytes[self.length..self.length + bytes.len()].copy_from_slice(bytes);
+        self.length += bytes.len();
+    }
+
+    // `| length | AD type | data |`, the length covers type and data
+    fn structure(&mut self, ad_type: u8, data: &[u8]) {
+        self.push(&[data.len() as u8 + 1, ad_type]);
+        self.push(data);
+    }
+}
+
+/// Environmental Sensing format. `battery` is the charge in %, `None` without a battery.
+pub fn environmental_sensing(data: &SensorData, battery: Option<u8>) -> Payload {
+    let mut payload = Payload::new();
+    payload.structure(FLAGS, &[FLAGS_GENERAL_DISCOVERABLE]);
+    payload.structure(
+        COMPLETE_SERVICE_UUIDS_16,
+        &ENVIRONMENTAL_SENSING.to_le_bytes(),
+    );
+
+    let (temperature, humidity, co2) = fixed_point(data);
+    characteristic(&mut payload, TEMPERATURE, &temperature.to_le_bytes());
+    characteristic(&mut payload, HUMIDITY, &humidity.to_le_bytes());
+    characteristic(&mut payload, CO2_CONCENTRATION, &co2.to_le_bytes());
+    if let Some(battery) = battery {
+        characteristic(&mut payload, BATTERY_LEVEL, &[battery.min(100)]);
+    }
+    payload
+}
+
+/// BTHome v2 format, unencrypted. `battery` is the charge in %, `None` without a battery.
+pub fn bthome(data: &SensorData, battery: Option<u8>) -> Payload {
+    let mut payload = Payload::new();
+    payload.structure(FLAGS, &[FLAGS_GENERAL_DISCOVERABLE]);
+
+    // the service data is assembled first, its length comes before it
+    let mut service_data = Payload::new();
+    service_data.push(&BTHOME.to_le_bytes());
+    service_data.push(&[BTHOME_DEVICE_INFO]);
+    if let Some(battery) = battery {
+        service_data.push(&[BTHOME_BATTERY, battery.min(100)]);
+    }
+    let (temperature, humidity, co2) = fixed_point(data);
+    let [low, high] = temperature.to_le_bytes();
+    service_data.push(&[BTHOME_TEMPERATURE, low, high]);
+    let [low, high] = humidity.to_le_bytes();
+    service_data.push(&[BTHOME_HUMIDITY, low, high]);
+    let [low, high] = co2.to_le_bytes();
+    service_data.push(&[BTHOME_CO2, low, high]);
+
+    payload.structure(SERVICE_DATA_16, service_data.as_bytes());
+    payload
+}
+
+// service data of a single characteristic: its UUID, then its value
+fn characteristic(payload: &mut Payload, uuid: u16, value: &[u8]) {
+    let mut data = [0u8; 4];
+    data[..2].copy_from_slice(&uuid.to_le_bytes());
+    data[2..2 + value.len()].copy_from_slice(value);
+    payload.structure(SERVICE_DATA_16, &data[..2 + value.len()]);
+}
+
+// temperature in 0.01 °C, humidity in 0.01 % and CO2 in ppm; `as` saturates at the limits
+fn fixed_point(data: &SensorData) -> (i16, u16, u16) {
+    (
+        round(data.temperature * 100.0) as i16,
+        (round(data.humidity * 100.0) as u16).min(10_000),
+        round(data.co2) as u16,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(co2: f32, temperature: f32, humidity: f32) -> SensorData {
+        SensorData {
+            co2,
+            temperature,
+            humidity,
+        }
+    }
+
+    #[test]
+    fn environmental_sensing_with_battery() {
+        let payload = environmental_sensing(&data(1250.4, 23.456, 45.0), Some(87));
+        #[rustfmt::skip]
+        let expected = [
+            0x02, 0x01, 0x06,
+            0x03, 0x03, 0x1a, 0x18,
+            0x05, 0x16, 0x6e, 0x2a, 0x2a, 0x09, // 23.46 °C
+            0x05, 0x16, 0x6f, 0x2a, 0x94, 0x11, // 45.00 %
+            0x05, 0x16, 0x8c, 0x2b, 0xe2, 0x04, // 1250 ppm
+            0x04, 0x16, 0x19, 0x2a, 0x57,       // 87 %
+        ];
+        assert_eq!(payload.as_bytes(), expected);
+        assert!(payload.as_bytes().len() <= MAX_PAYLOAD_SIZE);
+    }
+
+    #[test]
+    fn environmental_sensing_without_battery() {
+        let payload = environmental_sensing(&data(1250.4, 23.456, 45.0), None);
+        #[rustfmt::skip]
+        let expected = [
+            0x02, 0x01, 0x06,
+            0x03, 0x03, 0x1a, 0x18,
+            0x05, 0x16, 0x6e, 0x2a, 0x2a, 0x09,
+            0x05, 0x16, 0x6f, 0x2a, 0x94, 0x11,
+            0x05, 0x16, 0x8c, 0x2b, 0xe2, 0x04,
+        ];
+        assert_eq!(payload.as_bytes(), expected);
+    }
+
+    #[test]
+    fn bthome_with_battery() {
+        let payload = bthome(&data(1250.4, 23.456, 45.0), Some(87));
+        #[rustfmt::skip]
+        let expected = [
+            0x02, 0x01, 0x06,
+            0x0f, 0x16, 0xd2, 0xfc, 0x40,
+            0x01, 0x57,       // 87 %
+            0x02, 0x2a, 0x09, // 23.46 °C
+            0x03, 0x94, 0x11, // 45.00 %
+            0x12, 0xe2, 0x04, // 1250 ppm
+        ];
+        assert_eq!(payload.as_bytes(), expected);
+    }
+
+    #[test]
+    fn bthome_without_battery() {
+        let payload = bthome(&data(1250.4, 23.456, 45.0), None);
+        #[rustfmt::skip]
+        let expected = [
+            0x02, 0x01, 0x06,
+            0x0d, 0x16, 0xd2, 0xfc, 0x40,
+            0x02, 0x2a, 0x09,
+            0x03, 0x94, 0x11,
+            0x12, 0xe2, 0x04,
+        ];
+        assert_eq!(payload.as_bytes(), expected);
+    }
+
+    #[test]
+    fn negative_temperature() {
+        let data = data(412.0, -12.34, 80.5);
+
+        // -1234 in 0.01 °C
+        let payload = environmental_sensing(&data, None);
+        assert_eq!(
+            payload.as_bytes()[7..13],
+            [0x05, 0x16, 0x6e, 0x2a, 0x2e, 0xfb]
+        );
+        let payload = bthome(&data, None);
+        assert_eq!(payload.as_bytes()[8..11], [0x02, 0x2e, 0xfb]);
+    }
+
+    #[test]
+    fn values_are_clamped() {
+        let payload = environmental_sensing(&data(70_000.0, 400.0, 120.0), Some(150));
+        #[rustfmt::skip]
+        let expected = [
+            0x02, 0x01, 0x06,
+            0x03, 0x03, 0x1a, 0x18,
+            0x05, 0x16, 0x6e, 0x2a, 0xff, 0x7f, // 327.67 °C
+            0x05, 0x16, 0x6f, 0x2a, 0x10, 0x27, // 100.00 %
+            0x05, 0x16, 0x8c, 0x2b, 0xff, 0xff, // 65535 ppm
+            0x04, 0x16, 0x19, 0x2a, 0x64,       // 100 %
+        ];
+        assert_eq!(payload.as_bytes(), expected);
+
+        let payload = bthome(&data(-3.0, -400.0, -1.0), Some(150));
+        #[rustfmt::skip]
+        let expected = [
+            0x02, 0x01, 0x06,
+            0x0f, 0x16, 0xd2, 0xfc, 0x40,
+            0x01, 0x64,       // 100 %
+            0x02, 0x00, 0x80, // -327.68 °C
+            0x03, 0x00, 0x00, // 0 %
+            0x12, 0x00, 0x00, // 0 ppm
+        ];
+        assert_eq!(payload.as_bytes(), expected);
+    }
+}
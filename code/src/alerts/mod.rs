@@ -1,23 +1,245 @@
-use nrf52840_hal::{pac::TIMER0, timer::OneShot, Timer};
-
-use crate::buzzer::Buzzer;
-use crate::rgb_led::LEDColor;
-
-const UPPER_LIMIT: f32 = 2000.0;
-const WARN_LIMIT: f32 = 1000.0;
-
-pub fn check_levels(
-    co2: &f32,
-    buzzer: &mut Buzzer,
-    led: &mut LEDColor,
-    mut timer: &mut Timer<TIMER0, OneShot>,
-) {
-    if *co2 < WARN_LIMIT {
-        led.green();
-    } else if *co2 > WARN_LIMIT && *co2 < UPPER_LIMIT {
-        led.yellow();
-    } else {
-        led.red();
-        buzzer.noise(&mut timer)
+#[cfg(target_os = "none")]
+mod signalling;
+#[cfg(target_os = "none")]
+pub use signalling::{check_levels, show_category, signal};
+
+/// typical CO2 concentration of outdoor air, in ppm
+pub const DEFAULT_OUTDOOR_CO2: f32 = 420.0;
+
+// Upper limits of the categories I, II and III in ppm above outdoor air.
+// Anything above the last limit is category IV.
+// EN 16798-1:2019, Annex B, default design values
+const EN_16798_LIMITS: [f32; 3] = [550.0, 800.0, 1350.0];
+// EN 13779:2007, indoor air quality classes IDA 1 to IDA 4
+const EN_13779_LIMITS: [f32; 3] = [400.0, 600.0, 1000.0];
+
+/// how far in ppm the CO2 concentration has to fall below a limit before the better category is
+/// shown again, so readings around a limit don't flip between two categories
+pub const HYSTERESIS_PPM: f32 = 50.0;
+
+/// Standard the CO2 concentration is classified by
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum Standard {
+    /// EN 16798-1, categories I to IV
+    En16798,
+    /// EN 13779, indoor air quality classes IDA 1 to IDA 4
+    En13779,
+}
+
+/// Indoor air quality category, from best (I) to worst (IV).
+/// For EN 13779 the categories correspond to IDA 1 to IDA 4.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, defmt::Format)]
+pub enum Category {
+    I,
+    II,
+    III,
+    IV,
+}
+
+impl Category {
+    /// Name of the category as used in the given standard
+    pub fn name(&self, standard: Standard) -> &'static str {
+        match (standard, self) {
+            (Standard::En16798, Category::I) => "I",
+            (Standard::En16798, Category::II) => "II",
+            (Standard::En16798, Category::III) => "III",
+            (Standard::En16798, Category::IV) => "IV",
+            (Standard::En13779, Category::I) => "IDA 1",
+            (Standard::En13779, Category::II) => "IDA 2",
+            (Standard::En13779, Category::III) => "IDA 3",
+            (Standard::En13779, Category::IV) => "IDA 4",
+        }
+    }
+
+    /// Index of the category, 0 for the best air
+    pub fn level(&self) -> u8 {
+        *self as u8
+    }
+
+    /// The worst category also sounds the buzzer
+    pub fn is_alarm(&self) -> bool {
+        *self == Category::IV
+    }
+}
+
+pub struct Classifier {
+    standard: Standard,
+    outdoor_co2: f32,
+    // overrides the limits of the standard
+    limits: Option<[f32; 3]>,
+}
+
+impl Classifier {
+    pub fn new(standard: Standard) -> Self {
+        Classifier {
+            standard,
+            outdoor_co2: DEFAULT_OUTDOOR_CO2,
+            limits: None,
+        }
+    }
+
+    pub fn standard(&self) -> Standard {
+        self.standard
+    }
+
+    pub fn set_standard(&mut self, standard: Standard) {
+        self.standard = standard;
+    }
+
+    pub fn set_outdoor_co2(&mut self, co2: f32) {
+        self.outdoor_co2 = co2;
+    }
+
+    /// Upper limits of the categories I, II and III in ppm above outdoor air, instead of the
+    /// limits of the standard. `None` returns to the limits of the standard.
+    pub fn set_limits(&mut self, limits: Option<[f32; 3]>) {
+        self.limits = limits;
+    }
+
+    /// Limits the CO2 concentration is classified by, in ppm above outdoor air
+    pub fn limits(&self) -> [f32; 3] {
+        match (self.limits, self.standard) {
+            (Some(limits), _) => limits,
+            (None, Standard::En16798) => EN_16798_LIMITS,
+            (None, Standard::En13779) => EN_13779_LIMITS,
+        }
+    }
+
+    /// Classifies a CO2 concentration in ppm
+    pub fn classify(&self, co2: &f32) -> Category {
+        let limits = self.limits();
+        let above_outdoor = *co2 - self.outdoor_co2;
+
+        if above_outdoor <= limits[0] {
+            Category::I
+        } else if above_outdoor <= limits[1] {
+            Category::II
+        } else if above_outdoor <= limits[2] {
+            Category::III
+        } else {
+            Category::IV
+        }
+    }
+
+    /// Classifies like `classify`, but coming from the `previous` category, a better one is
+    /// only returned once the concentration is `HYSTERESIS_PPM` below its upper limit.
+    pub fn classify_from(&self, co2: &f32, previous: Option<Category>) -> Category {
+        let category = self.classify(co2);
+        match previous {
+            Some(previous) if category < previous => {
+                let with_hysteresis = self.classify(&(*co2 + HYSTERESIS_PPM));
+                if with_hysteresis < previous {
+                    with_hysteresis
+                } else {
+                    previous
+                }
+            }
+            _ => category,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the CO2 concentrations at which each category ends, with the default outdoor air
+    fn boundaries(standard: Standard) -> [f32; 3] {
+        let limits = Classifier::new(standard).limits();
+        [
+            limits[0] + DEFAULT_OUTDOOR_CO2,
+            limits[1] + DEFAULT_OUTDOOR_CO2,
+            limits[2] + DEFAULT_OUTDOOR_CO2,
+        ]
+    }
+
+    #[test]
+    fn en_16798_boundaries() {
+        assert_eq!(boundaries(Standard::En16798), [970.0, 1220.0, 1770.0]);
+
+        let classifier = Classifier::new(Standard::En16798);
+        assert_eq!(classifier.classify(&0.0), Category::I);
+        assert_eq!(classifier.classify(&970.0), Category::I);
+        assert_eq!(classifier.classify(&970.1), Category::II);
+        assert_eq!(classifier.classify(&1220.0), Category::II);
+        assert_eq!(classifier.classify(&1220.1), Category::III);
+        assert_eq!(classifier.classify(&1770.0), Category::III);
+        assert_eq!(classifier.classify(&1770.1), Category::IV);
+        assert_eq!(classifier.classify(&40_000.0), Category::IV);
+    }
+
+    #[test]
+    fn en_13779_boundaries() {
+        assert_eq!(boundaries(Standard::En13779), [820.0, 1020.0, 1420.0]);
+
+        let classifier = Classifier::new(Standard::En13779);
+        assert_eq!(classifier.classify(&820.0), Category::I);
+        assert_eq!(classifier.classify(&820.1), Category::II);
+        assert_eq!(classifier.classify(&1020.0), Category::II);
+        assert_eq!(classifier.classify(&1020.1), Category::III);
+        assert_eq!(classifier.classify(&1420.0), Category::III);
+        assert_eq!(classifier.classify(&1420.1), Category::IV);
+    }
+
+    #[test]
+    fn outdoor_air_and_own_limits() {
+        let mut classifier = Classifier::new(Standard::En16798);
+        classifier.set_outdoor_co2(400.0);
+        assert_eq!(classifier.classify(&950.0), Category::I);
+        assert_eq!(classifier.classify(&950.1), Category::II);
+
+        classifier.set_limits(Some([100.0, 200.0, 300.0]));
+        assert_eq!(classifier.limits(), [100.0, 200.0, 300.0]);
+        assert_eq!(classifier.classify(&700.1), Category::IV);
+
+        classifier.set_limits(None);
+        classifier.set_standard(Standard::En13779);
+        assert_eq!(classifier.limits(), EN_13779_LIMITS);
+    }
+
+    #[test]
+    fn hysteresis() {
+        for standard in [Standard::En16798, Standard::En13779].iter() {
+            let classifier = Classifier::new(*standard);
+            let [first, second, third] = boundaries(*standard);
+
+            // worse as soon as a limit is passed
+            assert_eq!(
+                classifier.classify_from(&(first + 0.1), Some(Category::I)),
+                Category::II
+            );
+            assert_eq!(
+                classifier.classify_from(&(third + 0.1), Some(Category::II)),
+                Category::IV
+            );
+
+            // better only once clearly below the limit
+            let category = classifier.classify_from(&(third - 10.0), Some(Category::IV));
+            assert_eq!(category, Category::IV);
+            let category =
+                classifier.classify_from(&(third - HYSTERESIS_PPM + 0.1), Some(category));
+            assert_eq!(category, Category::IV);
+            let category = classifier.classify_from(&(third - HYSTERESIS_PPM), Some(category));
+            assert_eq!(category, Category::III);
+            let category = classifier.classify_from(&(second - 1.0), Some(category));
+            assert_eq!(category, Category::III);
+
+            // a big drop skips categories
+            assert_eq!(
+                classifier.classify_from(&(first - 100.0), Some(Category::IV)),
+                Category::I
+            );
+            assert_eq!(classifier.classify_from(&(first - 1.0), None), Category::I);
+        }
+    }
+
+    #[test]
+    fn names_and_levels() {
+        assert_eq!(Category::III.name(Standard::En16798), "III");
+        assert_eq!(Category::III.name(Standard::En13779), "IDA 3");
+        assert_eq!(Category::I.level(), 0);
+        assert_eq!(Category::IV.level(), 3);
+        assert!(Category::IV.is_alarm());
+        assert!(!Category::III.is_alarm());
     }
 }
@@ -0,0 +1,44 @@
+// The categories on the LED and the buzzer, only on the board.
+
+use nrf52840_hal::{pac::TIMER0, timer::OneShot, Timer};
+
+use super::{Category, Classifier};
+use crate::buzzer::Buzzer;
+use crate::rgb_led::LEDColor;
+use crate::Error;
+
+/// Shows the category on the LED
+pub fn show_category(category: Category, led: &mut LEDColor) -> Result<(), Error> {
+    match category {
+        Category::I | Category::II => led.green(),
+        Category::III => led.yellow(),
+        Category::IV => led.red(),
+    }
+}
+
+/// Shows the category on the LED, and buzzes for the worst category.
+pub fn signal(
+    category: Category,
+    buzzer: &mut Buzzer,
+    led: &mut LEDColor,
+    mut timer: &mut Timer<TIMER0, OneShot>,
+) -> Result<(), Error> {
+    show_category(category, led)?;
+    if category.is_alarm() {
+        buzzer.noise(&mut timer)?;
+    }
+    Ok(())
+}
+
+/// Classifies the CO2 concentration, signals it and returns the category for logging.
+pub fn check_levels(
+    co2: &f32,
+    classifier: &Classifier,
+    buzzer: &mut Buzzer,
+    led: &mut LEDColor,
+    timer: &mut Timer<TIMER0, OneShot>,
+) -> Result<Category, Error> {
+    let category = classifier.classify(co2);
+    signal(category, buzzer, led, timer)?;
+    Ok(category)
+}
@@ -3,13 +3,18 @@
 
 use knurling_session_20q4 as _; // global logger + panicking-behavior + memory layout
 
-use knurling_session_20q4::scd30;
+use knurling_session_20q4::{
+    clock::{self, Duration},
+    co2_sensor::Co2Sensor,
+    scd30,
+};
 
 use embedded_hal::blocking::delay::DelayMs;
 
 // access to board peripherals:
 use nrf52840_hal::{
     self as hal,
+    clocks::Clocks,
     gpio::{p0::Parts as P0Parts, Level},
     prelude::*,
     twim::{self, Twim},
@@ -22,6 +27,9 @@ fn main() -> ! {
     let board = hal::pac::Peripherals::take().unwrap();
     // first peripheral: initialize timer
     let mut timer = Timer::new(board.TIMER0);
+    // the clock measures the time we wait for the sensor
+    let clocks = Clocks::new(board.CLOCK).start_lfclk();
+    clock::init(board.RTC1, &clocks);
 
     let pins = P0Parts::new(board.P0);
     // onboard led
@@ -48,12 +56,9 @@ fn main() -> ! {
 
     sensor.start_continuous_measurement(pressure).unwrap();
 
-    loop {
-        if sensor.data_ready().unwrap() {
-            defmt::info!("Data ready.");
-            break;
-        }
-    }
+    // panics with `Timeout` or `NotResponding` instead of waiting forever
+    sensor.wait_for_data(Duration::from_secs(5)).unwrap();
+    defmt::info!("Data ready.");
 
     loop {
         let result = sensor.read_measurement().unwrap();
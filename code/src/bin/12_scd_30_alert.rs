@@ -1,110 +1,230 @@
 #![no_main]
 #![no_std]
 
+use core::cell::RefCell;
+
 use knurling_session_20q4 as _; // global logger + panicking-behavior + memory layout
-use knurling_session_20q4::{alerts, buzzer, rgb_led, scd30};
+#[cfg(feature = "barometer")]
+use knurling_session_20q4::bmp280;
+use knurling_session_20q4::{
+    alerts,
+    board::Board,
+    clock::{self, Duration},
+    co2_sensor::Co2Sensor,
+    frame::Frame,
+    i2c::SharedBus,
+    occupancy,
+    pressure::PressureCompensation,
+    scd30::SCD30,
+    serial_report::{Format, SerialReport},
+    Error,
+};
 
 use embedded_hal::blocking::delay::DelayMs;
 
-// access to board peripherals:
-use nrf52840_hal::{
-    self as hal,
-    gpio::{p0::Parts as P0Parts, Level},
-    prelude::*,
-    twim::{self, Twim},
-    Timer,
-};
+// OutputPin for the onboard led
+use nrf52840_hal::{prelude::*, uarte::Baudrate};
 
-#[cortex_m_rt::entry]
-fn main() -> ! {
-    // take() returns all peripherals, so we can access them
-    let board = hal::pac::Peripherals::take().unwrap();
-    // first peripheral: initialize timer
-    let mut timer = Timer::new(board.TIMER0);
+// size and ventilation of the room the sensor is in, used to estimate how many people are present
+const ROOM_VOLUME_M3: f32 = 60.0;
+const AIR_CHANGES_PER_HOUR: f32 = 1.0;
 
-    let pins = P0Parts::new(board.P0);
-    // onboard led
-    let mut led_1 = pins.p0_13.into_push_pull_output(Level::Low);
+// how long to wait for the first measurement before reporting a problem
+const DATA_TIMEOUT: Duration = Duration::from_secs(5);
 
-    // external led
-    let led_channel_red = pins.p0_03.degrade();
-    let led_channel_blue = pins.p0_04.degrade();
-    let led_channel_green = pins.p0_28.degrade();
+// measurements are sent as lines over the UART as well, `None` only logs them over RTT
+const SERIAL_FORMAT: Option<Format> = Some(Format::Csv);
+const SERIAL_BAUDRATE: Baudrate = Baudrate::BAUD115200;
 
-    let mut led_indicator =
-        rgb_led::LEDColor::init(led_channel_red, led_channel_blue, led_channel_green);
+// LED and buzzer only fail if a pin can't be set, measuring goes on regardless
+fn report(result: Result<(), Error>) {
+    if let Err(error) = result {
+        defmt::warn!("Signalling failed: {:?}", error);
+    }
+}
 
-    // buzzer pin
-    let buzzer_pin = pins.p0_29.degrade();
-    let mut buzzer = buzzer::Buzzer::init(buzzer_pin);
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    // take() returns the board with all drivers configured
+    let board = Board::take().unwrap();
+    // standard, limits, pressure and altitude are taken from the settings stored in the flash
+    let settings = board.settings;
+    let mut timer = board.timer;
+    // onboard led
+    let mut led_1 = board.leds.one;
+    // external led
+    let mut led_indicator = board.led_indicator;
+    let mut buzzer = board.buzzer;
+
+    let serial = board.serial;
+    let mut serial_report = SERIAL_FORMAT
+        .map(|format| SerialReport::new(serial.uarte, serial.pins, SERIAL_BAUDRATE, format));
+    if let Some(serial_report) = serial_report.as_mut() {
+        if let Err(error) = serial_report.header() {
+            defmt::warn!("Sending the header failed: {:?}", error);
+        }
+    }
 
     //simple buzz method, frequency and length is fixed
-    buzzer.noise(&mut timer);
+    report(buzzer.noise(&mut timer));
 
     // buzzes in desired frequency in Hz and desired length in ms
-    buzzer.noise_variable(&mut timer, 440_u32, 500_u32);
-
-    // instanciate I2C
-    let scl = pins.p0_30.into_floating_input().degrade();
-    let sda = pins.p0_31.into_floating_input().degrade();
-
-    let pins = twim::Pins { scl, sda };
-    let i2c = Twim::new(board.TWIM0, pins, twim::Frequency::K100);
-
-    // set ambient air pressure:
-    let pressure = 1020_u16;
-
-    let mut sensor = scd30::SCD30::init(i2c);
+    report(buzzer.noise_variable(&mut timer, 440_u32, 500_u32));
+
+    // the SCD30 shares the bus with the barometer
+    let (bus, _) = board.sensor.release();
+    let bus = RefCell::new(bus);
+    let mut sensor = SCD30::init(SharedBus::new(&bus));
+
+    #[cfg(feature = "barometer")]
+    let barometer = bmp280::BMP280::init(SharedBus::new(&bus), bmp280::DEFAULT_ADDRESS);
+    #[cfg(feature = "barometer")]
+    let mut barometer = match barometer {
+        Ok(barometer) => {
+            defmt::info!("Barometer: {:?}", barometer.model());
+            Some(barometer)
+        }
+        Err(error) => {
+            defmt::warn!("No barometer, compensating for the altitude: {:?}", error);
+            None
+        }
+    };
+    // ambient air pressure in hPa, `None` compensates for the altitude instead.
+    // A pressure in the settings overrides the barometer.
+    // Build with `--features barometer` to measure it with a BMP280 or BME280.
+    let fixed_pressure = match settings.pressure {
+        0 => None,
+        pressure => Some(f32::from(pressure)),
+    };
+    #[cfg(feature = "barometer")]
+    let mut read_pressure = || fixed_pressure.or_else(|| barometer.as_mut()?.read_pressure().ok());
+    #[cfg(not(feature = "barometer"))]
+    let read_pressure = || fixed_pressure;
+
+    match sensor.get_firmware_version() {
+        Ok(firmware_version) => defmt::info!(
+            "Firmware Version: {=u8}.{=u8}",
+            firmware_version[0],
+            firmware_version[1]
+        ),
+        Err(error) => defmt::warn!("Reading the firmware version failed: {:?}", error),
+    }
 
-    let firmware_version = sensor.get_firmware_version().unwrap();
-    defmt::info!(
-        "Firmware Version: {=u8}.{=u8}",
-        firmware_version[0],
-        firmware_version[1]
-    );
+    // the first update starts the measurement, the sensor may still be starting up, keep trying
+    let mut compensation = PressureCompensation::new(settings.altitude);
+    while let Err(error) = compensation.update(&mut sensor, read_pressure(), clock::now()) {
+        defmt::warn!("Starting the measurement failed: {:?}", error);
+        report(led_indicator.blink_red(&mut timer));
+    }
+    defmt::info!("Compensating for {:?}", compensation.current());
 
-    sensor.start_continuous_measurement(pressure).unwrap();
+    let classifier = settings.classifier();
+    let mut occupancy = occupancy::OccupancyEstimator::new(ROOM_VOLUME_M3, AIR_CHANGES_PER_HOUR);
 
     loop {
-        if sensor.data_ready().unwrap() {
-            defmt::info!("Data ready.");
-            // green light for 2000ms to indicate data is ready
-            led_indicator.green();
-            timer.delay_ms(2000_u32);
-            led_indicator.off();
-            break;
-        } else {
+        match sensor.wait_for_data(DATA_TIMEOUT) {
+            Ok(()) => {
+                defmt::info!("Data ready.");
+                // green light for 2000ms to indicate data is ready
+                report(led_indicator.green());
+                timer.delay_ms(2000_u32);
+                report(led_indicator.off());
+                break;
+            }
             // blinks red as long as data is not ready
-            led_indicator.blink_red(&mut timer);
+            Err(Error::NotResponding) => {
+                defmt::warn!("Sensor is not responding, check the wiring.");
+                report(led_indicator.blink_red(&mut timer));
+            }
+            Err(error) => {
+                defmt::warn!("Waiting for data failed: {:?}", error);
+                report(led_indicator.blink_red(&mut timer));
+            }
         }
     }
 
+    let mut last_measurement = clock::now();
+    let mut last_category = None;
+
     loop {
-        let result = sensor.read_measurement().unwrap();
+        // blink onboard LED with 2000ms delay as visual signal, that program is running
+        // delay leads to new measurment every 4 sec.
+        // length of interval is arbitrary
+        timer.delay_ms(2000_u32);
+        led_1.set_high().unwrap();
+        timer.delay_ms(2000_u32);
+        led_1.set_low().unwrap();
+
+        // resending the pressure restarts the measurement, so it's only done if it changed
+        let now = clock::now();
+        if compensation.is_due(now) {
+            match compensation.update(&mut sensor, read_pressure(), now) {
+                Ok(Some(update)) => defmt::info!("Compensating for {:?}", update),
+                Ok(None) => {}
+                Err(error) => defmt::warn!("Updating the compensation failed: {:?}", error),
+            }
+        }
+
+        // after repeated failures, the driver recovers the bus and restarts the measurement
+        let reading = sensor.read_measurement();
+        if let Some(event) = sensor.poll_event() {
+            defmt::warn!("Sensor recovery: {:?}", event);
+            occupancy.reset();
+        }
+
+        let result = match reading {
+            Ok(result) => result,
+            // a failed reading is skipped
+            Err(error) => {
+                defmt::warn!("Reading the measurement failed: {:?}", error);
+                continue;
+            }
+        };
+        let measured_at = clock::now();
 
         let co2 = result.co2;
         let temp = result.temperature;
         let humidity = result.humidity;
 
-        alerts::check_levels(&co2, &mut buzzer, &mut led_indicator, &mut timer);
+        let category = classifier.classify_from(&co2, last_category);
+        last_category = Some(category);
+        report(alerts::signal(
+            category,
+            &mut buzzer,
+            &mut led_indicator,
+            &mut timer,
+        ));
+
+        let elapsed = measured_at - last_measurement;
+        last_measurement = measured_at;
+        let people = occupancy.update(co2, elapsed.as_secs_f32());
+
+        // for analysis on the host, decoded by `decode_frames --text`
+        let uptime = (measured_at.as_millis() / 1000) as u32;
+        let frame = Frame::new(uptime, co2, temp, humidity, category.level()).encode();
+        defmt::info!("Frame {=[u8]}", &frame[..]);
+
+        if let Some(serial_report) = serial_report.as_mut() {
+            if let Err(error) = serial_report.report(measured_at, &result, category) {
+                defmt::warn!("Sending the measurement failed: {:?}", error);
+            }
+        }
 
         defmt::info!(
             "
             CO2 {=f32} ppm
+            Air quality category {=str}
             Temperature {=f32} °C
             Humidity {=f32} %
+            CO2 rise rate {=f32} ppm/s
+            Estimated occupancy {=u32} people
             ",
             co2,
+            category.name(classifier.standard()),
             temp,
-            humidity
+            humidity,
+            occupancy.rise_rate(),
+            occupancy::head_count(people)
         );
-
-        // blink onboard LED with 2000ms delay as visual signal, that program is running
-        // delay leads to new measurment every 4 sec.
-        // length of interval is arbitrary
-        timer.delay_ms(2000_u32);
-        led_1.set_high().unwrap();
-        timer.delay_ms(2000_u32);
-        led_1.set_low().unwrap();
     }
 }
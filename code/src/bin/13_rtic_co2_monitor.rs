@@ -0,0 +1,423 @@
+#![no_main]
+#![no_std]
+
+use knurling_session_20q4 as _; // global logger + panicking-behavior + memory layout
+
+// The CO2 monitor of `12_scd_30_alert` as RTIC application.
+//
+// Instead of one blocking loop, every job is a task of its own:
+// * `tick` (TIMER1 interrupt, every ms) asks the scheduler which jobs are due and spawns them
+// * `poll_buttons`, `read_sensor` and `animate` run at medium priority
+// * `log` and `buzz` run at low priority, so the buzzer no longer freezes the rest of the
+//   application: while it buzzes, the sensor is still read and buttons still work.
+//
+// The drivers from the library are moved into the tasks as local resources, which requires them
+// to be `Send`; RTIC checks this at compile time.
+//
+// Holding button 1 for 3 s starts the forced recalibration with outdoor air, see `calibration`;
+// holding it again cancels it. While it runs, the LED blinks blue.
+//
+// Once a minute, a measurement is appended to the log in the flash. Clicking button 2 prints the
+// log over RTT.
+//
+// Tapping the device with a phone shows the latest measurement: the NFCT emulates a tag with an
+// NDEF message, see `nfc`. The tag is served in the NFCT interrupt at the highest priority.
+//
+// The sensor is used through the `Co2Sensor` trait, so the application runs unchanged with an
+// SCD40/SCD41 when built with `--features sensor-scd4x`.
+
+#[rtic::app(device = nrf52840_hal::pac, dispatchers = [SWI0_EGU0, SWI1_EGU1])]
+mod app {
+    use knurling_session_20q4::{
+        alerts::{self, Category, Classifier},
+        board::{Board, Sensor},
+        buzzer::Buzzer,
+        calibration::{self, Calibration},
+        clock::{self, Duration, Instant},
+        co2_sensor::{Co2Sensor, SensorData},
+        dk_button::Button,
+        flash::Flash,
+        i2c::TwimBus,
+        logger::Logger,
+        nfc::{self, NfcTag},
+        rgb_led::LEDColor,
+        scheduler::{Policy, Scheduler, TaskId},
+        Error,
+    };
+    use nrf52840_hal::{
+        pac::{TIMER0, TIMER1},
+        prelude::*,
+        timer::{OneShot, Periodic},
+        Timer,
+    };
+
+    // how long button 1 has to be held to start or cancel the calibration
+    const LONG_PRESS: Duration = Duration::from_secs(3);
+    // time between two records in the flash log
+    const LOG_INTERVAL: Duration = Duration::from_secs(60);
+    // link added to the NFC message, e.g. to a dashboard with the history of the readings
+    const NFC_URI: Option<&str> = None;
+
+    // LED and buzzer only fail if a pin can't be set, the application goes on regardless
+    fn report(result: Result<(), Error>) {
+        if let Err(error) = result {
+            defmt::warn!("Signalling failed: {:?}", error);
+        }
+    }
+
+    #[derive(Clone, Copy)]
+    pub enum Sound {
+        Alarm,
+        CalibrationResult(Result<(), Error>),
+    }
+
+    #[derive(Clone, Copy)]
+    pub enum LogRequest {
+        Append(SensorData, Instant),
+        Dump,
+    }
+
+    struct Jobs {
+        buttons: TaskId,
+        sensor: TaskId,
+        animation: TaskId,
+    }
+
+    #[shared]
+    struct Shared {
+        // `None` until the first measurement arrived
+        category: Option<Category>,
+        muted: bool,
+        calibration: Calibration,
+        nfc_tag: NfcTag,
+    }
+
+    #[local]
+    struct Local {
+        ticker: Timer<TIMER1, Periodic>,
+        scheduler: Scheduler<3>,
+        jobs: Jobs,
+        button_1: Button,
+        button_2: Button,
+        sensor: Sensor<TwimBus>,
+        // ambient pressure in mbar, 0 compensates for the altitude
+        pressure: u16,
+        sensor_started: bool,
+        // `None` until the first record
+        last_logged: Option<Instant>,
+        classifier: Classifier,
+        led_indicator: LEDColor,
+        blink_on: bool,
+        buzzer: Buzzer,
+        timer: Timer<TIMER0, OneShot>,
+        flash: Flash,
+        logger: Logger,
+    }
+
+    #[init(local = [nfc_buffer: [u8; nfc::FRAME_BUFFER_SIZE] = [0; nfc::FRAME_BUFFER_SIZE]])]
+    fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
+        let board = Board::new(cx.device);
+        // classification, pressure, calibration reference and measurement interval are taken
+        // from the settings stored in the flash
+        let settings = board.settings;
+
+        let mut sensor = board.sensor;
+        // only the SCD30 reports a firmware version
+        #[cfg(not(feature = "sensor-scd4x"))]
+        match sensor.get_firmware_version() {
+            Ok(firmware_version) => defmt::info!(
+                "Firmware Version: {=u8}.{=u8}",
+                firmware_version[0],
+                firmware_version[1]
+            ),
+            Err(error) => defmt::warn!("Reading the firmware version failed: {:?}", error),
+        }
+        // the sensor keeps the altitude, it's used whenever the pressure is 0
+        if let Err(error) = sensor.set_altitude(settings.altitude) {
+            defmt::warn!("Setting the altitude failed: {:?}", error);
+        }
+        // if this fails, `read_sensor` tries again
+        let sensor_started = match sensor.start(settings.pressure) {
+            Ok(()) => true,
+            Err(error) => {
+                defmt::warn!("Starting the measurement failed: {:?}", error);
+                false
+            }
+        };
+
+        let logger = Logger::open(&board.flash);
+        // shows an empty message until the first measurement
+        let nfc_tag = NfcTag::new(board.nfct, cx.local.nfc_buffer);
+
+        let mut calibration = Calibration::new();
+        calibration.set_reference(settings.calibration_reference);
+
+        let mut scheduler = Scheduler::new();
+        let jobs = Jobs {
+            buttons: scheduler
+                .add(Duration::from_millis(5), Policy::Skip)
+                .unwrap(),
+            sensor: scheduler
+                .add(
+                    Duration::from_secs(u64::from(settings.measurement_interval)),
+                    Policy::Skip,
+                )
+                .unwrap(),
+            animation: scheduler
+                .add(Duration::from_millis(500), Policy::Skip)
+                .unwrap(),
+        };
+
+        // Timer counts in microseconds/at 1MHz, the tick is one millisecond.
+        let mut ticker = board.periodic_timer;
+        ticker.enable_interrupt();
+        ticker.start(1000u32);
+
+        (
+            Shared {
+                category: None,
+                muted: false,
+                calibration,
+                nfc_tag,
+            },
+            Local {
+                ticker,
+                scheduler,
+                jobs,
+                button_1: board.buttons.one,
+                button_2: board.buttons.two,
+                sensor,
+                pressure: settings.pressure,
+                sensor_started,
+                last_logged: None,
+                classifier: settings.classifier(),
+                led_indicator: board.led_indicator,
+                blink_on: false,
+                buzzer: board.buzzer,
+                timer: board.timer,
+                flash: board.flash,
+                logger,
+            },
+            init::Monotonics(),
+        )
+    }
+
+    #[idle]
+    fn idle(_: idle::Context) -> ! {
+        loop {
+            // sleep until the next tick
+            cortex_m::asm::wfi();
+        }
+    }
+
+    #[task(binds = TIMER1, priority = 3, local = [ticker, scheduler, jobs])]
+    fn tick(cx: tick::Context) {
+        // acknowledges the interrupt
+        cx.local.ticker.wait().ok();
+
+        let jobs = cx.local.jobs;
+        while let Some(due) = cx.local.scheduler.poll(clock::now()) {
+            // spawning fails if the task has not finished its previous run, then this run is skipped
+            if due.task == jobs.buttons {
+                poll_buttons::spawn().ok();
+            } else if due.task == jobs.sensor {
+                read_sensor::spawn().ok();
+            } else if due.task == jobs.animation {
+                animate::spawn().ok();
+            }
+        }
+    }
+
+    // the reader expects an answer within a few hundred µs
+    #[task(binds = NFCT, priority = 4, shared = [nfc_tag])]
+    fn serve_nfc(mut cx: serve_nfc::Context) {
+        cx.shared.nfc_tag.lock(|tag| tag.handle_interrupt());
+    }
+
+    #[task(priority = 2, local = [button_1, button_2], shared = [muted, calibration])]
+    fn poll_buttons(mut cx: poll_buttons::Context) {
+        let button_1 = cx.local.button_1;
+
+        // holding button 1 starts or cancels the calibration
+        let now = clock::now();
+        if button_1.check_long_press(now, LONG_PRESS).unwrap_or(false) {
+            cx.shared.calibration.lock(|calibration| {
+                if calibration.is_active() {
+                    calibration.cancel();
+                    defmt::info!("Calibration cancelled");
+                } else {
+                    calibration.start(now);
+                    defmt::info!("Calibration started, waiting for stable readings");
+                }
+            });
+        }
+
+        // clicking button 1 mutes and unmutes the buzzer
+        if button_1.check_rising_edge().unwrap_or(false) {
+            let muted = cx.shared.muted.lock(|muted| {
+                *muted = !*muted;
+                *muted
+            });
+            defmt::info!("Buzzer muted: {=bool}", muted);
+        }
+
+        // clicking button 2 prints the log
+        if cx.local.button_2.check_rising_edge().unwrap_or(false) {
+            store::spawn(LogRequest::Dump).ok();
+        }
+    }
+
+    #[task(
+        priority = 2,
+        local = [sensor, pressure, sensor_started, last_logged, classifier],
+        shared = [category, calibration, nfc_tag]
+    )]
+    fn read_sensor(mut cx: read_sensor::Context) {
+        let sensor = cx.local.sensor;
+
+        if !*cx.local.sensor_started {
+            *cx.local.sensor_started = sensor.start(*cx.local.pressure).is_ok();
+        }
+        // after repeated failures, the driver recovers the bus and restarts the measurement
+        let result = match sensor.data_ready() {
+            Ok(true) => sensor.read().map(Some),
+            Ok(false) => Ok(None),
+            Err(error) => Err(error),
+        };
+        if let Some(event) = sensor.poll_event() {
+            defmt::warn!("Sensor recovery: {:?}", event);
+        }
+
+        let data = match result {
+            Ok(Some(data)) => data,
+            Ok(None) => return,
+            // a failed reading is skipped
+            Err(error) => {
+                defmt::warn!("Sensor request failed: {:?}", error);
+                return;
+            }
+        };
+
+        let classifier = cx.local.classifier;
+        let category = cx.shared.category.lock(|shared| {
+            let category = classifier.classify_from(&data.co2, *shared);
+            *shared = Some(category);
+            category
+        });
+
+        match nfc::reading_message(&data, category, classifier.standard(), NFC_URI) {
+            Ok(message) => cx.shared.nfc_tag.lock(|tag| tag.set_message(&message)),
+            Err(_) => defmt::warn!("The measurement doesn't fit into the NFC message"),
+        }
+
+        let calibration_result = cx
+            .shared
+            .calibration
+            .lock(|calibration| calibration.update(sensor, data.co2, clock::now()));
+        if let Some(result) = calibration_result {
+            match result {
+                Ok(()) => defmt::info!("Calibration succeeded"),
+                Err(error) => defmt::warn!("Calibration failed: {:?}", error),
+            }
+            buzz::spawn(Sound::CalibrationResult(result)).ok();
+        }
+
+        let now = clock::now();
+        let last_logged = cx.local.last_logged;
+        let is_log_due = match *last_logged {
+            Some(last_logged) => now.duration_since(last_logged) >= LOG_INTERVAL,
+            None => true,
+        };
+        if is_log_due {
+            *last_logged = Some(now);
+            store::spawn(LogRequest::Append(data, now)).ok();
+        }
+
+        log::spawn(data, category).ok();
+        if category.is_alarm() {
+            buzz::spawn(Sound::Alarm).ok();
+        }
+    }
+
+    #[task(priority = 2, local = [led_indicator, blink_on], shared = [category, calibration])]
+    fn animate(mut cx: animate::Context) {
+        let led = cx.local.led_indicator;
+
+        // a running or just finished calibration is shown instead of the category
+        let now = clock::now();
+        let state = cx
+            .shared
+            .calibration
+            .lock(|calibration| calibration.state(now));
+        match calibration::show_state(state, now, led) {
+            Ok(true) => return,
+            Ok(false) => {}
+            Err(error) => report(Err(error)),
+        }
+
+        match cx.shared.category.lock(|category| *category) {
+            Some(category) => report(alerts::show_category(category, led)),
+            // blinks red as long as no data is ready
+            None => {
+                *cx.local.blink_on = !*cx.local.blink_on;
+                if *cx.local.blink_on {
+                    report(led.red());
+                } else {
+                    report(led.off());
+                }
+            }
+        }
+    }
+
+    #[task(priority = 1, capacity = 2)]
+    fn log(_: log::Context, data: SensorData, category: Category) {
+        defmt::info!(
+            "
+            CO2 {=f32} ppm
+            Air quality category {:?}
+            Temperature {=f32} °C
+            Humidity {=f32} %
+            ",
+            data.co2,
+            category,
+            data.temperature,
+            data.humidity
+        );
+    }
+
+    // Writing to the flash halts the CPU, even for the interrupts of higher priority. Erasing a
+    // page takes 85 ms, which the NFC reader doesn't wait for, so `Flash` erases it in steps of
+    // 1 ms and the NFCT interrupt is served in between. At the lowest priority, the other jobs
+    // run between the steps as well.
+    #[task(priority = 1, capacity = 2, local = [flash, logger])]
+    fn store(cx: store::Context, request: LogRequest) {
+        let flash = cx.local.flash;
+        let logger = cx.local.logger;
+        match request {
+            LogRequest::Append(data, time) => {
+                if let Err(error) = logger.append(flash, time, &data) {
+                    defmt::warn!("Logging the measurement failed: {:?}", error);
+                }
+            }
+            LogRequest::Dump => logger.dump(flash),
+        }
+    }
+
+    // blocks for the length of the buzz, but only tasks of the lowest priority have to wait
+    #[task(priority = 1, capacity = 2, local = [buzzer, timer], shared = [muted])]
+    fn buzz(mut cx: buzz::Context, sound: Sound) {
+        match sound {
+            Sound::Alarm => {
+                if !cx.shared.muted.lock(|muted| *muted) {
+                    report(cx.local.buzzer.noise(cx.local.timer));
+                }
+            }
+            // the calibration was started by hand, so its result is confirmed even when muted
+            Sound::CalibrationResult(result) => report(calibration::sound_result(
+                &result,
+                cx.local.buzzer,
+                cx.local.timer,
+            )),
+        }
+    }
+}
@@ -0,0 +1,266 @@
+#![no_main]
+#![no_std]
+
+use knurling_session_20q4 as _; // global logger + panicking-behavior + memory layout
+
+// The CO2 monitor on the native USB port, for a deployed monitor without a debug probe.
+//
+// Plugged into a PC, the board shows up as a serial port, e.g. `/dev/ttyACM0` on Linux. It streams
+// every measurement as a line, see `serial_report`, and accepts commands, one per line:
+// * `stream on`, `stream off`: starts or stops streaming
+// * `format csv`, `format json`, `format influx`: line format of the measurements
+// * the commands of `shell`, e.g. `status`, `set pressure 1013` or `log dump`, see `help`
+//
+// Build with `--features usb`, the USB port to use is the one labelled "nRF USB" on the DK.
+
+#[rtic::app(device = nrf52840_hal::pac)]
+mod app {
+    use core::fmt::Write;
+
+    use knurling_session_20q4::{
+        alerts::Category,
+        board::{Board, Sensor},
+        clock::{self, Instant},
+        co2_sensor::{Co2Sensor, SensorData},
+        config::{ConfigStore, Settings},
+        flash::Flash,
+        i2c::TwimBus,
+        logger::Logger,
+        serial_report::{self, Format, Line},
+        shell,
+        usb_serial::{self, UsbBus, UsbSerial},
+    };
+    use nrf52840_hal::{
+        clocks::{Clocks, ExternalOscillator, Internal, LfOscStarted},
+        pac::TIMER1,
+        prelude::*,
+        timer::Periodic,
+        Timer,
+    };
+    use usb_device::bus::UsbBusAllocator;
+
+    #[derive(Clone, Copy)]
+    pub struct Measurement {
+        data: SensorData,
+        time: Instant,
+        category: Category,
+    }
+
+    // USB and sensor tasks run at the same priority, so they never preempt each other
+    #[shared]
+    struct Shared {
+        #[lock_free]
+        usb_serial: UsbSerial,
+        // the shell changes the settings, the sensor task applies pressure and thresholds
+        #[lock_free]
+        settings: Settings,
+        #[lock_free]
+        sensor: Sensor<TwimBus>,
+        // restarted when the shell changes the measurement interval
+        #[lock_free]
+        ticker: Timer<TIMER1, Periodic>,
+        // `None` until the first measurement arrived
+        #[lock_free]
+        latest: Option<Measurement>,
+        #[lock_free]
+        streaming: bool,
+        #[lock_free]
+        format: Format,
+    }
+
+    #[local]
+    struct Local {
+        sensor_started: bool,
+        flash: Flash,
+        config: ConfigStore,
+        logger: Logger,
+    }
+
+    // the USB device borrows the bus allocator, which borrows the clocks, for the whole runtime
+    #[init(local = [
+        clocks: Option<Clocks<ExternalOscillator, Internal, LfOscStarted>> = None,
+        usb_bus: Option<UsbBusAllocator<UsbBus>> = None,
+    ])]
+    fn init(cx: init::Context) -> (Shared, Local, init::Monotonics) {
+        let board = Board::new(cx.device);
+        let settings = board.settings;
+
+        let mut sensor = board.sensor;
+        // the sensor keeps the altitude, it's used whenever the pressure is 0
+        if let Err(error) = sensor.set_altitude(settings.altitude) {
+            defmt::warn!("Setting the altitude failed: {:?}", error);
+        }
+        // if this fails, `read_sensor` tries again
+        let sensor_started = match sensor.start(settings.pressure) {
+            Ok(()) => true,
+            Err(error) => {
+                defmt::warn!("Starting the measurement failed: {:?}", error);
+                false
+            }
+        };
+
+        let clocks = cx.local.clocks.insert(board.usb.clocks);
+        let usb_bus = cx
+            .local
+            .usb_bus
+            .insert(usb_serial::bus_allocator(board.usb.usbd, clocks));
+        let usb_serial = UsbSerial::new(usb_bus);
+
+        let logger = Logger::open(&board.flash);
+
+        let mut ticker = board.periodic_timer;
+        ticker.enable_interrupt();
+        start_ticker(&mut ticker, &settings);
+
+        (
+            Shared {
+                usb_serial,
+                settings,
+                sensor,
+                ticker,
+                latest: None,
+                streaming: true,
+                format: Format::Csv,
+            },
+            Local {
+                sensor_started,
+                flash: board.flash,
+                config: board.config,
+                logger,
+            },
+            init::Monotonics(),
+        )
+    }
+
+    #[idle]
+    fn idle(_: idle::Context) -> ! {
+        loop {
+            cortex_m::asm::wfi();
+        }
+    }
+
+    #[task(
+        binds = USBD,
+        priority = 2,
+        local = [flash, config, logger],
+        shared = [usb_serial, settings, sensor, ticker, latest, streaming, format]
+    )]
+    fn usb(cx: usb::Context) {
+        let usb_serial = cx.shared.usb_serial;
+        while let Some(command) = usb_serial.poll() {
+            let line = command.as_str().unwrap_or("").trim();
+
+            // streaming is handled here, everything else by the shell
+            let result = match line {
+                "stream on" | "stream off" => {
+                    *cx.shared.streaming = line == "stream on";
+                    writeln!(usb_serial, "ok")
+                }
+                "format csv" | "format json" | "format influx" => {
+                    let format = match line {
+                        "format csv" => Format::Csv,
+                        "format json" => Format::Json,
+                        _ => Format::Influx,
+                    };
+                    *cx.shared.format = format;
+                    usb_serial.write_str(serial_report::format_header(format).unwrap_or("ok\n"))
+                }
+                _ => {
+                    let previous = *cx.shared.settings;
+                    let mut context = shell::Context {
+                        sensor: &mut *cx.shared.sensor,
+                        settings: &mut *cx.shared.settings,
+                        config: &mut *cx.local.config,
+                        flash: &mut *cx.local.flash,
+                        logger: &mut *cx.local.logger,
+                        latest: cx.shared.latest.map(|latest| (latest.data, latest.time)),
+                    };
+                    let result = shell::run(line, &mut context, usb_serial);
+
+                    if cx.shared.settings.measurement_interval != previous.measurement_interval {
+                        start_ticker(cx.shared.ticker, cx.shared.settings);
+                    }
+                    result
+                }
+            };
+            if result.is_err() {
+                defmt::warn!("Sending the reply failed");
+            }
+        }
+    }
+
+    #[task(
+        binds = TIMER1,
+        priority = 2,
+        local = [sensor_started],
+        shared = [usb_serial, settings, sensor, ticker, latest, streaming, format]
+    )]
+    fn read_sensor(cx: read_sensor::Context) {
+        // acknowledges the interrupt
+        cx.shared.ticker.wait().ok();
+
+        let settings = cx.shared.settings;
+        let sensor = cx.shared.sensor;
+        if !*cx.local.sensor_started {
+            *cx.local.sensor_started = sensor.start(settings.pressure).is_ok();
+        }
+        // after repeated failures, the driver recovers the bus and restarts the measurement
+        let result = match sensor.data_ready() {
+            Ok(true) => sensor.read().map(Some),
+            Ok(false) => Ok(None),
+            Err(error) => Err(error),
+        };
+        if let Some(event) = sensor.poll_event() {
+            defmt::warn!("Sensor recovery: {:?}", event);
+        }
+
+        let data = match result {
+            Ok(Some(data)) => data,
+            Ok(None) => return,
+            // a failed reading is skipped
+            Err(error) => {
+                defmt::warn!("Sensor request failed: {:?}", error);
+                return;
+            }
+        };
+
+        let measurement = Measurement {
+            data,
+            time: clock::now(),
+            category: settings
+                .classifier()
+                .classify_from(&data.co2, cx.shared.latest.map(|latest| latest.category)),
+        };
+        *cx.shared.latest = Some(measurement);
+        defmt::info!("CO2 {=f32} ppm", data.co2);
+
+        if *cx.shared.streaming {
+            if let Ok(line) = format_measurement(*cx.shared.format, measurement) {
+                // without a reader on the host, the line is dropped
+                if let Err(error) = cx.shared.usb_serial.write(line.as_bytes()) {
+                    defmt::warn!("Streaming the measurement failed: {:?}", error);
+                }
+            }
+        }
+    }
+
+    // Timer counts in microseconds/at 1MHz, the settings keep the interval short enough for it
+    fn start_ticker(ticker: &mut Timer<TIMER1, Periodic>, settings: &Settings) {
+        let cycles = u32::from(settings.measurement_interval)
+            .checked_mul(1_000_000)
+            .unwrap_or(u32::MAX);
+        ticker.start(cycles);
+    }
+
+    fn format_measurement(
+        format: Format,
+        measurement: Measurement,
+    ) -> Result<Line, core::fmt::Error> {
+        serial_report::format_line(
+            format,
+            measurement.time,
+            &measurement.data,
+            measurement.category,
+        )
+    }
+}
@@ -0,0 +1,103 @@
+#![no_main]
+#![no_std]
+
+use knurling_session_20q4 as _; // global logger + panicking-behavior + memory layout
+
+// The CO2 monitor as Modbus RTU slave, for a building management system on an RS-485 bus.
+//
+// The UARTE of `board::Serial` is wired to an RS-485 transceiver that switches direction by
+// itself. Between two measurements, the application answers requests; see `modbus_rtu` for the
+// registers. Settings written by the master are applied to the sensor and saved to the flash.
+
+use knurling_session_20q4::{
+    board::Board,
+    co2_sensor::Co2Sensor,
+    modbus::{Slave, UartePort},
+    modbus_rtu::{Registers, RtuPort},
+};
+
+use nrf52840_hal::uarte::{Baudrate, Parity};
+
+// address of the monitor on the bus, 1 to 247
+const SLAVE_ADDRESS: u8 = 1;
+const BAUDRATE: Baudrate = Baudrate::BAUD19200;
+// how long to wait for a request before checking the sensor
+const POLL_TIMEOUT_US: u32 = 100_000;
+
+#[cortex_m_rt::entry]
+fn main() -> ! {
+    let board = Board::take().unwrap();
+    let mut settings = board.settings;
+    let mut config = board.config;
+    let mut flash = board.flash;
+
+    let mut sensor = board.sensor;
+    if let Err(error) = sensor.set_altitude(settings.altitude) {
+        defmt::warn!("Setting the altitude failed: {:?}", error);
+    }
+    // if this fails, it's tried again in the loop
+    let mut sensor_started = sensor.start(settings.pressure).is_ok();
+
+    let serial = board.serial;
+    let uarte_port = UartePort::new(
+        serial.uarte,
+        serial.pins,
+        Parity::INCLUDED,
+        BAUDRATE,
+        board.timer,
+        None,
+    );
+    let bits_per_second = uarte_port.bits_per_second();
+    let mut port = RtuPort::new(uarte_port, bits_per_second);
+    let slave = Slave::new(SLAVE_ADDRESS);
+    let mut registers = Registers::new(settings);
+    defmt::info!("Modbus slave {=u8} ready", slave.address());
+
+    loop {
+        if let Err(error) = port.serve(&slave, &mut registers, POLL_TIMEOUT_US) {
+            defmt::warn!("Modbus request failed: {:?}", error);
+        }
+
+        // settings written by the master
+        if registers.settings != settings {
+            let changed = registers.settings;
+            if changed.altitude != settings.altitude {
+                if let Err(error) = sensor.set_altitude(changed.altitude) {
+                    defmt::warn!("Setting the altitude failed: {:?}", error);
+                }
+            }
+            if changed.pressure != settings.pressure {
+                sensor_started = sensor.start(changed.pressure).is_ok();
+            }
+            if let Err(error) = config.save(&mut flash, &changed) {
+                defmt::warn!("Saving the settings failed: {:?}", error);
+            }
+            settings = changed;
+        }
+
+        if !sensor_started {
+            sensor_started = sensor.start(settings.pressure).is_ok();
+        }
+        // after repeated failures, the driver recovers the bus and restarts the measurement
+        let result = match sensor.data_ready() {
+            Ok(true) => sensor.read().map(Some),
+            Ok(false) => Ok(None),
+            Err(error) => Err(error),
+        };
+        if let Some(event) = sensor.poll_event() {
+            defmt::warn!("Sensor recovery: {:?}", event);
+        }
+
+        match result {
+            Ok(Some(data)) => {
+                let previous = registers.measurement.map(|(_, category)| category);
+                let category = settings.classifier().classify_from(&data.co2, previous);
+                registers.measurement = Some((data, category));
+                defmt::info!("CO2 {=f32} ppm, category {:?}", data.co2, category);
+            }
+            Ok(None) => {}
+            // a failed reading is skipped
+            Err(error) => defmt::warn!("Sensor request failed: {:?}", error),
+        }
+    }
+}
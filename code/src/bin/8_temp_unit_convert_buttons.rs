@@ -2,15 +2,18 @@
 #![no_std]
 
 use knurling_session_20q4 as _; // global logger + panicking-behavior + memory layout
-
-use nb::block;
+use knurling_session_20q4::{
+    clock::{self, Duration},
+    scheduler::{Policy, Scheduler},
+};
 
 // access to board peripherals:
 use nrf52840_hal::{
     self as hal,
+    clocks::Clocks,
     gpio::{p0::Parts as P0Parts, Input, Pin, PullUp},
     prelude::*,
-    Temp, Timer,
+    Temp,
 };
 
 enum Unit {
@@ -81,9 +84,9 @@ fn main() -> ! {
     // take() returns all peripherals, so we can access them
     let board = hal::pac::Peripherals::take().unwrap();
 
-    // Set up our "wall clock" timer, will be used to count milliseconds
-    let mut periodic_timer = Timer::new(board.TIMER1).into_periodic();
-    let mut millis: u64 = 0;
+    // the clock counts milliseconds since the start, it runs on the low frequency clock
+    let clocks = Clocks::new(board.CLOCK).start_lfclk();
+    clock::init(board.RTC1, &clocks);
 
     // Initialize temperature sensor
     let mut temp = Temp::new(board.TEMP);
@@ -93,39 +96,42 @@ fn main() -> ! {
     let mut button_1 = Button::new(pins.p0_11.degrade());
 
     let mut current_unit = Unit::Celsius;
-    // state of the button is read continuoulsly, and new button state is saved, so every input gets noticed,
-    // but temp value is only printed if tick number is divisible
+    // state of the button is read every 5ms, so every input gets noticed,
+    // but temp value is only printed every second
+    let mut scheduler = Scheduler::<2>::new();
+    let temperature_task = scheduler
+        .add(Duration::from_millis(1000), Policy::Skip)
+        .unwrap();
+    let button_task = scheduler
+        .add(Duration::from_millis(5), Policy::Skip)
+        .unwrap();
+
     loop {
-        // Start by setting/resetting the timer for our next interval
-        // Timer counts in microseconds/at 1MHz, we care about milliseconds.
-        periodic_timer.start(1000u32);
-
-        // Every 1000ms, print the current temperature reading
-        if (millis % 1000) == 0 {
-            defmt::info!("Tick (milliseconds): {=u32}", millis as u32);
-
-            let temperature: f32 = temp.measure().to_num();
-            let converted_temp = current_unit.convert_temperature(temperature);
-            match current_unit {
-                Unit::Fahrenheit => defmt::info!("{=f32} °F", converted_temp),
-                Unit::Kelvin => defmt::info!("{=f32} K", converted_temp),
-                Unit::Celsius => defmt::info!("{=f32} °C", converted_temp),
-            };
-        }
+        while let Some(due) = scheduler.poll(clock::now()) {
+            // Every 1000ms, print the current temperature reading
+            if due.task == temperature_task {
+                defmt::info!("Tick (milliseconds): {=u64}", clock::now_ms());
+
+                let temperature: f32 = temp.measure().to_num();
+                let converted_temp = current_unit.convert_temperature(temperature);
+                match current_unit {
+                    Unit::Fahrenheit => defmt::info!("{=f32} °F", converted_temp),
+                    Unit::Kelvin => defmt::info!("{=f32} K", converted_temp),
+                    Unit::Celsius => defmt::info!("{=f32} °C", converted_temp),
+                };
+            }
 
-        // Every 5ms, check the current state of the button
-        if (millis % 5) == 0 && button_1.check_rising_edge() {
-            current_unit = match current_unit {
-                Unit::Fahrenheit => Unit::Kelvin,
-                Unit::Kelvin => Unit::Celsius,
-                Unit::Celsius => Unit::Fahrenheit,
+            // Every 5ms, check the current state of the button
+            if due.task == button_task && button_1.check_rising_edge() {
+                current_unit = match current_unit {
+                    Unit::Fahrenheit => Unit::Kelvin,
+                    Unit::Kelvin => Unit::Celsius,
+                    Unit::Celsius => Unit::Fahrenheit,
+                };
             };
-        };
-
-        // Now wait for the timer to complete
-        block!(periodic_timer.wait()).unwrap();
+        }
 
-        // Increment our millisecond count
-        millis = millis.wrapping_add(1);
+        // sleep until the next task is due
+        scheduler.sleep();
     }
 }
@@ -3,16 +3,12 @@
 
 use knurling_session_20q4 as _; // global logger + panicking-behavior + memory layout
 use knurling_session_20q4::{
-    dk_button,
-    number_representation::{self, Unit},
-    rgb_led,
+    board::Board,
+    clock::{self, Duration},
+    number_representation::Unit,
+    scheduler::{Policy, Scheduler},
 };
 
-use nb::block;
-
-// access to board peripherals:
-use nrf52840_hal::{self as hal, gpio::p0::Parts as P0Parts, prelude::*, Temp, Timer};
-
 use core::ops::Range;
 
 const FREEZING_TEMPERATURE: f32 = 19.99;
@@ -23,80 +19,80 @@ const BOILING_TEMPERATURE: f32 = 26.00;
 
 #[cortex_m_rt::entry]
 fn main() -> ! {
-    // take() returns all peripherals, so we can access them
-    let board = hal::pac::Peripherals::take().unwrap();
-
-    // one for continuous counting
-    let mut periodic_timer = Timer::periodic(board.TIMER0);
-    let mut millis: u64 = 0;
-
-    // Initialize temperature sensor
-    let mut temp = Temp::new(board.TEMP);
-
-    // Initialize access to pins for the button and led
-    let pins = P0Parts::new(board.P0);
-    let mut button_1 = dk_button::Button::new(pins.p0_11.degrade());
-
-    let led_channel_red = pins.p0_03.degrade();
-    let led_channel_green = pins.p0_04.degrade();
-    let led_channel_blue = pins.p0_28.degrade();
-
-    let mut led_indicator =
-        rgb_led::LEDColor::init(led_channel_red, led_channel_blue, led_channel_green);
-
-    // state of the button is read and updated continuoulsly
-    // but temp value is only printed if tick number is divisible
-
-    let mut current_unit = number_representation::Unit::Celsius;
+    // take() returns the board with all drivers configured
+    let board = Board::take().unwrap();
+
+    // temperature sensor, button and led
+    let mut temp = board.temp;
+    let mut button_1 = board.buttons.one;
+    let mut led_indicator = board.led_indicator;
+    // the selected unit is stored in the flash, so it survives a reset
+    let mut flash = board.flash;
+    let mut config = board.config;
+    let mut settings = board.settings;
+
+    // the state of the button is read every 5ms, so every input gets noticed,
+    // the temperature is only read every second
+    let mut scheduler = Scheduler::<2>::new();
+    let temperature_task = scheduler
+        .add(Duration::from_millis(1000), Policy::Skip)
+        .unwrap();
+    let button_task = scheduler
+        .add(Duration::from_millis(5), Policy::Skip)
+        .unwrap();
+
+    let mut current_unit = settings.unit;
 
     loop {
-        // Start by setting/resetting the timer for next interval
-        // Timer counts in microseconds/at 1MHz, we care about milliseconds.
-        periodic_timer.start(1000u32);
-
-        // Every 1000ms:
-        // read temperature
-        // light led in appropriate color
-        // print the current temperature reading
-
-        if (millis % 1000) == 0 {
-            defmt::info!("Tick (milliseconds): {=u32}", millis as u32);
-
-            let temperature: f32 = temp.measure().to_num();
-
-            if temperature < FREEZING_TEMPERATURE {
-                led_indicator.blue();
-            } else if CRISP_TEMPERATURES.contains(&temperature) {
-                led_indicator.light_blue();
-            } else if PLEASANTLY_WARM_TEMPERATURES.contains(&temperature) {
-                led_indicator.green();
-            } else if A_BIT_TOO_STEAMY_TEMPERATURES.contains(&temperature) {
-                led_indicator.yellow();
-            } else if temperature > BOILING_TEMPERATURE {
-                led_indicator.red();
+        while let Some(due) = scheduler.poll(clock::now()) {
+            if due.missed > 0 {
+                defmt::warn!("{:?} missed {=u32} periods", due.task, due.missed);
             }
 
-            let converted_temp = current_unit.convert_temperature(&temperature);
-            match current_unit {
-                Unit::Fahrenheit => defmt::info!("{=f32} °F", converted_temp),
-                Unit::Kelvin => defmt::info!("{=f32} K", converted_temp),
-                Unit::Celsius => defmt::info!("{=f32} °C", converted_temp),
-            };
-        };
-
-        // Every 5ms, check the current state of the button
-        if (millis % 5) == 0 && button_1.check_rising_edge() {
-            current_unit = match current_unit {
-                Unit::Fahrenheit => Unit::Kelvin,
-                Unit::Kelvin => Unit::Celsius,
-                Unit::Celsius => Unit::Fahrenheit,
-            };
-        };
+            // Every 1000ms:
+            // read temperature
+            // light led in appropriate color
+            // print the current temperature reading
+            if due.task == temperature_task {
+                defmt::info!("Tick (milliseconds): {=u64}", clock::now_ms());
+
+                let temperature: f32 = temp.measure().to_num();
+
+                if temperature < FREEZING_TEMPERATURE {
+                    led_indicator.blue().unwrap();
+                } else if CRISP_TEMPERATURES.contains(&temperature) {
+                    led_indicator.light_blue().unwrap();
+                } else if PLEASANTLY_WARM_TEMPERATURES.contains(&temperature) {
+                    led_indicator.green().unwrap();
+                } else if A_BIT_TOO_STEAMY_TEMPERATURES.contains(&temperature) {
+                    led_indicator.yellow().unwrap();
+                } else if temperature > BOILING_TEMPERATURE {
+                    led_indicator.red().unwrap();
+                }
+
+                let converted_temp = current_unit.convert_temperature(&temperature);
+                match current_unit {
+                    Unit::Fahrenheit => defmt::info!("{=f32} °F", converted_temp),
+                    Unit::Kelvin => defmt::info!("{=f32} K", converted_temp),
+                    Unit::Celsius => defmt::info!("{=f32} °C", converted_temp),
+                };
+            }
 
-        // Now wait for the timer to complete
-        block!(periodic_timer.wait()).unwrap();
+            // Every 5ms, check the current state of the button
+            if due.task == button_task && button_1.check_rising_edge().unwrap() {
+                current_unit = match current_unit {
+                    Unit::Fahrenheit => Unit::Kelvin,
+                    Unit::Kelvin => Unit::Celsius,
+                    Unit::Celsius => Unit::Fahrenheit,
+                };
+                settings.unit = current_unit;
+                if let Err(error) = config.save(&mut flash, &settings) {
+                    defmt::warn!("Saving the unit failed: {:?}", error);
+                }
+            }
+        }
 
-        // Increment our millisecond count
-        millis = millis.wrapping_add(1);
+        // sleep until the next task is due
+        scheduler.sleep();
     }
 }
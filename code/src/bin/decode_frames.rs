@@ -0,0 +1,114 @@
+// Decodes measurement frames, see `src/frame/mod.rs`, into CSV or JSON lines.
+//
+// Runs on the host, not on the board:
+// cargo run --features host-tools --target x86_64-unknown-linux-gnu --bin decode_frames -- <file>
+//
+// The file is either the raw frames, e.g. captured from a serial port, or with `--text` the
+// output of `probe-run`, in which `12_scd_30_alert` prints every frame as `Frame [165, 90, ...]`.
+//
+// Options:
+// --json  one JSON object per line instead of CSV
+// --text  read the frames from a `probe-run` log
+
+use std::{env, fs, process};
+
+use knurling_session_20q4::frame::{Frame, Frames, NO_ALERT_LEVEL};
+
+fn main() {
+    let mut json = false;
+    let mut text = false;
+    let mut path = None;
+    for argument in env::args().skip(1) {
+        match argument.as_str() {
+            "--json" => json = true,
+            "--text" => text = true,
+            _ if path.is_none() => path = Some(argument),
+            _ => usage(),
+        }
+    }
+    let path = path.unwrap_or_else(|| usage());
+
+    let bytes = match fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            eprintln!("Reading {} failed: {}", path, error);
+            process::exit(1);
+        }
+    };
+    let bytes = if text {
+        parse_log(&String::from_utf8_lossy(&bytes))
+    } else {
+        bytes
+    };
+
+    if !json {
+        println!("uptime_s,co2_ppm,temperature_c,humidity_percent,alert_level");
+    }
+    let mut damaged = 0;
+    for frame in Frames::new(&bytes) {
+        match frame {
+            Ok(frame) if json => println!("{}", to_json(&frame)),
+            Ok(frame) => println!("{}", to_csv(&frame)),
+            Err(error) => {
+                eprintln!("Skipping frame: {:?}", error);
+                damaged += 1;
+            }
+        }
+    }
+    if damaged > 0 {
+        eprintln!("{} damaged frames", damaged);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("usage: decode_frames [--json] [--text] <file>");
+    process::exit(2);
+}
+
+// bytes of all `Frame [...]` lines of the log
+fn parse_log(log: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for line in log.lines() {
+        let list = line
+            .find("Frame [")
+            .map(|start| &line[start + "Frame [".len()..])
+            .and_then(|list| list.split(']').next());
+        if let Some(list) = list {
+            bytes.extend(
+                list.split(',')
+                    .filter_map(|byte| byte.trim().parse::<u8>().ok()),
+            );
+        }
+    }
+    bytes
+}
+
+// `unknown` if the measurement wasn't classified
+fn alert_level(frame: &Frame, unknown: &str) -> String {
+    match frame.alert_level {
+        NO_ALERT_LEVEL => unknown.to_string(),
+        level => level.to_string(),
+    }
+}
+
+fn to_csv(frame: &Frame) -> String {
+    format!(
+        "{},{},{:.2},{:.2},{}",
+        frame.uptime,
+        frame.co2_ppm(),
+        frame.temperature_celsius(),
+        frame.humidity_percent(),
+        alert_level(frame, "")
+    )
+}
+
+fn to_json(frame: &Frame) -> String {
+    format!(
+        "{{\"uptime_s\":{},\"co2_ppm\":{},\"temperature_c\":{:.2},\"humidity_percent\":{:.2},\"alert_level\":{}}}",
+        frame.uptime,
+        frame.co2_ppm(),
+        frame.temperature_celsius(),
+        frame.humidity_percent(),
+        alert_level(frame, "null")
+    )
+}
@@ -0,0 +1,482 @@
+// Serves the latest measurement of the device to Prometheus.
+//
+// Runs on the host, not on the board:
+// cargo run --features host-tools --target x86_64-unknown-linux-gnu --bin prometheus_exporter -- <port>
+//
+// The port is the serial port the device streams its measurements to, see `serial_report`: the
+// UART of `12_scd_30_alert` or the USB serial port of `14_usb_co2_monitor`. Lines in any of the
+// formats are understood; headers, replies of the shell and damaged lines are skipped. If the
+// port goes away, e.g. because the device was unplugged, it's opened again.
+//
+// `GET /metrics` returns the latest measurement in the Prometheus text format:
+// scd30_co2_ppm 812.4
+// scd30_temperature_celsius 21.46
+// scd30_humidity_percent 45.5
+// alert_level 1
+// Without a measurement, or while the port is gone, the metrics are left out.
+//
+// Options:
+// --baud <rate>       baud rate of the UART, 115200 by default; USB ignores it
+// --listen <address>  address the HTTP server listens on, 127.0.0.1:9184 by default
+//
+// A pseudo-terminal can stand in for the device: `socat -d -d pty,raw,echo=0 pty,raw,echo=0`
+// prints two connected ones. The exporter reads the first one, lines written to the second one,
+// e.g. `echo 12,812.4,21.46,45.50,1 > /dev/pts/5`, show up as metrics.
+
+use std::{
+    env,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    process,
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+const DEFAULT_BAUD_RATE: u32 = 115_200;
+const DEFAULT_LISTEN_ADDRESS: &str = "127.0.0.1:9184";
+// a read returns this often without data, so a port that's gone is noticed
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+// time between two attempts to open the port
+const REOPEN_DELAY: Duration = Duration::from_secs(1);
+// longest request that is read, the exporter only needs the request line
+const MAX_REQUEST_SIZE: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Measurement {
+    co2: f64,
+    temperature: f64,
+    humidity: f64,
+    alert_level: u8,
+}
+
+type Latest = Arc<Mutex<Option<Measurement>>>;
+
+fn main() {
+    let mut baud_rate = DEFAULT_BAUD_RATE;
+    let mut listen = DEFAULT_LISTEN_ADDRESS.to_string();
+    let mut port = None;
+    let mut arguments = env::args().skip(1);
+    while let Some(argument) = arguments.next() {
+        match argument.as_str() {
+            "--baud" => {
+                baud_rate = arguments
+                    .next()
+                    .and_then(|rate| rate.parse().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--listen" => listen = arguments.next().unwrap_or_else(|| usage()),
+            _ if port.is_none() => port = Some(argument),
+            _ => usage(),
+        }
+    }
+    let port = port.unwrap_or_else(|| usage());
+
+    let listener = match TcpListener::bind(&listen) {
+        Ok(listener) => listener,
+        Err(error) => {
+            eprintln!("Listening on {} failed: {}", listen, error);
+            process::exit(1);
+        }
+    };
+    eprintln!("Serving http://{}/metrics", listen);
+
+    let latest = Latest::default();
+    let reader_latest = Arc::clone(&latest);
+    thread::spawn(move || read_port(&port, baud_rate, &reader_latest));
+
+    accept(listener, latest);
+}
+
+fn usage() -> ! {
+    eprintln!("usage: prometheus_exporter [--baud <rate>] [--listen <address>] <port>");
+    process::exit(2);
+}
+
+// answers every connection on a thread of its own, so a client that doesn't send its request
+// only holds up itself
+fn accept(listener: TcpListener, latest: Latest) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                eprintln!("Accepting a connection failed: {}", error);
+                continue;
+            }
+        };
+        let latest = Arc::clone(&latest);
+        thread::spawn(move || {
+            if let Err(error) = serve(stream, &latest) {
+                eprintln!("Answering a request failed: {}", error);
+            }
+        });
+    }
+}
+
+// keeps `latest` up to date, forever
+fn read_port(path: &str, baud_rate: u32, latest: &Mutex<Option<Measurement>>) {
+    loop {
+        match serialport::new(path, baud_rate)
+            .timeout(READ_TIMEOUT)
+            .open()
+        {
+            Ok(port) => {
+                eprintln!("Reading measurements from {}", path);
+                let error = read_lines(port, latest);
+                eprintln!("Reading from {} failed: {}", path, error);
+                // the values are outdated once the device is gone
+                *latest.lock().unwrap() = None;
+            }
+            Err(error) => eprintln!("Opening {} failed: {}", path, error),
+        }
+        thread::sleep(REOPEN_DELAY);
+    }
+}
+
+// returns once the port fails
+fn read_lines(port: impl Read, latest: &Mutex<Option<Measurement>>) -> io::Error {
+    let mut reader = BufReader::new(port);
+    // a line that arrives in parts is completed by the next reads
+    let mut line = String::new();
+    loop {
+        match reader.read_line(&mut line) {
+            Ok(0) => return io::ErrorKind::UnexpectedEof.into(),
+            Ok(_) if line.ends_with('\n') => {
+                if let Some(measurement) = parse_line(&line) {
+                    *latest.lock().unwrap() = Some(measurement);
+                }
+                line.clear();
+            }
+            Ok(_) => {}
+            Err(error) if error.kind() == io::ErrorKind::TimedOut => {}
+            // e.g. a byte that's not UTF-8
+            Err(error) if error.kind() == io::ErrorKind::InvalidData => line.clear(),
+            Err(error) => return error,
+        }
+    }
+}
+
+// A measurement in any of the formats of `serial_report`, `None` for anything else:
+// * CSV: `12,812.4,21.46,45.50,1`
+// * JSON: `{"uptime_s":12,"co2_ppm":812.4,"temperature_c":21.46,...}`
+// * InfluxDB line protocol: `air_quality co2=812.4,temperature=21.46,...,alert_level=1i`
+fn parse_line(line: &str) -> Option<Measurement> {
+    let line = line.trim();
+    if line.starts_with('{') && line.ends_with('}') {
+        let fields = &line[1..line.len() - 1];
+        parse_fields(
+            fields,
+            ':',
+            ["\"co2_ppm\"", "\"temperature_c\"", "\"humidity_percent\""],
+            "\"alert_level\"",
+        )
+    } else if let Some(fields) = line.strip_prefix("air_quality ") {
+        parse_fields(
+            fields,
+            '=',
+            ["co2", "temperature", "humidity"],
+            "alert_level",
+        )
+    } else {
+        parse_csv(line)
+    }
+}
+
+// `uptime_s,co2_ppm,temperature_c,humidity_percent,alert_level`, the header doesn't parse
+fn parse_csv(line: &str) -> Option<Measurement> {
+    let fields: Vec<&str> = line.split(',').collect();
+    match fields[..] {
+        [_uptime, co2, temperature, humidity, alert_level] => Some(Measurement {
+            co2: co2.parse().ok()?,
+            temperature: temperature.parse().ok()?,
+            humidity: humidity.parse().ok()?,
+            alert_level: alert_level.parse().ok()?,
+        }),
+        _ => None,
+    }
+}
+
+// comma separated `name<separator>value` pairs, the integer of InfluxDB ends with `i`
+fn parse_fields(
+    fields: &str,
+    separator: char,
+    [co2, temperature, humidity]: [&str; 3],
+    alert_level: &str,
+) -> Option<Measurement> {
+    let value = |name: &str| {
+        fields
+            .split(',')
+            .filter_map(|field| field.split_once(separator))
+            .find(|(key, _)| key.trim() == name)
+            .map(|(_, value)| value.trim().trim_end_matches('i'))
+    };
+    Some(Measurement {
+        co2: value(co2)?.parse().ok()?,
+        temperature: value(temperature)?.parse().ok()?,
+        humidity: value(humidity)?.parse().ok()?,
+        alert_level: value(alert_level)?.parse().ok()?,
+    })
+}
+
+// answers one request and closes the connection
+fn serve(mut stream: TcpStream, latest: &Mutex<Option<Measurement>>) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut request = Vec::new();
+    let mut buffer = [0u8; 512];
+    // only the request line is of interest
+    while !request.contains(&b'\n') && request.len() < MAX_REQUEST_SIZE {
+        match stream.read(&mut buffer)? {
+            0 => break,
+            length => request.extend_from_slice(&buffer[..length]),
+        }
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut words = request.split_whitespace();
+
+    let (status, body) = match (words.next(), words.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let measurement = *latest.lock().unwrap();
+            ("200 OK", metrics(measurement))
+        }
+        (Some("GET"), Some("/")) => ("200 OK", "Metrics are at /metrics\n".to_string()),
+        (Some("GET"), _) => ("404 Not Found", "Not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "Only GET is supported\n".to_string(),
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+// Prometheus text format, empty without a measurement
+fn metrics(measurement: Option<Measurement>) -> String {
+    let mut text = String::new();
+    let measurement = match measurement {
+        Some(measurement) => measurement,
+        None => return text,
+    };
+    let gauges = [
+        ("scd30_co2_ppm", "CO2 concentration in ppm", measurement.co2),
+        (
+            "scd30_temperature_celsius",
+            "Temperature in degrees Celsius",
+            measurement.temperature,
+        ),
+        (
+            "scd30_humidity_percent",
+            "Relative humidity in percent",
+            measurement.humidity,
+        ),
+        (
+            "alert_level",
+            "Index of the air quality category, 0 for the best air",
+            f64::from(measurement.alert_level),
+        ),
+    ];
+    for (name, help, value) in gauges.iter() {
+        // writing to a `String` can't fail
+        let _ = writeln!(text, "# HELP {} {}", name, help);
+        let _ = writeln!(text, "# TYPE {} gauge", name);
+        let _ = writeln!(text, "{} {}", name, value);
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    const MEASUREMENT: Measurement = Measurement {
+        co2: 812.4,
+        temperature: 21.46,
+        humidity: 45.5,
+        alert_level: 1,
+    };
+
+    #[test]
+    fn csv() {
+        assert_eq!(parse_line("12,812.4,21.46,45.50,1\r\n"), Some(MEASUREMENT));
+    }
+
+    #[test]
+    fn json() {
+        let line = r#"{"uptime_s":12,"co2_ppm":812.4,"temperature_c":21.46,"humidity_percent":45.50,"alert_level":1}"#;
+        assert_eq!(parse_line(line), Some(MEASUREMENT));
+        // the order of the fields doesn't matter
+        let line = r#"{"alert_level": 1, "humidity_percent": 45.5, "temperature_c": 21.46, "co2_ppm": 812.4}"#;
+        assert_eq!(parse_line(line), Some(MEASUREMENT));
+    }
+
+    #[test]
+    fn influx() {
+        let line = "air_quality co2=812.4,temperature=21.46,humidity=45.50,alert_level=1i\n";
+        assert_eq!(parse_line(line), Some(MEASUREMENT));
+    }
+
+    #[test]
+    fn header() {
+        let line = "uptime_s,co2_ppm,temperature_c,humidity_percent,alert_level\n";
+        assert_eq!(parse_line(line), None);
+    }
+
+    #[test]
+    fn garbage() {
+        let lines = [
+            "",
+            "ok",
+            "error: unknown command, try help",
+            "12,812.4,21.46,45.50",
+            "12,812.4,21.46,45.50,1,7",
+            "12,812.4,21.46,45.50,-1",
+            "2,8\u{fffd}2.4,21.46,45.50,1",
+            r#"{"uptime_s":12,"co2_ppm":812.4,"temperature_c":21.46}"#,
+            r#"{"uptime_s":12,"co2_ppm":812.4,"#,
+            "air_quality co2=812.4,temperature=21.46,humidity=45.50",
+            "air_quality co2=high,temperature=21.46,humidity=45.50,alert_level=1i",
+        ];
+        for line in lines.iter() {
+            assert_eq!(parse_line(line), None, "{}", line);
+        }
+    }
+
+    #[test]
+    fn metrics_of_a_measurement() {
+        let expected = "\
+# HELP scd30_co2_ppm CO2 concentration in ppm
+# TYPE scd30_co2_ppm gauge
+scd30_co2_ppm 812.4
+# HELP scd30_temperature_celsius Temperature in degrees Celsius
+# TYPE scd30_temperature_celsius gauge
+scd30_temperature_celsius 21.46
+# HELP scd30_humidity_percent Relative humidity in percent
+# TYPE scd30_humidity_percent gauge
+scd30_humidity_percent 45.5
+# HELP alert_level Index of the air quality category, 0 for the best air
+# TYPE alert_level gauge
+alert_level 1
+";
+        assert_eq!(metrics(Some(MEASUREMENT)), expected);
+    }
+
+    #[test]
+    fn metrics_without_measurement() {
+        assert_eq!(metrics(None), "");
+    }
+
+    // hands out the reads of a serial port one by one, then fails like a port that's gone
+    struct Port {
+        reads: VecDeque<io::Result<Vec<u8>>>,
+    }
+
+    impl Read for Port {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            match self.reads.pop_front() {
+                Some(Ok(bytes)) => {
+                    buffer[..bytes.len()].copy_from_slice(&bytes);
+                    Ok(bytes.len())
+                }
+                Some(Err(error)) => Err(error),
+                None => Err(io::ErrorKind::BrokenPipe.into()),
+            }
+        }
+    }
+
+    fn timeout() -> io::Result<Vec<u8>> {
+        Err(io::ErrorKind::TimedOut.into())
+    }
+
+    #[test]
+    fn read_lines_keeps_the_latest_measurement() {
+        let port = Port {
+            reads: vec![
+                Ok(b"uptime_s,co2_ppm,temperature_c,humidity_percent,alert_level\n".to_vec()),
+                Ok(b"10,700.0,21.00,40.00,0\n".to_vec()),
+                timeout(),
+                // a line that arrives in parts, with nothing in between
+                Ok(b"12,812.4,21.".to_vec()),
+                timeout(),
+                Ok(b"46,45.50,1\n".to_vec()),
+                // damaged lines don't replace the measurement
+                Ok(b"14,9\xff9.0,21.00,40.00,0\n".to_vec()),
+                Ok(b"ok\n".to_vec()),
+            ]
+            .into(),
+        };
+        let latest = Mutex::new(None);
+
+        let error = read_lines(port, &latest);
+
+        assert_eq!(error.kind(), io::ErrorKind::BrokenPipe);
+        assert_eq!(*latest.lock().unwrap(), Some(MEASUREMENT));
+    }
+
+    #[test]
+    fn read_lines_ends_with_the_port() {
+        let latest = Mutex::new(None);
+        let error = read_lines(&b"12,812.4,21.46,45.50,1\n"[..], &latest);
+
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(*latest.lock().unwrap(), Some(MEASUREMENT));
+    }
+
+    // a pseudo-terminal stands in for the device
+    #[cfg(unix)]
+    #[test]
+    fn read_lines_from_a_serial_port() {
+        let (mut device, port) = serialport::TTYPort::pair().unwrap();
+        let latest = Latest::default();
+        let reader_latest = Arc::clone(&latest);
+        let reader = thread::spawn(move || read_lines(port, &reader_latest));
+
+        device
+            .write_all(b"uptime_s,co2_ppm,temperature_c,humidity_percent,alert_level\n")
+            .unwrap();
+        device.write_all(b"10,700.0,21.00,40.00,0\n").unwrap();
+        device.write_all(b"12,812.4,21.46,45.50,1\n").unwrap();
+
+        let mut waited = Duration::from_secs(0);
+        while *latest.lock().unwrap() != Some(MEASUREMENT) {
+            assert!(waited < Duration::from_secs(5), "no measurement arrived");
+            thread::sleep(Duration::from_millis(10));
+            waited += Duration::from_millis(10);
+        }
+        assert_eq!(metrics(*latest.lock().unwrap()), metrics(Some(MEASUREMENT)));
+
+        // the reader returns once the other end is gone
+        drop(device);
+        let error = reader.join().unwrap();
+        assert_ne!(error.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn stalled_client_doesnt_hold_up_others() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let latest = Latest::new(Mutex::new(Some(MEASUREMENT)));
+        thread::spawn(move || accept(listener, latest));
+
+        // connects, but never sends its request
+        let _stalled = TcpStream::connect(address).unwrap();
+        let mut client = TcpStream::connect(address).unwrap();
+        // answered before the stalled connection times out
+        client.set_read_timeout(Some(READ_TIMEOUT / 2)).unwrap();
+        client
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with(&metrics(Some(MEASUREMENT))));
+    }
+}
@@ -0,0 +1,313 @@
+// Driver for the Bosch BMP280 and BME280 barometric sensors.
+//
+// Only temperature and pressure are read, the humidity of the BME280 is left to the SCD30.
+// The sensor runs in normal mode: it measures once a second on its own, a read returns the
+// latest result. The raw values are compensated with the integer formulas of the datasheet.
+
+use crate::{i2c::Bus, Error};
+
+/// address with SDO tied to GND, `SECONDARY_ADDRESS` with SDO tied to VDDIO
+pub const DEFAULT_ADDRESS: u8 = 0x76;
+pub const SECONDARY_ADDRESS: u8 = 0x77;
+
+// registers
+const CHIP_ID: u8 = 0xd0;
+const CALIBRATION: u8 = 0x88;
+const CTRL_MEAS: u8 = 0xf4;
+const CONFIG: u8 = 0xf5;
+const DATA: u8 = 0xf7;
+
+const BMP280_CHIP_ID: u8 = 0x58;
+const BME280_CHIP_ID: u8 = 0x60;
+
+// temperature oversampling x1, pressure oversampling x16, normal mode
+#[allow(clippy::unusual_byte_groupings)] // grouped by register field
+const CTRL_MEAS_NORMAL: u8 = 0b001_101_11;
+// standby 1000 ms between measurements, IIR filter coefficient 4
+#[allow(clippy::unusual_byte_groupings)] // grouped by register field
+const CONFIG_NORMAL: u8 = 0b101_010_00;
+
+#[derive(Clone, Copy, PartialEq, defmt::Format)]
+pub enum Model {
+    Bmp280,
+    /// also measures humidity, which this driver doesn't read
+    Bme280,
+}
+
+#[derive(Clone, Copy)]
+pub struct BarometerData {
+    /// in hPa, which is the same as mbar
+    pub pressure: f32,
+    /// in °C, of the sensor itself, not necessarily of the room
+    pub temperature: f32,
+}
+
+// trimming parameters stored in the sensor during production
+struct Calibration {
+    t1: u16,
+    t2: i16,
+    t3: i16,
+    p1: u16,
+    p2: i16,
+    p3: i16,
+    p4: i16,
+    p5: i16,
+    p6: i16,
+    p7: i16,
+    p8: i16,
+    p9: i16,
+}
+
+pub struct BMP280<B: Bus> {
+    bus: B,
+    address: u8,
+    model: Model,
+    calibration: Calibration,
+}
+
+impl<B> BMP280<B>
+where
+    B: Bus,
+{
+    /// Checks the chip ID, reads the calibration and starts measuring.
+    /// Returns `Error::UnknownDevice` if something else answers at `address`.
+    pub fn init(mut bus: B, address: u8) -> Result<Self, Error> {
+        let mut chip_id = [0u8; 1];
+        read_register(&mut bus, address, CHIP_ID, &mut chip_id)?;
+        let model = match chip_id[0] {
+            BMP280_CHIP_ID => Model::Bmp280,
+            BME280_CHIP_ID => Model::Bme280,
+            _ => return Err(Error::UnknownDevice),
+        };
+
+        let mut rd_buffer = [0u8; 24];
+        read_register(&mut bus, address, CALIBRATION, &mut rd_buffer)?;
+        let calibration = parse_calibration(&rd_buffer);
+
+        // the config register is only written reliably in sleep mode, which the sensor is in
+        // after power up
+        bus.write(address, &[CONFIG, CONFIG_NORMAL])?;
+        bus.write(address, &[CTRL_MEAS, CTRL_MEAS_NORMAL])?;
+
+        Ok(BMP280 {
+            bus,
+            address,
+            model,
+            calibration,
+        })
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    pub fn read(&mut self) -> Result<BarometerData, Error> {
+        let mut rd_buffer = [0u8; 6];
+        read_register(&mut self.bus, self.address, DATA, &mut rd_buffer)?;
+
+        // 20 bit values, most significant byte first
+        let raw_pressure = (i32::from(rd_buffer[0]) << 12)
+            | (i32::from(rd_buffer[1]) << 4)
+            | (i32::from(rd_buffer[2]) >> 4);
+        let raw_temperature = (i32::from(rd_buffer[3]) << 12)
+            | (i32::from(rd_buffer[4]) << 4)
+            | (i32::from(rd_buffer[5]) >> 4);
+
+        let (temperature, t_fine) = self.calibration.temperature(raw_temperature);
+        let pressure = self.calibration.pressure(raw_pressure, t_fine);
+
+        Ok(BarometerData {
+            pressure: pressure as f32 / 25_600.0,
+            temperature: temperature as f32 / 100.0,
+        })
+    }
+
+    /// Ambient pressure in hPa
+    pub fn read_pressure(&mut self) -> Result<f32, Error> {
+        Ok(self.read()?.pressure)
+    }
+
+    pub fn release(self) -> B {
+        self.bus
+    }
+}
+
+// writes the register address, then reads from there on; the sensor increments the address
+fn read_register<B: Bus>(
+    bus: &mut B,
+    address: u8,
+    register: u8,
+    rd_buffer: &mut [u8],
+) -> Result<(), Error> {
+    bus.write(address, &[register])?;
+    bus.read(address, rd_buffer)
+}
+
+// the calibration words are little endian
+fn parse_calibration(rd_buffer: &[u8; 24]) -> Calibration {
+    let unsigned = |index: usize| u16::from_le_bytes([rd_buffer[index], rd_buffer[index + 1]]);
+    let signed = |index: usize| i16::from_le_bytes([rd_buffer[index], rd_buffer[index + 1]]);
+
+    Calibration {
+        t1: unsigned(0),
+        t2: signed(2),
+        t3: signed(4),
+        p1: unsigned(6),
+        p2: signed(8),
+        p3: signed(10),
+        p4: signed(12),
+        p5: signed(14),
+        p6: signed(16),
+        p7: signed(18),
+        p8: signed(20),
+        p9: signed(22),
+    }
+}
+
+// compensation formulas from the BMP280 datasheet, section 8.2
+impl Calibration {
+    // returns the temperature in 0.01 °C, and the fine temperature the pressure formula needs
+    fn temperature(&self, raw: i32) -> (i32, i32) {
+        let t1 = i32::from(self.t1);
+        let var1 = (((raw >> 3) - (t1 << 1)) * i32::from(self.t2)) >> 11;
+        let var2 = (((((raw >> 4) - t1) * ((raw >> 4) - t1)) >> 12) * i32::from(self.t3)) >> 14;
+        let t_fine = var1 + var2;
+
+        ((t_fine * 5 + 128) >> 8, t_fine)
+    }
+
+    // returns the pressure in Pa as Q24.8 fixed point number
+    fn pressure(&self, raw: i32, t_fine: i32) -> u32 {
+        let mut var1 = i64::from(t_fine) - 128_000;
+        let mut var2 = var1 * var1 * i64::from(self.p6);
+        var2 += (var1 * i64::from(self.p5)) << 17;
+        var2 += i64::from(self.p4) << 35;
+        var1 = ((var1 * var1 * i64::from(self.p3)) >> 8) + ((var1 * i64::from(self.p2)) << 12);
+        var1 = (((1_i64 << 47) + var1) * i64::from(self.p1)) >> 33;
+        if var1 == 0 {
+            // avoids a division by zero, only happens with an uncalibrated sensor
+            return 0;
+        }
+
+        let mut p = 1_048_576 - i64::from(raw);
+        p = (((p << 31) - var2) * 3125) / var1;
+        var1 = (i64::from(self.p9) * (p >> 13) * (p >> 13)) >> 25;
+        var2 = (i64::from(self.p8) * p) >> 19;
+        p = ((p + var1 + var2) >> 8) + (i64::from(self.p7) << 4);
+
+        p as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the example of the datasheet, section 3.12
+    const CALIBRATION_WORDS: [i32; 12] = [
+        27504, 26435, -1000, 36477, -10685, 3024, 2855, 140, -7, 15500, -14600, 6000,
+    ];
+    const RAW_TEMPERATURE: i32 = 519_888;
+    const RAW_PRESSURE: i32 = 415_148;
+
+    // a sensor with the registers of the example, and a log of the register writes
+    struct MockBus {
+        registers: [u8; 256],
+        pointer: usize,
+        writes: Vec<[u8; 2]>,
+    }
+
+    impl MockBus {
+        fn new(chip_id: u8) -> Self {
+            let mut registers = [0u8; 256];
+            registers[usize::from(CHIP_ID)] = chip_id;
+            for (index, word) in CALIBRATION_WORDS.iter().enumerate() {
+                let start = usize::from(CALIBRATION) + 2 * index;
+                registers[start..start + 2].copy_from_slice(&(*word as u16).to_le_bytes());
+            }
+            let data = usize::from(DATA);
+            registers[data..data + 3].copy_from_slice(&(RAW_PRESSURE << 4).to_be_bytes()[1..]);
+            registers[data + 3..data + 6]
+                .copy_from_slice(&(RAW_TEMPERATURE << 4).to_be_bytes()[1..]);
+            MockBus {
+                registers,
+                pointer: 0,
+                writes: Vec::new(),
+            }
+        }
+    }
+
+    impl Bus for MockBus {
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+            assert_eq!(address, DEFAULT_ADDRESS);
+            self.pointer = usize::from(bytes[0]);
+            if let [register, value] = *bytes {
+                self.writes.push([register, value]);
+            }
+            Ok(())
+        }
+
+        fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+            assert_eq!(address, DEFAULT_ADDRESS);
+            buffer.copy_from_slice(&self.registers[self.pointer..self.pointer + buffer.len()]);
+            Ok(())
+        }
+
+        fn recover(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn calibration() -> Calibration {
+        let mut rd_buffer = [0u8; 24];
+        let bus = MockBus::new(BMP280_CHIP_ID);
+        let start = usize::from(CALIBRATION);
+        rd_buffer.copy_from_slice(&bus.registers[start..start + 24]);
+        parse_calibration(&rd_buffer)
+    }
+
+    #[test]
+    fn temperature_of_the_datasheet() {
+        assert_eq!(calibration().temperature(RAW_TEMPERATURE), (2508, 128_422));
+    }
+
+    #[test]
+    fn pressure_of_the_datasheet() {
+        // 100653.27 Pa as Q24.8
+        let pressure = calibration().pressure(RAW_PRESSURE, 128_422);
+        assert!((f64::from(pressure) / 256.0 - 100_653.27).abs() < 0.02);
+    }
+
+    #[test]
+    fn uncalibrated() {
+        let calibration = Calibration {
+            p1: 0,
+            ..calibration()
+        };
+        assert_eq!(calibration.pressure(RAW_PRESSURE, 128_422), 0);
+    }
+
+    #[test]
+    fn init_and_read() {
+        let mut barometer = BMP280::init(MockBus::new(BME280_CHIP_ID), DEFAULT_ADDRESS).unwrap();
+        assert!(barometer.model() == Model::Bme280);
+
+        let data = barometer.read().unwrap();
+        assert!((data.temperature - 25.08).abs() < 0.001);
+        assert!((data.pressure - 1006.5327).abs() < 0.001);
+
+        let bus = barometer.release();
+        assert_eq!(
+            bus.writes,
+            [[CONFIG, CONFIG_NORMAL], [CTRL_MEAS, CTRL_MEAS_NORMAL]]
+        );
+    }
+
+    #[test]
+    fn unknown_device() {
+        assert!(matches!(
+            BMP280::init(MockBus::new(0x55), DEFAULT_ADDRESS),
+            Err(Error::UnknownDevice)
+        ));
+    }
+}
@@ -0,0 +1,32 @@
+// Wiring of our carrier board. The DK sits on the carrier, so buttons and onboard LEDs are the
+// ones of the DK, while LED, buzzer and sensor are routed to the P1 header to keep P0 free.
+
+use nrf52840_hal::gpio::{p0, p1};
+
+use super::PinMap;
+
+pub(crate) fn pin_map(p0: p0::Parts, p1: p1::Parts) -> PinMap {
+    PinMap {
+        led_red: p1.p1_01.degrade(),
+        led_blue: p1.p1_02.degrade(),
+        led_green: p1.p1_03.degrade(),
+        buzzer: p1.p1_04.degrade(),
+        scl: p1.p1_05.degrade(),
+        sda: p1.p1_06.degrade(),
+        // virtual COM port of the DK's debugger
+        txd: p0.p0_06.degrade(),
+        rxd: p0.p0_08.degrade(),
+        buttons: [
+            p0.p0_11.degrade(),
+            p0.p0_12.degrade(),
+            p0.p0_24.degrade(),
+            p0.p0_25.degrade(),
+        ],
+        leds: [
+            p0.p0_13.degrade(),
+            p0.p0_14.degrade(),
+            p0.p0_15.degrade(),
+            p0.p0_16.degrade(),
+        ],
+    }
+}
@@ -0,0 +1,31 @@
+// Wiring of the nRF52840-DK as used throughout the knurling sessions
+
+use nrf52840_hal::gpio::{p0, p1};
+
+use super::PinMap;
+
+pub(crate) fn pin_map(p0: p0::Parts, _p1: p1::Parts) -> PinMap {
+    PinMap {
+        led_red: p0.p0_03.degrade(),
+        led_blue: p0.p0_04.degrade(),
+        led_green: p0.p0_28.degrade(),
+        buzzer: p0.p0_29.degrade(),
+        scl: p0.p0_30.degrade(),
+        sda: p0.p0_31.degrade(),
+        // virtual COM port of the onboard debugger
+        txd: p0.p0_06.degrade(),
+        rxd: p0.p0_08.degrade(),
+        buttons: [
+            p0.p0_11.degrade(),
+            p0.p0_12.degrade(),
+            p0.p0_24.degrade(),
+            p0.p0_25.degrade(),
+        ],
+        leds: [
+            p0.p0_13.degrade(),
+            p0.p0_14.degrade(),
+            p0.p0_15.degrade(),
+            p0.p0_16.degrade(),
+        ],
+    }
+}
@@ -0,0 +1,229 @@
+// Board support: takes the peripherals and returns the ready-to-use drivers, so binaries don't
+// have to know which pin is wired to what.
+//
+// The wiring is selected with a cargo feature:
+// * `board-dk`: nRF52840-DK as wired in the knurling sessions (default)
+// * `board-carrier`: DK on our carrier board
+//
+// The CO2 sensor is an SCD30, or an SCD40/SCD41 with the `sensor-scd4x` feature.
+//
+// The settings stored in the flash are loaded first, as some drivers depend on them.
+//
+// The I2C bus runs at 100 kHz, `Board::take_with_frequency` picks another frequency.
+//
+// With the `usb` feature, the external high frequency crystal is started for the USB peripheral.
+
+use nrf52840_hal::{
+    clocks::Clocks,
+    gpio::{p0, p1, Disconnected, Level, Output, Pin, PushPull},
+    pac::{self, NFCT, TIMER0, TIMER1, UARTE0},
+    timer::{OneShot, Periodic},
+    twim, uarte,
+    Temp, Timer,
+};
+
+#[cfg(feature = "usb")]
+use nrf52840_hal::{
+    clocks::{ExternalOscillator, Internal, LfOscStarted},
+    pac::USBD,
+};
+
+#[cfg(not(feature = "sensor-scd4x"))]
+use crate::scd30::Timing;
+use crate::{
+    buzzer::Buzzer,
+    clock,
+    config::{ConfigStore, Settings},
+    dk_button::Button,
+    flash::Flash,
+    i2c::TwimBus,
+    rgb_led::LEDColor,
+};
+
+/// Driver of the CO2 sensor fitted to the board, it implements `co2_sensor::Co2Sensor`
+#[cfg(not(feature = "sensor-scd4x"))]
+pub use crate::scd30::SCD30 as Sensor;
+#[cfg(feature = "sensor-scd4x")]
+pub use crate::scd4x::SCD4x as Sensor;
+
+#[cfg(all(feature = "board-dk", feature = "board-carrier"))]
+compile_error!("only one of the features `board-dk` and `board-carrier` can be enabled");
+
+#[cfg(not(any(feature = "board-dk", feature = "board-carrier")))]
+compile_error!("one of the features `board-dk` or `board-carrier` has to be enabled");
+
+#[cfg(feature = "board-carrier")]
+mod carrier;
+#[cfg(feature = "board-dk")]
+mod dk;
+
+#[cfg(feature = "board-carrier")]
+use carrier::pin_map;
+#[cfg(feature = "board-dk")]
+use dk::pin_map;
+
+/// Pins of one wiring variant, not yet configured
+pub(crate) struct PinMap {
+    pub led_red: Pin<Disconnected>,
+    pub led_blue: Pin<Disconnected>,
+    pub led_green: Pin<Disconnected>,
+    pub buzzer: Pin<Disconnected>,
+    pub scl: Pin<Disconnected>,
+    pub sda: Pin<Disconnected>,
+    pub txd: Pin<Disconnected>,
+    pub rxd: Pin<Disconnected>,
+    pub buttons: [Pin<Disconnected>; 4],
+    pub leds: [Pin<Disconnected>; 4],
+}
+
+/// The four user buttons of the DK
+pub struct Buttons {
+    pub one: Button,
+    pub two: Button,
+    pub three: Button,
+    pub four: Button,
+}
+
+/// The four onboard LEDs of the DK. They are active low: `set_low()` turns them on.
+pub struct Leds {
+    pub one: Pin<Output<PushPull>>,
+    pub two: Pin<Output<PushPull>>,
+    pub three: Pin<Output<PushPull>>,
+    pub four: Pin<Output<PushPull>>,
+}
+
+/// UARTE0 and its pins, the application configures the baud rate, e.g. with
+/// `serial_report::SerialReport::new`
+pub struct Serial {
+    pub uarte: UARTE0,
+    pub pins: uarte::Pins,
+}
+
+/// USB peripheral and the clocks it runs on, see `usb_serial`
+#[cfg(feature = "usb")]
+pub struct Usb {
+    pub usbd: USBD,
+    pub clocks: Clocks<ExternalOscillator, Internal, LfOscStarted>,
+}
+
+pub struct Board {
+    pub led_indicator: LEDColor,
+    pub buzzer: Buzzer,
+    pub buttons: Buttons,
+    pub leds: Leds,
+    pub sensor: Sensor<TwimBus>,
+    pub timer: Timer<TIMER0, OneShot>,
+    pub periodic_timer: Timer<TIMER1, Periodic>,
+    pub temp: Temp,
+    pub serial: Serial,
+    /// NFC tag peripheral, the antenna is connected to NFC1 and NFC2, see `nfc`
+    pub nfct: NFCT,
+    #[cfg(feature = "usb")]
+    pub usb: Usb,
+    pub flash: Flash,
+    /// saves changed `settings`
+    pub config: ConfigStore,
+    pub settings: Settings,
+}
+
+impl Board {
+    /// Returns the configured board and starts the `clock`.
+    /// Returns `None` if the peripherals have already been taken.
+    pub fn take() -> Option<Self> {
+        pac::Peripherals::take().map(Board::new)
+    }
+
+    /// Like `take`, with the I2C bus at `frequency`, e.g. `twim::Frequency::K400`. Above
+    /// 100 kHz, the SCD30 is driven with `Timing::ClockStretchTolerant`.
+    pub fn take_with_frequency(frequency: twim::Frequency) -> Option<Self> {
+        pac::Peripherals::take().map(|board| Board::new_with_frequency(board, frequency))
+    }
+
+    /// Configures the board from peripherals that have been taken elsewhere, e.g. by RTIC.
+    pub fn new(board: pac::Peripherals) -> Self {
+        Board::new_with_frequency(board, twim::Frequency::K100)
+    }
+
+    /// Like `new`, with the I2C bus at `frequency`
+    pub fn new_with_frequency(board: pac::Peripherals, frequency: twim::Frequency) -> Self {
+        let clocks = Clocks::new(board.CLOCK);
+        #[cfg(feature = "usb")]
+        let clocks = clocks.enable_ext_hfosc();
+        let clocks = clocks.start_lfclk();
+        clock::init(board.RTC1, &clocks);
+
+        let flash = Flash::new(board.NVMC);
+        let (config, settings) = ConfigStore::load(&flash);
+
+        let pins = pin_map(p0::Parts::new(board.P0), p1::Parts::new(board.P1));
+
+        let led_indicator = LEDColor::init_with_polarity(
+            pins.led_red,
+            pins.led_blue,
+            pins.led_green,
+            settings.led_polarity,
+        );
+        let buzzer = Buzzer::init(pins.buzzer);
+
+        let [button_1, button_2, button_3, button_4] = pins.buttons;
+        let buttons = Buttons {
+            one: Button::new(button_1),
+            two: Button::new(button_2),
+            three: Button::new(button_3),
+            four: Button::new(button_4),
+        };
+
+        let [led_1, led_2, led_3, led_4] = pins.leds;
+        let leds = Leds {
+            one: led_1.into_push_pull_output(Level::High),
+            two: led_2.into_push_pull_output(Level::High),
+            three: led_3.into_push_pull_output(Level::High),
+            four: led_4.into_push_pull_output(Level::High),
+        };
+
+        let i2c = TwimBus::new(
+            board.TWIM0,
+            pins.scl.into_floating_input(),
+            pins.sda.into_floating_input(),
+            frequency,
+        );
+        #[cfg_attr(feature = "sensor-scd4x", allow(unused_mut))]
+        let mut sensor = Sensor::init(i2c);
+        // the SCD30 only supports clock stretching up to 100 kHz
+        #[cfg(not(feature = "sensor-scd4x"))]
+        if frequency != twim::Frequency::K100 {
+            sensor.set_timing(Timing::ClockStretchTolerant);
+        }
+
+        let serial = Serial {
+            uarte: board.UARTE0,
+            pins: uarte::Pins {
+                txd: pins.txd.into_push_pull_output(Level::High),
+                rxd: pins.rxd.into_floating_input(),
+                cts: None,
+                rts: None,
+            },
+        };
+
+        Board {
+            led_indicator,
+            buzzer,
+            buttons,
+            leds,
+            sensor,
+            timer: Timer::new(board.TIMER0),
+            periodic_timer: Timer::periodic(board.TIMER1),
+            temp: Temp::new(board.TEMP),
+            serial,
+            nfct: board.NFCT,
+            #[cfg(feature = "usb")]
+            usb: Usb {
+                usbd: board.USBD,
+                clocks,
+            },
+            flash,
+            config,
+            settings,
+        }
+    }
+}
@@ -0,0 +1,47 @@
+// Async variant of the buzzer, the delays between toggling the pin let other tasks run.
+
+use embedded_hal_1::digital::OutputPin;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::Error;
+
+use super::{duration_to_range, frequency_to_delay, VALID_FREQUENCIES_HZ};
+
+pub struct Buzzer<P, D> {
+    pin: P,
+    delay: D,
+}
+
+impl<P, D> Buzzer<P, D>
+where
+    P: OutputPin,
+    D: DelayNs,
+{
+    /// The pin has to be configured as output
+    pub fn init(pin: P, delay: D) -> Self {
+        Buzzer { pin, delay }
+    }
+
+    /// Buzzes with a fixed frequency and length, like `Buzzer::noise()`
+    pub async fn noise(&mut self) -> Result<(), Error> {
+        self.beep(100, 5000).await
+    }
+
+    /// Buzzes in the desired frequency in Hz for the desired length in ms
+    pub async fn beep(&mut self, frequency_hz: u32, duration_ms: u32) -> Result<(), Error> {
+        if !VALID_FREQUENCIES_HZ.contains(&frequency_hz) {
+            return Err(Error::InvalidArgument);
+        }
+
+        let delay_ms = frequency_to_delay(&frequency_hz);
+        let max_range = duration_to_range(duration_ms, &frequency_hz);
+
+        for _i in 0..max_range {
+            self.pin.set_high().map_err(|_| Error::Pin)?;
+            self.delay.delay_ms(delay_ms).await;
+            self.pin.set_low().map_err(|_| Error::Pin)?;
+            self.delay.delay_ms(delay_ms).await;
+        }
+        Ok(())
+    }
+}
@@ -1,3 +1,5 @@
+use core::ops::RangeInclusive;
+
 use nrf52840_hal::{
     gpio::{Level, Output, Pin, PushPull},
     pac::TIMER0,
@@ -6,7 +8,18 @@ use nrf52840_hal::{
     Timer,
 };
 
-use embedded_hal::blocking::delay::DelayMs;
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use crate::Error;
+
+#[cfg(feature = "async")]
+pub mod asynch;
+
+// the delay between toggling the pin is counted in whole milliseconds
+const VALID_FREQUENCIES_HZ: RangeInclusive<u32> = 1..=1000;
+// `tone` counts in microseconds, a half period has to be at least one
+const VALID_TONE_FREQUENCIES_HZ: RangeInclusive<u32> = 1..=500_000;
+
 pub struct Buzzer(Pin<Output<PushPull>>);
 
 impl Buzzer {
@@ -18,20 +31,23 @@ impl Buzzer {
     // In this case, we could definately set the pins high and low directly in `fn noise()`
     // But it is a quite simple example, of how abstractions work.
 
-    fn high(&mut self) {
-        self.0.set_high().unwrap();
+    fn high(&mut self) -> Result<(), Error> {
+        self.0.set_high()?;
+        Ok(())
     }
 
-    fn low(&mut self) {
-        self.0.set_low().unwrap();
+    fn low(&mut self) -> Result<(), Error> {
+        self.0.set_low()?;
+        Ok(())
     }
-    pub fn noise(&mut self, timer: &mut Timer<TIMER0, OneShot>) {
+    pub fn noise(&mut self, timer: &mut Timer<TIMER0, OneShot>) -> Result<(), Error> {
         for _i in 0..250 {
-            self.high();
+            self.high()?;
             timer.delay_ms(10_u32);
-            self.low();
+            self.low()?;
             timer.delay_ms(10_u32);
         }
+        Ok(())
     }
 
     // this function allows you to choose frequency and length of buzz
@@ -40,16 +56,44 @@ impl Buzzer {
         timer: &mut Timer<TIMER0, OneShot>,
         frequency_hz: u32,
         duration_ms: u32,
-    ) {
+    ) -> Result<(), Error> {
+        if !VALID_FREQUENCIES_HZ.contains(&frequency_hz) {
+            return Err(Error::InvalidArgument);
+        }
+
         let delay_ms = frequency_to_delay(&frequency_hz);
         let max_range = duration_to_range(duration_ms, &frequency_hz);
 
         for _i in 0..max_range {
-            self.high();
+            self.high()?;
             timer.delay_ms(delay_ms);
-            self.low();
+            self.low()?;
             timer.delay_ms(delay_ms);
         }
+        Ok(())
+    }
+
+    // like `noise_variable`, but the pin is toggled in microseconds, so higher tones are exact
+    pub fn tone(
+        &mut self,
+        timer: &mut Timer<TIMER0, OneShot>,
+        frequency_hz: u32,
+        duration_ms: u32,
+    ) -> Result<(), Error> {
+        if !VALID_TONE_FREQUENCIES_HZ.contains(&frequency_hz) {
+            return Err(Error::InvalidArgument);
+        }
+
+        let half_period_us = 500_000 / frequency_hz;
+        let periods = duration_ms * 1000 / (half_period_us * 2);
+
+        for _ in 0..periods {
+            self.high()?;
+            timer.delay_us(half_period_us);
+            self.low()?;
+            timer.delay_us(half_period_us);
+        }
+        Ok(())
     }
 }
 
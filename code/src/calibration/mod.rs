@@ -0,0 +1,377 @@
+// Guided forced recalibration of the CO2 sensor.
+//
+// Without reference gas, the sensor is calibrated with outdoor air, which has about 420 ppm.
+// The device is put outside and the calibration started, e.g. with a long press of a button.
+// Once the readings have stayed within a narrow band for a few minutes, the sensor is told that
+// it measures the reference concentration. If they don't settle before the timeout, the
+// calibration fails and the sensor keeps its old calibration.
+//
+// calibration.start(clock::now());
+// loop {
+//     let data = sensor.read()?;
+//     if let Some(result) = calibration.update(&mut sensor, data.co2, clock::now()) { ... }
+// }
+
+use crate::{
+    clock::{Duration, Instant},
+    co2_sensor::Co2Sensor,
+    Error,
+};
+
+#[cfg(target_os = "none")]
+mod signalling;
+#[cfg(target_os = "none")]
+pub use signalling::{show_state, sound_result};
+
+/// CO2 concentration of fresh outdoor air in ppm
+pub const DEFAULT_REFERENCE_PPM: u16 = 420;
+/// how long the readings have to be stable before the sensor is calibrated
+pub const DEFAULT_STABLE_DURATION: Duration = Duration::from_secs(5 * 60);
+/// largest difference between the readings that still counts as stable, in ppm
+pub const DEFAULT_TOLERANCE_PPM: f32 = 30.0;
+/// the calibration fails if the readings aren't stable for long enough in this time
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+// how long `state()` reports the result of a finished calibration
+const RESULT_DURATION: Duration = Duration::from_secs(10);
+
+/// What the calibration is doing, e.g. to show it on the LED
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum State {
+    Idle,
+    /// waiting for the readings to be stable
+    Stabilizing,
+    Succeeded,
+    Failed(Error),
+}
+
+#[derive(Clone, Copy)]
+enum Phase {
+    Idle,
+    Stabilizing {
+        started: Instant,
+    },
+    Finished {
+        result: Result<(), Error>,
+        at: Instant,
+    },
+}
+
+// band the readings stayed in since `since`
+#[derive(Clone, Copy)]
+struct Window {
+    since: Instant,
+    min: f32,
+    max: f32,
+}
+
+pub struct Calibration {
+    reference: u16,
+    stable_duration: Duration,
+    tolerance: f32,
+    timeout: Duration,
+    phase: Phase,
+    window: Option<Window>,
+}
+
+impl Calibration {
+    pub fn new() -> Self {
+        Calibration {
+            reference: DEFAULT_REFERENCE_PPM,
+            stable_duration: DEFAULT_STABLE_DURATION,
+            tolerance: DEFAULT_TOLERANCE_PPM,
+            timeout: DEFAULT_TIMEOUT,
+            phase: Phase::Idle,
+            window: None,
+        }
+    }
+
+    /// CO2 concentration in ppm the sensor is calibrated to
+    pub fn set_reference(&mut self, reference: u16) {
+        self.reference = reference;
+    }
+
+    pub fn set_stable_duration(&mut self, stable_duration: Duration) {
+        self.stable_duration = stable_duration;
+    }
+
+    pub fn set_tolerance(&mut self, tolerance: f32) {
+        self.tolerance = tolerance;
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn start(&mut self, now: Instant) {
+        self.phase = Phase::Stabilizing { started: now };
+        self.window = None;
+    }
+
+    pub fn cancel(&mut self) {
+        self.phase = Phase::Idle;
+        self.window = None;
+    }
+
+    pub fn is_active(&self) -> bool {
+        matches!(self.phase, Phase::Stabilizing { .. })
+    }
+
+    /// The result of a finished calibration is reported for a few seconds, then it's idle again
+    pub fn state(&self, now: Instant) -> State {
+        match self.phase {
+            Phase::Idle => State::Idle,
+            Phase::Stabilizing { .. } => State::Stabilizing,
+            Phase::Finished { result, at } if now.duration_since(at) < RESULT_DURATION => {
+                match result {
+                    Ok(()) => State::Succeeded,
+                    Err(error) => State::Failed(error),
+                }
+            }
+            Phase::Finished { .. } => State::Idle,
+        }
+    }
+
+    /// How long the readings have been stable so far
+    pub fn stable_for(&self, now: Instant) -> Duration {
+        match (self.phase, self.window) {
+            (Phase::Stabilizing { .. }, Some(window)) => now.duration_since(window.since),
+            _ => Duration::from_millis(0),
+        }
+    }
+
+    /// Feeds a new reading in ppm. Once the readings have been stable for long enough, the
+    /// sensor is calibrated. Returns the result when the calibration finished, `None` while it
+    /// is still running or not started.
+    pub fn update<S: Co2Sensor>(
+        &mut self,
+        sensor: &mut S,
+        co2: f32,
+        now: Instant,
+    ) -> Option<Result<(), Error>> {
+        let started = match self.phase {
+            Phase::Stabilizing { started } => started,
+            _ => return None,
+        };
+
+        // a reading outside of the band starts a new window
+        let window = match self.window {
+            Some(window) if window.max.max(co2) - window.min.min(co2) <= self.tolerance => Window {
+                since: window.since,
+                min: window.min.min(co2),
+                max: window.max.max(co2),
+            },
+            _ => Window {
+                since: now,
+                min: co2,
+                max: co2,
+            },
+        };
+        self.window = Some(window);
+
+        let result = if now.duration_since(window.since) >= self.stable_duration {
+            defmt::info!(
+                "Readings stable between {=f32} and {=f32} ppm, calibrating to {=u16} ppm",
+                window.min,
+                window.max,
+                self.reference
+            );
+            sensor.calibrate(self.reference)
+        } else if now.duration_since(started) >= self.timeout {
+            Err(Error::Timeout)
+        } else {
+            return None;
+        };
+
+        self.phase = Phase::Finished { result, at: now };
+        self.window = None;
+        Some(result)
+    }
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::co2_sensor::SensorData;
+
+    #[derive(Default)]
+    struct MockSensor {
+        calibrated: Vec<u16>,
+        fails: bool,
+    }
+
+    impl Co2Sensor for MockSensor {
+        fn start(&mut self, _: u16) -> Result<(), Error> {
+            unimplemented!()
+        }
+
+        fn set_altitude(&mut self, _: u16) -> Result<(), Error> {
+            unimplemented!()
+        }
+
+        fn data_ready(&mut self) -> Result<bool, Error> {
+            unimplemented!()
+        }
+
+        fn read(&mut self) -> Result<SensorData, Error> {
+            unimplemented!()
+        }
+
+        fn calibrate(&mut self, reference: u16) -> Result<(), Error> {
+            self.calibrated.push(reference);
+            if self.fails {
+                Err(Error::NotResponding)
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    fn at(secs: u64) -> Instant {
+        Instant::from_millis(secs * 1000)
+    }
+
+    #[test]
+    fn idle_until_started() {
+        let mut calibration = Calibration::new();
+        let mut sensor = MockSensor::default();
+
+        assert_eq!(calibration.state(at(0)), State::Idle);
+        assert!(!calibration.is_active());
+        assert_eq!(calibration.update(&mut sensor, 420.0, at(0)), None);
+        assert_eq!(calibration.update(&mut sensor, 420.0, at(600)), None);
+        assert!(sensor.calibrated.is_empty());
+    }
+
+    #[test]
+    fn calibrates_once_stable() {
+        let mut calibration = Calibration::new();
+        let mut sensor = MockSensor::default();
+        calibration.start(at(0));
+        assert_eq!(calibration.state(at(0)), State::Stabilizing);
+
+        // readings every 2 s within 30 ppm of each other
+        for secs in (0..300).step_by(2) {
+            let co2 = 410.0 + (secs % 30) as f32;
+            assert_eq!(calibration.update(&mut sensor, co2, at(secs)), None);
+        }
+        assert_eq!(calibration.stable_for(at(298)), Duration::from_secs(298));
+
+        assert_eq!(
+            calibration.update(&mut sensor, 425.0, at(300)),
+            Some(Ok(()))
+        );
+        assert_eq!(sensor.calibrated, [DEFAULT_REFERENCE_PPM]);
+        assert!(!calibration.is_active());
+        assert_eq!(calibration.stable_for(at(300)), Duration::from_millis(0));
+
+        // later readings don't calibrate again
+        assert_eq!(calibration.update(&mut sensor, 425.0, at(302)), None);
+        assert_eq!(sensor.calibrated.len(), 1);
+    }
+
+    #[test]
+    fn reading_outside_the_band_restarts_the_window() {
+        let mut calibration = Calibration::new();
+        let mut sensor = MockSensor::default();
+        calibration.set_reference(400);
+        calibration.start(at(0));
+
+        calibration.update(&mut sensor, 420.0, at(0));
+        calibration.update(&mut sensor, 449.0, at(100));
+        // 31 ppm above the lowest reading
+        calibration.update(&mut sensor, 451.0, at(200));
+        assert_eq!(calibration.stable_for(at(200)), Duration::from_secs(0));
+
+        assert_eq!(calibration.update(&mut sensor, 440.0, at(300)), None);
+        assert_eq!(
+            calibration.update(&mut sensor, 440.0, at(500)),
+            Some(Ok(()))
+        );
+        assert_eq!(sensor.calibrated, [400]);
+    }
+
+    #[test]
+    fn times_out_without_stable_readings() {
+        let mut calibration = Calibration::new();
+        let mut sensor = MockSensor::default();
+        calibration.start(at(0));
+
+        let mut result = None;
+        let mut secs = 0;
+        while result.is_none() {
+            // alternates by 100 ppm, never stable
+            let co2 = if secs % 4 == 0 { 420.0 } else { 520.0 };
+            result = calibration.update(&mut sensor, co2, at(secs));
+            secs += 2;
+        }
+
+        assert_eq!(result, Some(Err(Error::Timeout)));
+        assert_eq!(secs - 2, 30 * 60);
+        assert!(sensor.calibrated.is_empty());
+    }
+
+    #[test]
+    fn sensor_error_is_the_result() {
+        let mut calibration = Calibration::new();
+        let mut sensor = MockSensor {
+            fails: true,
+            ..MockSensor::default()
+        };
+        calibration.set_stable_duration(Duration::from_secs(10));
+        calibration.start(at(0));
+
+        calibration.update(&mut sensor, 420.0, at(0));
+        assert_eq!(
+            calibration.update(&mut sensor, 420.0, at(10)),
+            Some(Err(Error::NotResponding))
+        );
+        assert_eq!(
+            calibration.state(at(10)),
+            State::Failed(Error::NotResponding)
+        );
+    }
+
+    #[test]
+    fn result_is_shown_for_a_while() {
+        let mut calibration = Calibration::new();
+        let mut sensor = MockSensor::default();
+        calibration.set_stable_duration(Duration::from_secs(10));
+        calibration.start(at(0));
+
+        calibration.update(&mut sensor, 420.0, at(0));
+        calibration.update(&mut sensor, 420.0, at(10));
+        assert_eq!(calibration.state(at(10)), State::Succeeded);
+        assert_eq!(calibration.state(at(19)), State::Succeeded);
+        assert_eq!(calibration.state(at(20)), State::Idle);
+    }
+
+    #[test]
+    fn cancel_and_restart() {
+        let mut calibration = Calibration::new();
+        let mut sensor = MockSensor::default();
+        calibration.set_tolerance(10.0);
+        calibration.set_timeout(Duration::from_secs(60));
+        calibration.start(at(0));
+
+        calibration.update(&mut sensor, 420.0, at(0));
+        calibration.cancel();
+        assert_eq!(calibration.state(at(1)), State::Idle);
+        assert_eq!(calibration.update(&mut sensor, 420.0, at(400)), None);
+
+        // the timeout counts from the restart
+        calibration.start(at(1000));
+        assert_eq!(calibration.update(&mut sensor, 420.0, at(1000)), None);
+        assert_eq!(calibration.update(&mut sensor, 431.0, at(1059)), None);
+        assert_eq!(
+            calibration.update(&mut sensor, 420.0, at(1060)),
+            Some(Err(Error::Timeout))
+        );
+    }
+}
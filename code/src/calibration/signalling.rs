@@ -0,0 +1,46 @@
+// The state of the calibration on the LED and the buzzer, only on the board.
+
+use embedded_hal::blocking::delay::DelayMs;
+
+use nrf52840_hal::{pac::TIMER0, timer::OneShot, Timer};
+
+use super::State;
+use crate::{buzzer::Buzzer, clock::Instant, rgb_led::LEDColor, Error};
+
+// half a period of the "calibrating" animation
+const BLINK_INTERVAL_MS: u64 = 500;
+
+/// Shows the state on the LED: blinking blue while calibrating, green on success, red on failure.
+/// Returns `false` if there is nothing to show, then the LED is left as it is.
+pub fn show_state(state: State, now: Instant, led: &mut LEDColor) -> Result<bool, Error> {
+    match state {
+        State::Idle => return Ok(false),
+        State::Stabilizing => {
+            if (now.as_millis() / BLINK_INTERVAL_MS).is_multiple_of(2) {
+                led.blue()?;
+            } else {
+                led.off()?;
+            }
+        }
+        State::Succeeded => led.green()?,
+        State::Failed(_) => led.red()?,
+    }
+    Ok(true)
+}
+
+/// Confirms the result with the buzzer: two short high beeps on success, one long low tone on
+/// failure. Whole millisecond delays can't play 880 Hz, so the tones are timed in microseconds.
+pub fn sound_result(
+    result: &Result<(), Error>,
+    buzzer: &mut Buzzer,
+    timer: &mut Timer<TIMER0, OneShot>,
+) -> Result<(), Error> {
+    match result {
+        Ok(()) => {
+            buzzer.tone(timer, 880_u32, 150_u32)?;
+            timer.delay_ms(100_u32);
+            buzzer.tone(timer, 880_u32, 150_u32)
+        }
+        Err(_) => buzzer.tone(timer, 220_u32, 1000_u32),
+    }
+}
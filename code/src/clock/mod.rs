@@ -0,0 +1,114 @@
+// Monotonic clock based on the RTC1 peripheral. RTC0 is left alone, as Bluetooth stacks reserve it.
+//
+// The RTC counts the 32.768 kHz low frequency clock in a 24 bit counter, which overflows every
+// 512 seconds. Every overflow raises an interrupt that counts the overflows, together they form
+// a 56 bit tick count that doesn't wrap within the lifetime of the device.
+// Unlike the TIMER peripherals, the RTC keeps counting while the CPU sleeps.
+//
+// `Instant` and `Duration` are plain numbers, the RTC in `rtc` and `CycleDelay` only exist on
+// the board.
+
+use core::ops::{Add, Sub};
+
+#[cfg(target_os = "none")]
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+#[cfg(target_os = "none")]
+mod rtc;
+#[cfg(target_os = "none")]
+pub use rtc::{delay, init, is_running, now, now_ms, sleep_until};
+
+// the CPU runs from the 64 MHz high frequency clock
+#[cfg(target_os = "none")]
+const CPU_CYCLES_PER_US: u32 = 64;
+
+/// Point in time, in milliseconds since the clock was started
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub struct Instant(u64);
+
+/// Span of time in milliseconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub struct Duration(u64);
+
+impl Instant {
+    pub fn from_millis(millis: u64) -> Self {
+        Instant(millis)
+    }
+
+    pub fn as_millis(&self) -> u64 {
+        self.0
+    }
+
+    /// Time passed since `earlier`, zero if `earlier` is later than `self`
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration(self.0.saturating_sub(earlier.0))
+    }
+
+    /// Time passed since this instant
+    #[cfg(target_os = "none")]
+    pub fn elapsed(&self) -> Duration {
+        now().duration_since(*self)
+    }
+}
+
+impl Duration {
+    pub const fn from_millis(millis: u64) -> Self {
+        Duration(millis)
+    }
+
+    pub const fn from_secs(secs: u64) -> Self {
+        Duration(secs * 1000)
+    }
+
+    pub fn as_millis(&self) -> u64 {
+        self.0
+    }
+
+    pub fn as_secs_f32(&self) -> f32 {
+        self.0 as f32 / 1000.0
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant(self.0 + rhs.0)
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, rhs: Duration) -> Duration {
+        Duration(self.0 + rhs.0)
+    }
+}
+
+/// Delay provider that counts CPU cycles. It works without the clock running, and without
+/// taking a timer, but it waits longer if interrupts happen in between.
+pub struct CycleDelay;
+
+#[cfg(target_os = "none")]
+impl DelayUs<u32> for CycleDelay {
+    fn delay_us(&mut self, us: u32) {
+        cortex_m::asm::delay(us.saturating_mul(CPU_CYCLES_PER_US));
+    }
+}
+
+#[cfg(target_os = "none")]
+impl DelayMs<u32> for CycleDelay {
+    fn delay_ms(&mut self, ms: u32) {
+        for _ in 0..ms {
+            self.delay_us(1000);
+        }
+    }
+}
@@ -0,0 +1,124 @@
+// The RTC1 that drives the clock, only on the board.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use nrf52840_hal::{
+    clocks::{Clocks, LfOscStarted},
+    pac::{self, interrupt, RTC1},
+};
+
+use super::{Duration, Instant};
+
+const TICKS_PER_SECOND: u64 = 32_768;
+const COUNTER_BITS: u32 = 24;
+const COUNTER_MASK: u64 = (1 << COUNTER_BITS) - 1;
+// a compare value closer than this to the counter may not trigger
+const MIN_COMPARE_TICKS: u64 = 3;
+// stay well within one counter period, so the compare value is never ambiguous
+const MAX_SLEEP_TICKS: u64 = 1 << (COUNTER_BITS - 1);
+
+static OVERFLOWS: AtomicU32 = AtomicU32::new(0);
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Starts the clock. The low frequency clock has to be running, `Board::take()` takes care of that.
+pub fn init<H, L>(rtc: RTC1, _lfclk: &Clocks<H, L, LfOscStarted>) {
+    rtc.tasks_stop.write(|w| unsafe { w.bits(1) });
+    rtc.tasks_clear.write(|w| unsafe { w.bits(1) });
+    // count at the full 32.768 kHz
+    rtc.prescaler.write(|w| unsafe { w.prescaler().bits(0) });
+    OVERFLOWS.store(0, Ordering::Relaxed);
+
+    rtc.events_ovrflw.write(|w| unsafe { w.bits(0) });
+    rtc.evtenset.write(|w| w.ovrflw().set());
+    rtc.intenset.write(|w| w.ovrflw().set());
+    unsafe { pac::NVIC::unmask(pac::Interrupt::RTC1) };
+
+    rtc.tasks_start.write(|w| unsafe { w.bits(1) });
+    RUNNING.store(true, Ordering::Relaxed);
+    // from now on the registers are only accessed through `RTC1::ptr()`
+}
+
+/// Whether `init` has been called, e.g. the early examples don't start the clock
+pub fn is_running() -> bool {
+    RUNNING.load(Ordering::Relaxed)
+}
+
+/// Ticks of the 32.768 kHz clock since the clock was started
+fn ticks() -> u64 {
+    let rtc = unsafe { &*RTC1::ptr() };
+
+    cortex_m::interrupt::free(|_| {
+        let mut overflows = OVERFLOWS.load(Ordering::Relaxed);
+        let mut counter = rtc.counter.read().bits();
+
+        // The counter may have overflowed while interrupts are disabled, e.g. while defmt
+        // takes a timestamp. The interrupt has not counted that overflow yet, so do it here.
+        if rtc.events_ovrflw.read().bits() != 0 {
+            overflows += 1;
+            counter = rtc.counter.read().bits();
+        }
+
+        (u64::from(overflows) << COUNTER_BITS) | u64::from(counter)
+    })
+}
+
+/// Current time. Returns the zero instant until the clock is started.
+pub fn now() -> Instant {
+    Instant::from_millis(ticks() * 1000 / TICKS_PER_SECOND)
+}
+
+/// Milliseconds since the clock was started
+pub fn now_ms() -> u64 {
+    now().as_millis()
+}
+
+/// Busy waits for the given duration
+pub fn delay(duration: Duration) {
+    let deadline = now() + duration;
+    while now() < deadline {}
+}
+
+/// Puts the CPU to sleep until `deadline`.
+/// Other interrupts wake the CPU too, but this only returns once the deadline has passed.
+pub fn sleep_until(deadline: Instant) {
+    let rtc = unsafe { &*RTC1::ptr() };
+    // round up, so we never wake up before the deadline
+    let target = (deadline.as_millis() * TICKS_PER_SECOND).div_ceil(1000);
+
+    loop {
+        let now = ticks();
+        if now >= target {
+            return;
+        }
+
+        let remaining = target - now;
+        if remaining < MIN_COMPARE_TICKS {
+            // too close to program the compare register, the last few ticks are spent spinning
+            continue;
+        }
+
+        let wake_up = now + remaining.min(MAX_SLEEP_TICKS);
+        rtc.events_compare[0].write(|w| unsafe { w.bits(0) });
+        rtc.cc[0].write(|w| unsafe { w.bits((wake_up & COUNTER_MASK) as u32) });
+        rtc.intenset.write(|w| w.compare0().set());
+
+        // returns on the compare interrupt, any other interrupt or a pending event
+        cortex_m::asm::wfe();
+    }
+}
+
+#[interrupt]
+fn RTC1() {
+    let rtc = unsafe { &*RTC1::ptr() };
+
+    if rtc.events_ovrflw.read().bits() != 0 {
+        rtc.events_ovrflw.write(|w| unsafe { w.bits(0) });
+        OVERFLOWS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // compare 0 is only used to wake up from `sleep_until`
+    if rtc.events_compare[0].read().bits() != 0 {
+        rtc.events_compare[0].write(|w| unsafe { w.bits(0) });
+        rtc.intenclr.write(|w| w.compare0().clear());
+    }
+}
@@ -0,0 +1,213 @@
+// Interface shared by the CO2 sensor drivers.
+//
+// The SCD30 and the SCD4x family measure the same quantities, but with different command sets.
+// Code that only starts the sensor, reads it and calibrates it is written against `Co2Sensor`,
+// and works with either driver.
+
+#[cfg(target_os = "none")]
+use crate::clock;
+#[cfg(any(test, target_os = "none"))]
+use crate::clock::{Duration, Instant};
+use crate::Error;
+
+// interval between two data ready requests in `wait_for_data`
+#[cfg(any(test, target_os = "none"))]
+const DATA_READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy)]
+pub struct SensorData {
+    /// in ppm
+    pub co2: f32,
+    /// in °C
+    pub temperature: f32,
+    /// relative humidity in %
+    pub humidity: f32,
+}
+
+/// Reported by `Co2Sensor::poll_event()` when a driver recovered from repeated bus failures
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum Event {
+    /// the bus was blocked or the sensor stopped answering; the bus has been freed and the
+    /// sensor reset
+    BusRecovered,
+    /// the measurement has been restarted with the last configured pressure
+    MeasurementRestarted,
+}
+
+pub trait Co2Sensor {
+    /// Starts measuring periodically. If the sensor is measuring already, only the pressure is
+    /// updated. `pressure` is the ambient pressure in mbar the sensor compensates for, with 0
+    /// it compensates for the altitude instead.
+    fn start(&mut self, pressure: u16) -> Result<(), Error>;
+
+    /// Height above sea level in m, used while no ambient pressure is set
+    fn set_altitude(&mut self, altitude: u16) -> Result<(), Error>;
+
+    /// Whether a new measurement can be read
+    fn data_ready(&mut self) -> Result<bool, Error>;
+
+    fn read(&mut self) -> Result<SensorData, Error>;
+
+    /// Forced recalibration: tells the sensor that the air it measures right now has a CO2
+    /// concentration of `reference` ppm, e.g. 420 ppm for fresh outdoor air.
+    /// The sensor has to be measuring for a few minutes in stable air before.
+    fn calibrate(&mut self, reference: u16) -> Result<(), Error>;
+
+    /// Firmware version of the sensor as major and minor number, `None` if it doesn't report one
+    fn firmware_version(&mut self) -> Result<Option<[u8; 2]>, Error> {
+        Ok(None)
+    }
+
+    /// Returns what the driver did to recover from bus failures since the last call, if anything
+    fn poll_event(&mut self) -> Option<Event> {
+        None
+    }
+
+    /// Waits until the sensor has a new measurement, sleeping between requests. Transfers that
+    /// fail are repeated until the deadline as well.
+    /// Returns `Error::Timeout` if the sensor answered but had no data before `timeout` passed,
+    /// and `Error::NotResponding` if it never answered. Requires a running `clock`.
+    #[cfg(target_os = "none")]
+    fn wait_for_data(&mut self, timeout: Duration) -> Result<(), Error> {
+        poll_until(self, clock::now() + timeout, clock::now, clock::sleep_until)
+    }
+}
+
+// `wait_for_data` with the clock passed in
+#[cfg(any(test, target_os = "none"))]
+fn poll_until<S: Co2Sensor + ?Sized>(
+    sensor: &mut S,
+    deadline: Instant,
+    mut now: impl FnMut() -> Instant,
+    mut sleep_until: impl FnMut(Instant),
+) -> Result<(), Error> {
+    let mut responded = false;
+
+    loop {
+        match sensor.data_ready() {
+            Ok(true) => return Ok(()),
+            // a CRC error also means that the sensor is there
+            Ok(false) | Err(Error::Crc) => responded = true,
+            // the transfer failed, over I2C or over Modbus
+            Err(Error::Bus(_) | Error::NotResponding | Error::Serial) => {}
+            Err(error) => return Err(error),
+        }
+
+        let now = now();
+        if now >= deadline {
+            return Err(if responded {
+                Error::Timeout
+            } else {
+                Error::NotResponding
+            });
+        }
+        sleep_until(deadline.min(now + DATA_READY_POLL_INTERVAL));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::error::BusError;
+
+    // answers data ready requests from a script, and fails like an unplugged sensor after it
+    struct ScriptedSensor {
+        answers: VecDeque<Result<bool, Error>>,
+        requests: usize,
+    }
+
+    impl ScriptedSensor {
+        fn new(answers: &[Result<bool, Error>]) -> Self {
+            ScriptedSensor {
+                answers: answers.iter().copied().collect(),
+                requests: 0,
+            }
+        }
+    }
+
+    impl Co2Sensor for ScriptedSensor {
+        fn start(&mut self, _: u16) -> Result<(), Error> {
+            unimplemented!()
+        }
+
+        fn set_altitude(&mut self, _: u16) -> Result<(), Error> {
+            unimplemented!()
+        }
+
+        fn data_ready(&mut self) -> Result<bool, Error> {
+            self.requests += 1;
+            self.answers
+                .pop_front()
+                .unwrap_or(Err(Error::NotResponding))
+        }
+
+        fn read(&mut self) -> Result<SensorData, Error> {
+            unimplemented!()
+        }
+
+        fn calibrate(&mut self, _: u16) -> Result<(), Error> {
+            unimplemented!()
+        }
+    }
+
+    // polls with a clock that only advances while sleeping, for at most 1 s
+    fn poll(sensor: &mut ScriptedSensor) -> Result<(), Error> {
+        let time = Cell::new(Instant::from_millis(0));
+        poll_until(
+            sensor,
+            Instant::from_millis(1000),
+            || time.get(),
+            |until| time.set(until),
+        )
+    }
+
+    #[test]
+    fn data_ready() {
+        let mut sensor = ScriptedSensor::new(&[Ok(false), Ok(false), Ok(true)]);
+        assert_eq!(poll(&mut sensor), Ok(()));
+        assert_eq!(sensor.requests, 3);
+    }
+
+    #[test]
+    fn transport_fails_then_answers() {
+        for error in [
+            Error::Bus(BusError::AddressNack),
+            Error::NotResponding,
+            Error::Serial,
+            Error::Crc,
+        ]
+        .iter()
+        {
+            let mut sensor = ScriptedSensor::new(&[Err(*error), Err(*error), Ok(true)]);
+            assert_eq!(poll(&mut sensor), Ok(()), "{:?}", error);
+            assert_eq!(sensor.requests, 3);
+        }
+    }
+
+    #[test]
+    fn not_responding() {
+        let mut sensor = ScriptedSensor::new(&[Err(Error::Serial)]);
+        assert_eq!(poll(&mut sensor), Err(Error::NotResponding));
+        // every 100 ms, at 0 ms and at the deadline
+        assert_eq!(sensor.requests, 11);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut sensor = ScriptedSensor::new(&[Ok(false)]);
+        assert_eq!(poll(&mut sensor), Err(Error::Timeout));
+
+        let mut sensor = ScriptedSensor::new(&[Err(Error::Crc)]);
+        assert_eq!(poll(&mut sensor), Err(Error::Timeout));
+    }
+
+    #[test]
+    fn other_errors_end_the_wait() {
+        let mut sensor = ScriptedSensor::new(&[Ok(false), Err(Error::InvalidArgument)]);
+        assert_eq!(poll(&mut sensor), Err(Error::InvalidArgument));
+        assert_eq!(sensor.requests, 2);
+    }
+}
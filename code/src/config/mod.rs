@@ -0,0 +1,101 @@
+// Settings that can be changed at runtime and survive a reset, stored in the flash.
+//
+// `Settings` are plain values, `store` keeps them in the flash of the board in the format of
+// `record`.
+
+use core::ops::RangeInclusive;
+
+use crate::{
+    alerts::{Classifier, Standard, DEFAULT_OUTDOOR_CO2},
+    calibration::DEFAULT_REFERENCE_PPM,
+    number_representation::Unit,
+    rgb_led::Polarity,
+    Error,
+};
+
+#[cfg(any(test, target_os = "none"))]
+mod record;
+#[cfg(target_os = "none")]
+mod store;
+#[cfg(target_os = "none")]
+pub use store::ConfigStore;
+
+/// Measurement intervals in s the settings accept. The longest one still fits the 32 bit timers
+/// counting microseconds.
+pub const MEASUREMENT_INTERVAL_S: RangeInclusive<u16> = 1..=3600;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Settings {
+    pub standard: Standard,
+    /// upper limits of the categories I, II and III in ppm above outdoor air,
+    /// `None` uses the limits of the standard
+    pub limits: Option<[u16; 3]>,
+    /// CO2 concentration of the outdoor air in ppm
+    pub outdoor_co2: u16,
+    /// ambient pressure in mbar, 0 to measure it or to compensate for the altitude instead
+    pub pressure: u16,
+    /// height above sea level in m
+    pub altitude: u16,
+    pub unit: Unit,
+    pub led_polarity: Polarity,
+    /// CO2 concentration in ppm the forced recalibration sets
+    pub calibration_reference: u16,
+    /// time between two measurements in s
+    pub measurement_interval: u16,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            standard: Standard::En16798,
+            limits: None,
+            outdoor_co2: DEFAULT_OUTDOOR_CO2 as u16,
+            pressure: 0,
+            altitude: 0,
+            unit: Unit::Celsius,
+            led_polarity: Polarity::CommonAnode,
+            calibration_reference: DEFAULT_REFERENCE_PPM,
+            measurement_interval: 2,
+        }
+    }
+}
+
+impl Settings {
+    /// Classifier configured with the standard, the limits and the outdoor CO2 concentration
+    pub fn classifier(&self) -> Classifier {
+        let mut classifier = Classifier::new(self.standard);
+        classifier.set_outdoor_co2(f32::from(self.outdoor_co2));
+        classifier.set_limits(
+            self.limits
+                .map(|limits| [limits[0].into(), limits[1].into(), limits[2].into()]),
+        );
+        classifier
+    }
+
+    /// Upper limits of the categories I, II and III as absolute CO2 concentrations in ppm
+    pub fn thresholds(&self) -> [u16; 3] {
+        let limits = self.classifier().limits();
+        let outdoor = f32::from(self.outdoor_co2);
+        [
+            (limits[0] + outdoor) as u16,
+            (limits[1] + outdoor) as u16,
+            (limits[2] + outdoor) as u16,
+        ]
+    }
+
+    /// Sets the limits from absolute CO2 concentrations in ppm. They have to be above the outdoor
+    /// air and in ascending order, otherwise `Error::InvalidArgument` is returned.
+    pub fn set_thresholds(&mut self, thresholds: [u16; 3]) -> Result<(), Error> {
+        let outdoor = self.outdoor_co2;
+        if thresholds[0] < outdoor || thresholds[0] > thresholds[1] || thresholds[1] > thresholds[2]
+        {
+            return Err(Error::InvalidArgument);
+        }
+        self.limits = Some([
+            thresholds[0] - outdoor,
+            thresholds[1] - outdoor,
+            thresholds[2] - outdoor,
+        ]);
+        Ok(())
+    }
+}
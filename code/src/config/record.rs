@@ -0,0 +1,310 @@
+// Format of a record of the settings, as `store` writes it into a slot of the flash.
+//
+// Slot layout, little endian:
+// | magic u32 | sequence u32 | schema version u16 | payload length u16 | payload | CRC-32 |
+//
+// Schema changes: new fields are appended to the payload and `SCHEMA_VERSION` is increased.
+// Records of an older version lack the new fields, which then get their defaults. Records of a
+// newer version than the firmware knows are ignored.
+
+use crc_all::Crc;
+
+use super::{Settings, MEASUREMENT_INTERVAL_S};
+use crate::{alerts::Standard, number_representation::Unit, rgb_led::Polarity};
+
+const SCHEMA_VERSION: u16 = 1;
+
+const MAGIC: u32 = 0x4332_4f43; // "CO2C"
+pub(super) const SLOT_SIZE: usize = 64;
+// magic, sequence, version and length
+const HEADER_SIZE: usize = 12;
+const CRC_SIZE: usize = 4;
+const MAX_PAYLOAD_SIZE: usize = SLOT_SIZE - HEADER_SIZE - CRC_SIZE;
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc::<u32>::new(0x04c1_1db7, 32, 0xffff_ffff, 0xffff_ffff, true);
+    crc.update(data);
+    crc.finish()
+}
+
+pub(super) fn encode(sequence: u32, settings: &Settings) -> [u32; SLOT_SIZE / 4] {
+    let mut payload = Writer::default();
+    payload.u8(match settings.standard {
+        Standard::En16798 => 0,
+        Standard::En13779 => 1,
+    });
+    payload.u8(settings.limits.is_some() as u8);
+    for limit in settings.limits.unwrap_or_default().iter() {
+        payload.u16(*limit);
+    }
+    payload.u16(settings.outdoor_co2);
+    payload.u16(settings.pressure);
+    payload.u16(settings.altitude);
+    payload.u8(match settings.unit {
+        Unit::Celsius => 0,
+        Unit::Fahrenheit => 1,
+        Unit::Kelvin => 2,
+    });
+    payload.u8(match settings.led_polarity {
+        Polarity::CommonAnode => 0,
+        Polarity::CommonCathode => 1,
+    });
+    payload.u16(settings.calibration_reference);
+    payload.u16(settings.measurement_interval);
+
+    let mut record = [0xffu8; SLOT_SIZE];
+    record[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    record[4..8].copy_from_slice(&sequence.to_le_bytes());
+    record[8..10].copy_from_slice(&SCHEMA_VERSION.to_le_bytes());
+    record[10..12].copy_from_slice(&(payload.length as u16).to_le_bytes());
+    record[HEADER_SIZE..HEADER_SIZE + MAX_PAYLOAD_SIZE].copy_from_slice(&payload.buffer);
+    let crc = crc32(&record[4..SLOT_SIZE - CRC_SIZE]);
+    record[SLOT_SIZE - CRC_SIZE..].copy_from_slice(&crc.to_le_bytes());
+
+    let mut words = [0u32; SLOT_SIZE / 4];
+    for (word, bytes) in words.iter_mut().zip(record.chunks(4)) {
+        *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    words
+}
+
+// returns the sequence number and the settings, `None` if the record isn't valid
+pub(super) fn decode(record: &[u8; SLOT_SIZE]) -> Option<(u32, Settings)> {
+    let word = |index: usize| {
+        u32::from_le_bytes([
+            record[index],
+            record[index + 1],
+            record[index + 2],
+            record[index + 3],
+        ])
+    };
+    if word(0) != MAGIC || word(SLOT_SIZE - CRC_SIZE) != crc32(&record[4..SLOT_SIZE - CRC_SIZE]) {
+        return None;
+    }
+
+    let sequence = word(4);
+    let version = u16::from_le_bytes([record[8], record[9]]);
+    let length = usize::from(u16::from_le_bytes([record[10], record[11]]));
+    if version > SCHEMA_VERSION || length > MAX_PAYLOAD_SIZE {
+        defmt::warn!(
+            "Ignoring settings of unknown schema version {=u16}",
+            version
+        );
+        return None;
+    }
+    if version < SCHEMA_VERSION {
+        defmt::info!("Migrating settings from schema version {=u16}", version);
+    }
+
+    // fields missing in older versions keep their defaults
+    let mut payload = Reader {
+        buffer: &record[HEADER_SIZE..HEADER_SIZE + length],
+    };
+    let mut settings = Settings::default();
+    if let Some(standard) = payload.u8() {
+        settings.standard = match standard {
+            1 => Standard::En13779,
+            _ => Standard::En16798,
+        };
+    }
+    if let (Some(has_limits), Some(limit_1), Some(limit_2), Some(limit_3)) =
+        (payload.u8(), payload.u16(), payload.u16(), payload.u16())
+    {
+        settings.limits = if has_limits == 1 {
+            Some([limit_1, limit_2, limit_3])
+        } else {
+            None
+        };
+    }
+    if let Some(outdoor_co2) = payload.u16() {
+        settings.outdoor_co2 = outdoor_co2;
+    }
+    if let Some(pressure) = payload.u16() {
+        settings.pressure = pressure;
+    }
+    if let Some(altitude) = payload.u16() {
+        settings.altitude = altitude;
+    }
+    if let Some(unit) = payload.u8() {
+        settings.unit = match unit {
+            1 => Unit::Fahrenheit,
+            2 => Unit::Kelvin,
+            _ => Unit::Celsius,
+        };
+    }
+    if let Some(polarity) = payload.u8() {
+        settings.led_polarity = match polarity {
+            1 => Polarity::CommonCathode,
+            _ => Polarity::CommonAnode,
+        };
+    }
+    if let Some(calibration_reference) = payload.u16() {
+        settings.calibration_reference = calibration_reference;
+    }
+    // written by a firmware that didn't check the interval yet
+    if let Some(measurement_interval) = payload.u16() {
+        if MEASUREMENT_INTERVAL_S.contains(&measurement_interval) {
+            settings.measurement_interval = measurement_interval;
+        }
+    }
+
+    Some((sequence, settings))
+}
+
+struct Writer {
+    buffer: [u8; MAX_PAYLOAD_SIZE],
+    length: usize,
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        Writer {
+            buffer: [0xff; MAX_PAYLOAD_SIZE],
+            length: 0,
+        }
+    }
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.buffer[self.length] = value;
+        self.length += 1;
+    }
+
+    fn u16(&mut self, value: u16) {
+        for byte in value.to_le_bytes().iter() {
+            self.u8(*byte);
+        }
+    }
+}
+
+struct Reader<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Option<u8> {
+        let (value, rest) = self.buffer.split_first()?;
+        self.buffer = rest;
+        Some(*value)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes([self.u8()?, self.u8()?]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // payload of the current version
+    const PAYLOAD_SIZE: usize = 20;
+
+    fn custom() -> Settings {
+        Settings {
+            standard: Standard::En13779,
+            limits: Some([400, 700, 1100]),
+            outdoor_co2: 420,
+            pressure: 1013,
+            altitude: 520,
+            unit: Unit::Kelvin,
+            led_polarity: Polarity::CommonCathode,
+            calibration_reference: 450,
+            measurement_interval: 60,
+        }
+    }
+
+    fn as_bytes(words: [u32; SLOT_SIZE / 4]) -> [u8; SLOT_SIZE] {
+        let mut bytes = [0u8; SLOT_SIZE];
+        for (chunk, word) in bytes.chunks_mut(4).zip(words.iter()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    // a record with a valid CRC, as another firmware would have written it
+    fn written_by(version: u16, payload: &[u8]) -> [u8; SLOT_SIZE] {
+        let mut record = [0xffu8; SLOT_SIZE];
+        record[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        record[4..8].copy_from_slice(&3u32.to_le_bytes());
+        record[8..10].copy_from_slice(&version.to_le_bytes());
+        record[10..12].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        record[HEADER_SIZE..HEADER_SIZE + payload.len()].copy_from_slice(payload);
+        let crc = crc32(&record[4..SLOT_SIZE - CRC_SIZE]);
+        record[SLOT_SIZE - CRC_SIZE..].copy_from_slice(&crc.to_le_bytes());
+        record
+    }
+
+    #[test]
+    fn round_trip() {
+        let defaults = Settings::default();
+        assert_eq!(decode(&as_bytes(encode(1, &defaults))), Some((1, defaults)));
+        assert_eq!(
+            decode(&as_bytes(encode(0xffff_fffe, &custom()))),
+            Some((0xffff_fffe, custom()))
+        );
+    }
+
+    #[test]
+    fn layout() {
+        let record = as_bytes(encode(7, &custom()));
+
+        assert_eq!(record[0..4], *b"CO2C");
+        assert_eq!(record[4..8], [7, 0, 0, 0]);
+        assert_eq!(record[8..10], SCHEMA_VERSION.to_le_bytes());
+        // standard, limits, outdoor CO2, pressure, altitude, unit, polarity, reference, interval
+        assert_eq!(record[10..12], [PAYLOAD_SIZE as u8, 0]);
+        assert_eq!(record[HEADER_SIZE..HEADER_SIZE + 4], [1, 1, 0x90, 0x01]);
+    }
+
+    #[test]
+    fn corrupted() {
+        let record = as_bytes(encode(7, &custom()));
+        for index in 0..SLOT_SIZE {
+            let mut damaged = record;
+            damaged[index] ^= 0x10;
+            assert_eq!(decode(&damaged), None, "byte {}", index);
+        }
+    }
+
+    #[test]
+    fn erased() {
+        assert_eq!(decode(&[0xff; SLOT_SIZE]), None);
+    }
+
+    #[test]
+    fn migrates_older_version() {
+        // version 0 had neither the calibration reference nor the measurement interval
+        let payload = [
+            1, 1, 0x90, 0x01, 0xbc, 0x02, 0x4c, 0x04, 0xa4, 0x01, 0xf5, 0x03, 0x08, 0x02, 2, 1,
+        ];
+        let expected = Settings {
+            calibration_reference: Settings::default().calibration_reference,
+            measurement_interval: Settings::default().measurement_interval,
+            ..custom()
+        };
+
+        assert_eq!(decode(&written_by(0, &payload)), Some((3, expected)));
+    }
+
+    #[test]
+    fn ignores_newer_version() {
+        // with a field appended
+        let mut payload = [0x42u8; PAYLOAD_SIZE + 1];
+        payload[..PAYLOAD_SIZE]
+            .copy_from_slice(&as_bytes(encode(3, &custom()))[HEADER_SIZE..][..PAYLOAD_SIZE]);
+
+        assert_eq!(decode(&written_by(SCHEMA_VERSION + 1, &payload)), None);
+    }
+
+    #[test]
+    fn interval_out_of_range() {
+        let mut payload = [0u8; PAYLOAD_SIZE];
+        payload.copy_from_slice(&as_bytes(encode(3, &custom()))[HEADER_SIZE..][..PAYLOAD_SIZE]);
+        payload[PAYLOAD_SIZE - 2..].copy_from_slice(&0u16.to_le_bytes());
+
+        let (_, settings) = decode(&written_by(SCHEMA_VERSION, &payload)).unwrap();
+        assert_eq!(settings.measurement_interval, 2);
+    }
+}
@@ -0,0 +1,108 @@
+// Storage of the settings in the flash, only on the board.
+//
+// Every save writes a new record into the next free slot of one of the two `CONFIG_PAGES`,
+// instead of erasing and rewriting the same page. When a page is full, the other one is erased
+// and used. A page is only erased every 64 saves, and both pages wear the same. On boot, the
+// valid record with the highest sequence number wins; a record damaged by a reset during a write
+// fails its CRC and is ignored, so the previous settings are loaded instead.
+//
+// The format of a record is in `record`.
+
+use super::{
+    record::{decode, encode, SLOT_SIZE},
+    Settings,
+};
+use crate::{
+    flash::{Flash, CONFIG_PAGES, PAGE_SIZE},
+    Error,
+};
+
+const SLOTS_PER_PAGE: u32 = PAGE_SIZE / SLOT_SIZE as u32;
+
+/// Where the next record goes, and the sequence number of the newest one
+pub struct ConfigStore {
+    page: usize,
+    slot: u32,
+    sequence: u32,
+}
+
+impl ConfigStore {
+    /// Finds the newest valid settings, and returns the defaults if there are none, e.g. on the
+    /// first boot.
+    pub fn load(flash: &Flash) -> (Self, Settings) {
+        let mut newest: Option<(u32, usize, Settings)> = None;
+        // first slot of each page that has never been written
+        let mut free_slots = [SLOTS_PER_PAGE; 2];
+
+        for (page, free_slot) in free_slots.iter_mut().enumerate() {
+            for slot in 0..SLOTS_PER_PAGE {
+                let mut record = [0u8; SLOT_SIZE];
+                if flash.read(slot_address(page, slot), &mut record).is_err() {
+                    break;
+                }
+                // slots are written in order, the rest of the page is erased
+                if record.iter().all(|byte| *byte == 0xff) {
+                    *free_slot = slot;
+                    break;
+                }
+                if let Some((sequence, settings)) = decode(&record) {
+                    if newest.is_none_or(|(newest_sequence, _, _)| sequence > newest_sequence) {
+                        newest = Some((sequence, page, settings));
+                    }
+                }
+            }
+        }
+
+        match newest {
+            Some((sequence, page, settings)) => (
+                ConfigStore {
+                    page,
+                    slot: free_slots[page],
+                    sequence,
+                },
+                settings,
+            ),
+            None => {
+                defmt::info!("No settings stored, using the defaults");
+                (
+                    ConfigStore {
+                        page: 0,
+                        slot: free_slots[0],
+                        sequence: 0,
+                    },
+                    Settings::default(),
+                )
+            }
+        }
+    }
+
+    pub fn save(&mut self, flash: &mut Flash, settings: &Settings) -> Result<(), Error> {
+        let sequence = self.sequence.wrapping_add(1);
+        let words = encode(sequence, settings);
+
+        loop {
+            if self.slot >= SLOTS_PER_PAGE {
+                // the newest record stays on the full page until the other one has been written
+                self.page = 1 - self.page;
+                self.slot = 0;
+                flash.erase_page(CONFIG_PAGES[self.page])?;
+            }
+
+            let address = slot_address(self.page, self.slot);
+            self.slot += 1;
+            match flash.write(address, &words) {
+                Ok(()) => {
+                    self.sequence = sequence;
+                    return Ok(());
+                }
+                // a slot damaged by a reset during a write can't be written again, skip it
+                Err(Error::Flash) => defmt::warn!("Skipping damaged settings slot {=u32}", address),
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+fn slot_address(page: usize, slot: u32) -> u32 {
+    CONFIG_PAGES[page] + slot * SLOT_SIZE as u32
+}
@@ -3,10 +3,19 @@ use nrf52840_hal::{
     prelude::InputPin,
 };
 
+use crate::{
+    clock::{Duration, Instant},
+    Error,
+};
+
 // Button struct contains the boolean struct field to keep record of button status
 pub struct Button {
     pin: Pin<Input<PullUp>>,
     was_pressed: bool,
+    // when the button was pressed down, `None` while it is released
+    pressed_since: Option<Instant>,
+    // the current or last press has been reported by `check_long_press`
+    long_pressed: bool,
 }
 
 impl Button {
@@ -14,12 +23,14 @@ impl Button {
         Button {
             pin: pin.into_pullup_input(),
             was_pressed: false,
+            pressed_since: None,
+            long_pressed: false,
         }
     }
 
     /// Button is pressed
-    pub fn is_pressed(&self) -> bool {
-        self.pin.is_low().unwrap()
+    pub fn is_pressed(&self) -> Result<bool, Error> {
+        Ok(self.pin.is_low()?)
     }
 
     /// what state is the button in?
@@ -28,18 +39,44 @@ impl Button {
     // This is a very simple state machine with two states.
     //
     // Note: This function should be called periodically
-    pub fn check_rising_edge(&mut self) -> bool {
+    pub fn check_rising_edge(&mut self) -> Result<bool, Error> {
         let mut rising_edge = false;
 
-        let is_pressed = self.is_pressed();
+        let is_pressed = self.is_pressed()?;
         // Only trigger on "rising edge" of the signal
         // Term: "Edge Triggering"
         if self.was_pressed && !is_pressed {
             // Was pressed, now isn't:
-            rising_edge = true;
+            // letting go after a long press isn't a click
+            rising_edge = !self.long_pressed;
         }
 
         self.was_pressed = is_pressed;
-        rising_edge
+        Ok(rising_edge)
+    }
+
+    /// Returns `true` once the button has been held down for `hold`.
+    /// Call it periodically, with the current time of the `clock`.
+    pub fn check_long_press(&mut self, now: Instant, hold: Duration) -> Result<bool, Error> {
+        if !self.is_pressed()? {
+            self.pressed_since = None;
+            return Ok(false);
+        }
+
+        let pressed_since = match self.pressed_since {
+            Some(pressed_since) => pressed_since,
+            // a new press
+            None => {
+                self.pressed_since = Some(now);
+                self.long_pressed = false;
+                now
+            }
+        };
+
+        if !self.long_pressed && now.duration_since(pressed_since) >= hold {
+            self.long_pressed = true;
+            return Ok(true);
+        }
+        Ok(false)
     }
 }
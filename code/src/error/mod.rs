@@ -0,0 +1,90 @@
+// Error type shared by all drivers of this crate
+
+#[cfg(target_os = "none")]
+use nrf52840_hal::{twim, uarte};
+#[cfg(target_os = "none")]
+use void::Void;
+
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum Error {
+    /// I2C transfer failed
+    Bus(BusError),
+    /// checksum of data received from the sensor doesn't match
+    Crc,
+    /// the sensor responded, but didn't finish in time
+    Timeout,
+    /// the sensor didn't respond at all before the deadline
+    NotResponding,
+    /// argument outside of the range the device accepts
+    InvalidArgument,
+    /// the sensor rejected the calibration, e.g. because it hadn't been measuring long enough
+    CalibrationFailed,
+    /// a device answered at the address, but its chip ID is not one the driver supports
+    UnknownDevice,
+    /// a GPIO pin couldn't be set or read
+    Pin,
+    /// data written to the flash doesn't read back the same
+    Flash,
+    /// UART or USB serial transfer failed
+    Serial,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum BusError {
+    /// no device acknowledged the address, e.g. because the sensor is unplugged
+    AddressNack,
+    /// the device didn't acknowledge a data byte
+    DataNack,
+    /// received data was lost
+    Overrun,
+    /// any other failure of the bus or the peripheral
+    Other,
+}
+
+#[cfg(target_os = "none")]
+impl From<twim::Error> for Error {
+    fn from(error: twim::Error) -> Self {
+        let bus_error = match error {
+            twim::Error::AddressNack => BusError::AddressNack,
+            twim::Error::DataNack => BusError::DataNack,
+            twim::Error::Overrun => BusError::Overrun,
+            twim::Error::TxBufferTooLong
+            | twim::Error::RxBufferTooLong
+            | twim::Error::TxBufferZeroLength
+            | twim::Error::RxBufferZeroLength
+            | twim::Error::Transmit
+            | twim::Error::Receive
+            | twim::Error::DMABufferNotInDataMemory => BusError::Other,
+        };
+        Error::Bus(bus_error)
+    }
+}
+
+#[cfg(target_os = "none")]
+impl From<uarte::Error> for Error {
+    fn from(_: uarte::Error) -> Self {
+        Error::Serial
+    }
+}
+
+// the GPIO pins of the nRF52840 can't fail, but the `embedded-hal` traits allow it
+#[cfg(target_os = "none")]
+impl From<Void> for Error {
+    fn from(void: Void) -> Self {
+        void::unreachable(void)
+    }
+}
+
+/// Converts errors of `embedded-hal-async` I2C implementations, used by the async drivers
+#[cfg(feature = "async")]
+pub fn from_i2c<E: embedded_hal_async::i2c::Error>(error: E) -> Error {
+    use embedded_hal_async::i2c::{ErrorKind, NoAcknowledgeSource};
+
+    let bus_error = match error.kind() {
+        ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address) => BusError::AddressNack,
+        ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data) => BusError::DataNack,
+        ErrorKind::Overrun => BusError::Overrun,
+        _ => BusError::Other,
+    };
+    Error::Bus(bus_error)
+}
@@ -0,0 +1,143 @@
+// Access to the internal flash through the NVMC, for data that has to survive a reset.
+//
+// The last pages of the flash are reserved for data, the application has to end below
+// `RESERVED_START`. `memory.x` leaves them out of the flash the linker uses; `Flash::new` checks
+// that the two agree.
+//
+// Flash is erased in pages, which sets all bits to 1. Writing can only clear bits, in words of
+// 32 bits. While the NVMC erases or writes, the CPU is halted and no interrupt is served. An
+// erase takes up to 85 ms, far longer than e.g. an NFC reader waits for an answer, so a page is
+// erased in partial erases of 1 ms instead; pending interrupts are served between them.
+
+use core::ops::Range;
+
+use nrf52840_hal::pac::NVMC;
+
+use crate::Error;
+
+pub const PAGE_SIZE: u32 = 4096;
+// longest time the NVMC needs to erase a page
+const ERASE_TIME_MS: u8 = 85;
+// time the CPU is halted by one partial erase
+const PARTIAL_ERASE_MS: u8 = 1;
+// the nRF52840 has 1 MB of flash
+const FLASH_END: u32 = 0x10_0000;
+
+/// the two pages of `config`, used in turns
+pub const CONFIG_PAGES: [u32; 2] = [FLASH_END - 2 * PAGE_SIZE, FLASH_END - PAGE_SIZE];
+/// the ring buffer of `logger`, 128 kB below the `config` pages
+pub const LOG_PAGES: Range<u32> = CONFIG_PAGES[0] - 32 * PAGE_SIZE..CONFIG_PAGES[0];
+/// everything from here to the end of the flash is data, the end of `FLASH` in `memory.x`
+pub const RESERVED_START: u32 = LOG_PAGES.start;
+
+// symbols of the cortex-m-rt linker script: `.data` is stored in flash at `__sidata`,
+// and is the last section of the application image
+extern "C" {
+    static __sidata: u32;
+    static __sdata: u32;
+    static __edata: u32;
+}
+
+pub struct Flash {
+    nvmc: NVMC,
+}
+
+impl Flash {
+    pub fn new(nvmc: NVMC) -> Self {
+        let image_end = unsafe {
+            let data_size = &__edata as *const u32 as u32 - &__sdata as *const u32 as u32;
+            &__sidata as *const u32 as u32 + data_size
+        };
+        defmt::assert!(
+            image_end <= RESERVED_START,
+            "the application overlaps the flash reserved for data"
+        );
+
+        Flash { nvmc }
+    }
+
+    pub fn read(&self, address: u32, buffer: &mut [u8]) -> Result<(), Error> {
+        check_range(address..address + buffer.len() as u32)?;
+        let data = unsafe { core::slice::from_raw_parts(address as *const u8, buffer.len()) };
+        buffer.copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn read_word(&self, address: u32) -> Result<u32, Error> {
+        check_range(address..address + 4)?;
+        check_alignment(address, 4)?;
+        Ok(unsafe { core::ptr::read_volatile(address as *const u32) })
+    }
+
+    /// Sets all bytes of the page starting at `address` to 0xff. Interrupts are served at least
+    /// every `PARTIAL_ERASE_MS` while the page is erased.
+    pub fn erase_page(&mut self, address: u32) -> Result<(), Error> {
+        check_range(address..address + PAGE_SIZE)?;
+        check_alignment(address, PAGE_SIZE)?;
+
+        self.nvmc
+            .erasepagepartialcfg
+            .write(|w| unsafe { w.duration().bits(PARTIAL_ERASE_MS) });
+        self.nvmc.config.write(|w| w.wen().een());
+        // the partial erases have to add up to the time of a full erase
+        for _ in 0..ERASE_TIME_MS / PARTIAL_ERASE_MS {
+            self.nvmc
+                .erasepagepartial
+                .write(|w| unsafe { w.bits(address) });
+            self.wait_ready();
+        }
+        self.nvmc.config.write(|w| w.wen().ren());
+
+        self.verify(
+            address,
+            core::iter::repeat_n(u32::MAX, (PAGE_SIZE / 4) as usize),
+        )
+    }
+
+    /// Writes the words to erased flash starting at `address`, and reads them back.
+    /// Returns `Error::Flash` if they read back differently, e.g. because the flash wasn't erased.
+    pub fn write(&mut self, address: u32, words: &[u32]) -> Result<(), Error> {
+        check_range(address..address + 4 * words.len() as u32)?;
+        check_alignment(address, 4)?;
+
+        self.nvmc.config.write(|w| w.wen().wen());
+        for (index, word) in words.iter().enumerate() {
+            let word_address = address + 4 * index as u32;
+            unsafe { core::ptr::write_volatile(word_address as *mut u32, *word) };
+            self.wait_ready();
+        }
+        self.nvmc.config.write(|w| w.wen().ren());
+
+        self.verify(address, words.iter().copied())
+    }
+
+    fn wait_ready(&self) {
+        while self.nvmc.ready.read().ready().bit_is_clear() {}
+    }
+
+    fn verify(&self, address: u32, words: impl Iterator<Item = u32>) -> Result<(), Error> {
+        for (index, word) in words.enumerate() {
+            if self.read_word(address + 4 * index as u32)? != word {
+                return Err(Error::Flash);
+            }
+        }
+        Ok(())
+    }
+}
+
+// everything outside of the reserved pages belongs to the application
+fn check_range(range: Range<u32>) -> Result<(), Error> {
+    if range.start >= RESERVED_START && range.end <= FLASH_END {
+        Ok(())
+    } else {
+        Err(Error::InvalidArgument)
+    }
+}
+
+fn check_alignment(address: u32, alignment: u32) -> Result<(), Error> {
+    if address.is_multiple_of(alignment) {
+        Ok(())
+    } else {
+        Err(Error::InvalidArgument)
+    }
+}
@@ -0,0 +1,300 @@
+// Binary encoding of a measurement, for analysis on a host.
+//
+// Frame layout, 17 bytes, little endian:
+// | sync 0xa5 0x5a | version u8 | payload length u8 | payload | CRC-16 u16 |
+//
+// Payload of version 1:
+// | uptime s u32 | CO2 ppm u16 | temperature 0.01 °C i16 | humidity 0.01 % u16 | alert level u8 |
+//
+// The CRC-16/CCITT-FALSE covers version, length and payload. A reader that lost track of the
+// stream searches for the next sync marker. Frames of a newer version can be skipped by their
+// length; new fields are appended to the payload.
+
+use crc_all::Crc;
+
+use crate::number_representation::round;
+
+pub const VERSION: u8 = 1;
+pub const SYNC: [u8; 2] = [0xa5, 0x5a];
+
+const HEADER_SIZE: usize = 4;
+const PAYLOAD_SIZE: usize = 11;
+const CRC_SIZE: usize = 2;
+pub const FRAME_SIZE: usize = HEADER_SIZE + PAYLOAD_SIZE + CRC_SIZE;
+
+/// alert level of a measurement that wasn't classified
+pub const NO_ALERT_LEVEL: u8 = 0xff;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameError {
+    /// the buffer ends before the frame does
+    Truncated,
+    /// no sync marker at the start of the frame
+    NoSync,
+    Crc,
+    /// the frame was written by a newer firmware
+    UnsupportedVersion(u8),
+}
+
+/// A measurement in fixed point
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    /// seconds since the device was started
+    pub uptime: u32,
+    /// in ppm
+    pub co2: u16,
+    /// in 0.01 °C
+    pub temperature: i16,
+    /// relative humidity in 0.01 %
+    pub humidity: u16,
+    /// index of the air quality category, 0 is the best, `NO_ALERT_LEVEL` if unknown
+    pub alert_level: u8,
+}
+
+impl Frame {
+    pub fn new(uptime: u32, co2: f32, temperature: f32, humidity: f32, alert_level: u8) -> Self {
+        Frame {
+            uptime,
+            co2: round(co2) as u16,
+            temperature: round(temperature * 100.0) as i16,
+            humidity: round(humidity * 100.0) as u16,
+            alert_level,
+        }
+    }
+
+    pub fn co2_ppm(&self) -> f32 {
+        f32::from(self.co2)
+    }
+
+    pub fn temperature_celsius(&self) -> f32 {
+        f32::from(self.temperature) / 100.0
+    }
+
+    pub fn humidity_percent(&self) -> f32 {
+        f32::from(self.humidity) / 100.0
+    }
+
+    pub fn encode(&self) -> [u8; FRAME_SIZE] {
+        let mut bytes = [0u8; FRAME_SIZE];
+        bytes[0..2].copy_from_slice(&SYNC);
+        bytes[2] = VERSION;
+        bytes[3] = PAYLOAD_SIZE as u8;
+        bytes[4..8].copy_from_slice(&self.uptime.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.co2.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.temperature.to_le_bytes());
+        bytes[12..14].copy_from_slice(&self.humidity.to_le_bytes());
+        bytes[14] = self.alert_level;
+        let crc = crc16(&bytes[2..HEADER_SIZE + PAYLOAD_SIZE]);
+        bytes[HEADER_SIZE + PAYLOAD_SIZE..].copy_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    /// Decodes the frame at the start of `bytes`, and returns it with its size in bytes
+    pub fn decode(bytes: &[u8]) -> Result<(Frame, usize), FrameError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(FrameError::Truncated);
+        }
+        if bytes[0..2] != SYNC {
+            return Err(FrameError::NoSync);
+        }
+        let version = bytes[2];
+        let length = usize::from(bytes[3]);
+        let size = HEADER_SIZE + length + CRC_SIZE;
+        if bytes.len() < size {
+            return Err(FrameError::Truncated);
+        }
+
+        let crc = u16::from_le_bytes([bytes[size - 2], bytes[size - 1]]);
+        if crc != crc16(&bytes[2..size - CRC_SIZE]) {
+            return Err(FrameError::Crc);
+        }
+        // newer versions only append fields
+        if version < VERSION || length < PAYLOAD_SIZE {
+            return Err(FrameError::UnsupportedVersion(version));
+        }
+
+        let payload = &bytes[HEADER_SIZE..];
+        let frame = Frame {
+            uptime: u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]),
+            co2: u16::from_le_bytes([payload[4], payload[5]]),
+            temperature: i16::from_le_bytes([payload[6], payload[7]]),
+            humidity: u16::from_le_bytes([payload[8], payload[9]]),
+            alert_level: payload[10],
+        };
+        Ok((frame, size))
+    }
+}
+
+/// Frames in a stream of bytes. Bytes before a sync marker are skipped, as are damaged frames,
+/// which are reported as errors.
+pub struct Frames<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Frames<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Frames { bytes }
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Result<Frame, FrameError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let start = self.bytes.windows(2).position(|window| window == SYNC)?;
+            self.bytes = &self.bytes[start..];
+
+            match Frame::decode(self.bytes) {
+                Ok((frame, size)) => {
+                    self.bytes = &self.bytes[size..];
+                    return Some(Ok(frame));
+                }
+                // the end of the stream, unless the length was damaged or the sync marker was
+                // part of the data and a frame follows
+                Err(FrameError::Truncated) => {
+                    self.bytes = &self.bytes[1..];
+                    if !frame_follows(self.bytes) {
+                        self.bytes = &[];
+                        return None;
+                    }
+                    return Some(Err(FrameError::Truncated));
+                }
+                // the sync marker may have been part of the data, search again after it
+                Err(error) => {
+                    self.bytes = &self.bytes[1..];
+                    if error != FrameError::NoSync {
+                        return Some(Err(error));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// whether a frame starts at one of the sync markers in `bytes`
+fn frame_follows(bytes: &[u8]) -> bool {
+    (0..bytes.len()).any(|start| {
+        matches!(
+            Frame::decode(&bytes[start..]),
+            Ok(_) | Err(FrameError::UnsupportedVersion(_))
+        )
+    })
+}
+
+/// CRC-16/CCITT-FALSE, also used by the records of `logger`
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = Crc::<u16>::new(0x1021, 16, 0xffff, 0x0000, false);
+    crc.update(data);
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIRST: Frame = Frame {
+        uptime: 12,
+        co2: 812,
+        temperature: 2146,
+        humidity: 4550,
+        alert_level: 1,
+    };
+
+    const SECOND: Frame = Frame {
+        uptime: 14,
+        co2: 815,
+        temperature: -150,
+        humidity: 4560,
+        alert_level: NO_ALERT_LEVEL,
+    };
+
+    fn stream(parts: &[&[u8]]) -> Vec<u8> {
+        parts.concat()
+    }
+
+    fn decode_all(bytes: &[u8]) -> Vec<Result<Frame, FrameError>> {
+        Frames::new(bytes).collect()
+    }
+
+    #[test]
+    fn crc() {
+        // check value of CRC-16/CCITT-FALSE
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+    }
+
+    #[test]
+    fn encode_decode() {
+        let bytes = FIRST.encode();
+
+        assert_eq!(bytes[0..4], [0xa5, 0x5a, VERSION, 11]);
+        assert_eq!(Frame::decode(&bytes), Ok((FIRST, FRAME_SIZE)));
+    }
+
+    #[test]
+    fn new_rounds() {
+        let frame = Frame::new(14, 814.5, -1.499, 45.596, NO_ALERT_LEVEL);
+
+        assert_eq!(frame, SECOND);
+    }
+
+    #[test]
+    fn newer_version() {
+        // a version 2 frame with one more byte of payload
+        let mut bytes = FIRST.encode()[..HEADER_SIZE + PAYLOAD_SIZE].to_vec();
+        bytes[2] = 2;
+        bytes[3] = PAYLOAD_SIZE as u8 + 1;
+        bytes.push(0x42);
+        let crc = crc16(&bytes[2..]);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+
+        assert_eq!(Frame::decode(&bytes), Ok((FIRST, FRAME_SIZE + 1)));
+    }
+
+    #[test]
+    fn resync_after_garbage() {
+        let bytes = stream(&[
+            b"\x00\xa5\x13\x5a\xa5",
+            &FIRST.encode(),
+            b"ok\n",
+            &SECOND.encode(),
+        ]);
+
+        assert_eq!(decode_all(&bytes), [Ok(FIRST), Ok(SECOND)]);
+    }
+
+    #[test]
+    fn bad_crc() {
+        let mut damaged = FIRST.encode();
+        damaged[8] ^= 0x01;
+        let bytes = stream(&[&damaged, &SECOND.encode()]);
+
+        assert_eq!(decode_all(&bytes), [Err(FrameError::Crc), Ok(SECOND)]);
+    }
+
+    #[test]
+    fn truncated_at_the_end() {
+        let bytes = stream(&[&FIRST.encode(), &SECOND.encode()[..FRAME_SIZE - 3]]);
+
+        assert_eq!(decode_all(&bytes), [Ok(FIRST)]);
+        assert_eq!(decode_all(&SYNC), []);
+    }
+
+    #[test]
+    fn damaged_length() {
+        // the length claims more bytes than the stream has left
+        let mut damaged = FIRST.encode();
+        damaged[3] = 0xff;
+        let bytes = stream(&[&damaged, &SECOND.encode()]);
+
+        assert_eq!(decode_all(&bytes), [Err(FrameError::Truncated), Ok(SECOND)]);
+    }
+
+    #[test]
+    fn sync_in_garbage() {
+        // a sync marker with a long length in front of a frame
+        let bytes = stream(&[&[0xa5, 0x5a, 0x01, 0x40, 0x00], &FIRST.encode()]);
+
+        assert_eq!(decode_all(&bytes), [Err(FrameError::Truncated), Ok(FIRST)]);
+    }
+}
@@ -0,0 +1,49 @@
+// I2C bus as used by the sensor drivers.
+//
+// The drivers don't use `Twim` directly, but the `Bus` trait. This allows them to recover a
+// blocked bus, and to be tested on the host with a mock bus.
+
+use core::cell::RefCell;
+
+use crate::Error;
+
+#[cfg(target_os = "none")]
+mod twim;
+#[cfg(target_os = "none")]
+pub use twim::TwimBus;
+
+pub trait Bus {
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error>;
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Error>;
+
+    /// Frees the bus after a device stopped in the middle of a transfer and holds SDA low.
+    fn recover(&mut self) -> Result<(), Error>;
+}
+
+/// Hands one bus to several drivers, e.g. the SCD30 and a barometer.
+/// Every driver gets its own `SharedBus` pointing to the same `RefCell`.
+pub struct SharedBus<'a, B: Bus> {
+    bus: &'a RefCell<B>,
+}
+
+impl<'a, B: Bus> SharedBus<'a, B> {
+    pub fn new(bus: &'a RefCell<B>) -> Self {
+        SharedBus { bus }
+    }
+}
+
+// the drivers don't call each other, so the bus is never borrowed twice
+impl<'a, B: Bus> Bus for SharedBus<'a, B> {
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+        self.bus.borrow_mut().write(address, bytes)
+    }
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        self.bus.borrow_mut().read(address, buffer)
+    }
+
+    fn recover(&mut self) -> Result<(), Error> {
+        self.bus.borrow_mut().recover()
+    }
+}
@@ -0,0 +1,169 @@
+// `Bus` on the TWIM peripherals, only on the board.
+
+use nrf52840_hal::{
+    gpio::{Floating, Input, Pin},
+    pac::{self, TWIM0},
+    twim::{self, Instance, Twim},
+};
+
+use super::Bus;
+use crate::{error::BusError, Error};
+
+// A plain `Twim` keeps the drivers usable as in the knurling sessions, but can't recover the bus.
+impl<T: Instance> Bus for Twim<T> {
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+        Ok(Twim::write(self, address, bytes)?)
+    }
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        Ok(Twim::read(self, address, buffer)?)
+    }
+
+    fn recover(&mut self) -> Result<(), Error> {
+        Err(Error::Bus(BusError::Other))
+    }
+}
+
+// one I2C clock cycle at 100 kHz is 10 µs, the CPU runs at 64 MHz
+const HALF_CLOCK_CYCLES: u32 = 320;
+// a device can be stuck in the middle of a byte, it needs at most 9 clocks to finish it
+const RECOVERY_CLOCKS: u8 = 9;
+
+/// `Bus` on the TWIM0 peripheral
+pub struct TwimBus {
+    twim: Twim<TWIM0>,
+    scl: u32,
+    sda: u32,
+}
+
+impl TwimBus {
+    pub fn new(
+        twim: TWIM0,
+        scl: Pin<Input<Floating>>,
+        sda: Pin<Input<Floating>>,
+        frequency: twim::Frequency,
+    ) -> Self {
+        let scl_psel = scl.psel_bits();
+        let sda_psel = sda.psel_bits();
+        TwimBus {
+            twim: Twim::new(twim, twim::Pins { scl, sda }, frequency),
+            scl: scl_psel,
+            sda: sda_psel,
+        }
+    }
+}
+
+impl Bus for TwimBus {
+    fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+        Bus::write(&mut self.twim, address, bytes)
+    }
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+        Bus::read(&mut self.twim, address, buffer)
+    }
+
+    // While the TWIM is disabled, the pins are driven as GPIOs: SCL is clocked until the device
+    // releases SDA, then a STOP condition resets the state machines of all devices on the bus.
+    fn recover(&mut self) -> Result<(), Error> {
+        // `Twim` owns the peripheral, but it doesn't offer to disable it
+        let twim = unsafe { &*TWIM0::ptr() };
+        twim.enable.write(|w| w.enable().disabled());
+
+        let mut scl = GpioLine::new(self.scl);
+        let mut sda = GpioLine::new(self.sda);
+
+        for _ in 0..RECOVERY_CLOCKS {
+            if sda.is_high() {
+                break;
+            }
+            scl.pull_low();
+            cortex_m::asm::delay(HALF_CLOCK_CYCLES);
+            scl.release();
+            cortex_m::asm::delay(HALF_CLOCK_CYCLES);
+        }
+
+        // STOP: SDA goes high while SCL is high
+        sda.pull_low();
+        cortex_m::asm::delay(HALF_CLOCK_CYCLES);
+        scl.release();
+        cortex_m::asm::delay(HALF_CLOCK_CYCLES);
+        sda.release();
+        cortex_m::asm::delay(HALF_CLOCK_CYCLES);
+
+        let is_free = sda.is_high() && scl.is_high();
+
+        // hand the pins back to the TWIM, configured like `Twim::new` does
+        scl.restore();
+        sda.restore();
+        twim.enable.write(|w| w.enable().enabled());
+
+        if is_free {
+            Ok(())
+        } else {
+            Err(Error::Bus(BusError::Other))
+        }
+    }
+}
+
+// open drain line driven through the GPIO registers, identified by its PSEL bits
+struct GpioLine {
+    port: &'static pac::p0::RegisterBlock,
+    pin: usize,
+}
+
+impl GpioLine {
+    fn new(psel: u32) -> Self {
+        // bit 5 selects the port, bits 0 to 4 the pin
+        let port = if psel & (1 << 5) == 0 {
+            unsafe { &*pac::P0::ptr() }
+        } else {
+            unsafe { &*pac::P1::ptr() }
+        };
+        let line = GpioLine {
+            port,
+            pin: (psel & 0x1f) as usize,
+        };
+        // released before it becomes an output, so the line doesn't glitch low
+        line.port.outset.write(|w| unsafe { w.bits(1 << line.pin) });
+        line.port.pin_cnf[line.pin].write(|w| {
+            w.dir()
+                .output()
+                .input()
+                .connect()
+                .pull()
+                .pullup()
+                .drive()
+                .s0d1()
+                .sense()
+                .disabled()
+        });
+        line
+    }
+
+    fn pull_low(&mut self) {
+        self.port.outclr.write(|w| unsafe { w.bits(1 << self.pin) });
+    }
+
+    fn release(&mut self) {
+        self.port.outset.write(|w| unsafe { w.bits(1 << self.pin) });
+    }
+
+    fn is_high(&self) -> bool {
+        self.port.in_.read().bits() & (1 << self.pin) != 0
+    }
+
+    fn restore(self) {
+        self.port.pin_cnf[self.pin].write(|w| {
+            w.dir()
+                .input()
+                .input()
+                .connect()
+                .pull()
+                .disabled()
+                .drive()
+                .s0d1()
+                .sense()
+                .disabled()
+        });
+    }
+}
@@ -1,30 +1,102 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
+// The parts that need the nRF52840 are only built for the board, the rest builds on the host as
+// well, where the tests run:
+// cargo test --lib --target x86_64-unknown-linux-gnu
 
-use core::sync::atomic::{AtomicUsize, Ordering};
+#[cfg(target_os = "none")]
+use core::sync::atomic::{AtomicU32, Ordering};
 
+#[cfg(target_os = "none")]
 use defmt_rtt as _; // global logger
+#[cfg(target_os = "none")]
 use panic_probe as _;
 
+#[cfg(target_os = "none")]
 use nrf52840_hal as _; // memory layout
 
+pub mod advertising;
 pub mod alerts;
+#[cfg(feature = "barometer")]
+pub mod bmp280;
+#[cfg(target_os = "none")]
+pub mod board;
+#[cfg(target_os = "none")]
 pub mod buzzer;
+pub mod calibration;
+pub mod clock;
+pub mod co2_sensor;
+pub mod config;
+#[cfg(target_os = "none")]
 pub mod dk_button;
+pub mod error;
+#[cfg(target_os = "none")]
+pub mod flash;
+pub mod frame;
+pub mod i2c;
+#[cfg(any(test, target_os = "none"))]
+pub mod logger;
+pub mod modbus;
+pub mod modbus_rtu;
+pub mod ndef;
+pub mod nfc;
 pub mod number_representation;
+pub mod occupancy;
+pub mod pressure;
 pub mod rgb_led;
 pub mod scd30;
+pub mod scd4x;
+#[cfg(target_os = "none")]
+pub mod scheduler;
+pub mod serial_report;
+pub mod shell;
+#[cfg(all(feature = "usb", target_os = "none"))]
+pub mod usb_serial;
 
-static COUNT: AtomicUsize = AtomicUsize::new(0);
-defmt::timestamp!("{=usize}", {
-    // NOTE(no-CAS) `timestamps` runs with interrupts disabled
-    let n = COUNT.load(Ordering::Relaxed);
-    COUNT.store(n + 1, Ordering::Relaxed);
-    n
+pub use error::Error;
+
+#[cfg(target_os = "none")]
+static COUNT: AtomicU32 = AtomicU32::new(0);
+// uptime in milliseconds, or the number of the log line if the application doesn't start the clock
+#[cfg(target_os = "none")]
+defmt::timestamp!("{=u64}", {
+    if clock::is_running() {
+        clock::now_ms()
+    } else {
+        // NOTE(no-CAS) `timestamps` runs with interrupts disabled
+        let n = COUNT.load(Ordering::Relaxed);
+        COUNT.store(n + 1, Ordering::Relaxed);
+        u64::from(n)
+    }
 });
 
 /// Terminates the application and makes `probe-run` exit with exit-code = 0
+#[cfg(target_os = "none")]
 pub fn exit() -> ! {
     loop {
         cortex_m::asm::bkpt();
     }
 }
+
+// the tests log through the drivers, the messages are dropped
+#[cfg(not(target_os = "none"))]
+mod host_logger {
+    use core::ptr::NonNull;
+
+    #[defmt::global_logger]
+    struct Discard;
+
+    unsafe impl defmt::Logger for Discard {
+        fn acquire() -> Option<NonNull<dyn defmt::Write>> {
+            // zero sized, there's nothing to point to
+            Some(NonNull::<Discard>::dangling())
+        }
+
+        unsafe fn release(_: NonNull<dyn defmt::Write>) {}
+    }
+
+    impl defmt::Write for Discard {
+        fn write(&mut self, _: &[u8]) {}
+    }
+
+    defmt::timestamp!("{=u64}", 0);
+}
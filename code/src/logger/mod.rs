@@ -0,0 +1,147 @@
+// Ring buffer of measurements in the flash, for days without a host attached.
+//
+// Records are appended to `LOG_PAGES` one after the other. Before the first record is written
+// into a page, the page is erased; once the end of the region is reached, writing continues at
+// the start, so the oldest page is dropped. With 32 pages of 256 records, one record a minute
+// covers almost 6 days.
+//
+// Every record carries a sequence number that continues across resets, so after boot the
+// logger finds the newest record and appends after it. A record torn by a reset during the write
+// fails its CRC and is skipped when reading.
+//
+// The layout of a record is in `record`.
+//
+// The uptime starts at 0 after every reset; a reader recognises a reset by the uptime going back.
+
+mod record;
+#[cfg(target_os = "none")]
+mod ring;
+
+pub use record::Record;
+#[cfg(target_os = "none")]
+pub use ring::{Logger, Records};
+
+use record::RECORD_SIZE;
+
+// Where appending continues after a reset: the slot after the newest record, and the sequence
+// number after its one. `read` returns the bytes of a slot, `None` if it can't be read.
+fn resume(
+    capacity: u32,
+    records_per_page: u32,
+    read: impl Fn(u32) -> Option<[u8; RECORD_SIZE as usize]>,
+) -> (u32, u32) {
+    let newest = (0..capacity)
+        .filter_map(|slot| Some((slot, Record::decode(&read(slot)?)?.sequence)))
+        .max_by_key(|(_, sequence)| *sequence);
+    let is_erased =
+        |slot| matches!(read(slot), Some(bytes) if bytes.iter().all(|byte| *byte == 0xff));
+
+    match newest {
+        Some((slot, sequence)) => {
+            let mut next = (slot + 1) % capacity;
+            // slots after a torn record can't be written without erasing the page
+            while !next.is_multiple_of(records_per_page) && !is_erased(next) {
+                next = (next + 1) % capacity;
+            }
+            (next, sequence.wrapping_add(1))
+        }
+        None => (0, 0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{clock::Instant, co2_sensor::SensorData};
+
+    // two pages of four records
+    const CAPACITY: u32 = 8;
+    const RECORDS_PER_PAGE: u32 = 4;
+
+    type Slot = [u8; RECORD_SIZE as usize];
+
+    struct Ring([Option<Slot>; CAPACITY as usize]);
+
+    impl Ring {
+        fn erased() -> Self {
+            Ring([Some([0xff; RECORD_SIZE as usize]); CAPACITY as usize])
+        }
+
+        fn write(&mut self, slot: u32, sequence: u32) {
+            let data = SensorData {
+                co2: 420.0,
+                temperature: 21.0,
+                humidity: 40.0,
+            };
+            let record = Record::new(sequence, Instant::from_millis(0), &data);
+            let mut bytes = [0u8; RECORD_SIZE as usize];
+            for (chunk, word) in bytes.chunks_mut(4).zip(record.encode().iter()) {
+                chunk.copy_from_slice(&word.to_le_bytes());
+            }
+            self.0[slot as usize] = Some(bytes);
+        }
+
+        fn resume(&self) -> (u32, u32) {
+            resume(CAPACITY, RECORDS_PER_PAGE, |slot| self.0[slot as usize])
+        }
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(Ring::erased().resume(), (0, 0));
+    }
+
+    #[test]
+    fn appends_after_the_newest() {
+        let mut ring = Ring::erased();
+        for slot in 0..3 {
+            ring.write(slot, slot);
+        }
+        assert_eq!(ring.resume(), (3, 3));
+    }
+
+    #[test]
+    fn wraps_around_at_the_end() {
+        let mut ring = Ring::erased();
+        for slot in 0..CAPACITY {
+            ring.write(slot, slot);
+        }
+        assert_eq!(ring.resume(), (0, CAPACITY));
+    }
+
+    #[test]
+    fn newest_in_the_middle() {
+        // the first page was erased and written again, the second page holds older records
+        let mut ring = Ring::erased();
+        for slot in 4..CAPACITY {
+            ring.write(slot, slot);
+        }
+        for slot in 0..3 {
+            ring.write(slot, CAPACITY + slot);
+        }
+        assert_eq!(ring.resume(), (3, CAPACITY + 3));
+    }
+
+    #[test]
+    fn skips_torn_slots() {
+        let mut ring = Ring::erased();
+        ring.write(0, 0);
+        ring.write(1, 1);
+        // torn by a reset, neither a record nor erased
+        ring.0[2] = Some([0; RECORD_SIZE as usize]);
+        assert_eq!(ring.resume(), (3, 2));
+
+        // a torn last slot of a page continues on the next page
+        ring.write(2, 2);
+        ring.0[3] = Some([0; RECORD_SIZE as usize]);
+        assert_eq!(ring.resume(), (4, 3));
+    }
+
+    #[test]
+    fn unreadable_slot_is_not_written() {
+        let mut ring = Ring::erased();
+        ring.write(5, 5);
+        ring.0[6] = None;
+        assert_eq!(ring.resume(), (7, 6));
+    }
+}
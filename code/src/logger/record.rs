@@ -0,0 +1,159 @@
+// The format of a record in the flash.
+//
+// 16 bytes, little endian:
+// | sequence u32 | uptime s u32 | CO2 ppm u16 | temperature 0.01 °C i16 | humidity 0.01 % u16 |
+// | CRC-16 u16 |
+
+use crate::{clock::Instant, co2_sensor::SensorData, frame::crc16, number_representation::round};
+
+pub(super) const RECORD_SIZE: u32 = 16;
+
+/// Measurement as stored in the flash, in fixed point
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub struct Record {
+    pub sequence: u32,
+    /// seconds since the device was started
+    pub uptime: u32,
+    /// in ppm
+    pub co2: u16,
+    /// in 0.01 °C
+    pub temperature: i16,
+    /// relative humidity in 0.01 %
+    pub humidity: u16,
+}
+
+impl Record {
+    pub fn new(sequence: u32, time: Instant, data: &SensorData) -> Self {
+        Record {
+            sequence,
+            uptime: (time.as_millis() / 1000) as u32,
+            co2: round(data.co2) as u16,
+            temperature: round(data.temperature * 100.0) as i16,
+            humidity: round(data.humidity * 100.0) as u16,
+        }
+    }
+
+    pub fn sensor_data(&self) -> SensorData {
+        SensorData {
+            co2: f32::from(self.co2),
+            temperature: f32::from(self.temperature) / 100.0,
+            humidity: f32::from(self.humidity) / 100.0,
+        }
+    }
+
+    pub(super) fn encode(&self) -> [u32; (RECORD_SIZE / 4) as usize] {
+        let mut bytes = [0u8; RECORD_SIZE as usize];
+        bytes[0..4].copy_from_slice(&self.sequence.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.uptime.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.co2.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.temperature.to_le_bytes());
+        bytes[12..14].copy_from_slice(&self.humidity.to_le_bytes());
+        let crc = crc16(&bytes[0..14]);
+        bytes[14..16].copy_from_slice(&crc.to_le_bytes());
+
+        let mut words = [0u32; (RECORD_SIZE / 4) as usize];
+        for (word, chunk) in words.iter_mut().zip(bytes.chunks(4)) {
+            *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+        words
+    }
+
+    // `None` if the slot is erased or the record is torn
+    pub(super) fn decode(bytes: &[u8; RECORD_SIZE as usize]) -> Option<Self> {
+        if bytes.iter().all(|byte| *byte == 0xff) {
+            return None;
+        }
+        let crc = u16::from_le_bytes([bytes[14], bytes[15]]);
+        if crc != crc16(&bytes[0..14]) {
+            return None;
+        }
+        Some(Record {
+            sequence: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            uptime: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            co2: u16::from_le_bytes([bytes[8], bytes[9]]),
+            temperature: i16::from_le_bytes([bytes[10], bytes[11]]),
+            humidity: u16::from_le_bytes([bytes[12], bytes[13]]),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(record: &Record) -> [u8; RECORD_SIZE as usize] {
+        let mut bytes = [0u8; RECORD_SIZE as usize];
+        for (chunk, word) in bytes.chunks_mut(4).zip(record.encode().iter()) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    fn record() -> Record {
+        let data = SensorData {
+            co2: 612.4,
+            temperature: -3.456,
+            humidity: 45.678,
+        };
+        Record::new(7, Instant::from_millis(90_500), &data)
+    }
+
+    #[test]
+    fn fixed_point() {
+        assert_eq!(
+            record(),
+            Record {
+                sequence: 7,
+                uptime: 90,
+                co2: 612,
+                temperature: -346,
+                humidity: 4568,
+            }
+        );
+
+        let data = record().sensor_data();
+        assert_eq!(data.co2, 612.0);
+        assert!((data.temperature + 3.46).abs() < 1e-6);
+        assert!((data.humidity - 45.68).abs() < 1e-6);
+    }
+
+    #[test]
+    fn round_trip() {
+        let record = record();
+        assert_eq!(Record::decode(&bytes(&record)), Some(record));
+    }
+
+    #[test]
+    fn layout() {
+        let bytes = bytes(&record());
+        assert_eq!(bytes[0..4], 7u32.to_le_bytes());
+        assert_eq!(bytes[4..8], 90u32.to_le_bytes());
+        assert_eq!(bytes[8..10], 612u16.to_le_bytes());
+        assert_eq!(bytes[10..12], (-346i16).to_le_bytes());
+        assert_eq!(bytes[12..14], 4568u16.to_le_bytes());
+        assert_eq!(bytes[14..16], crc16(&bytes[0..14]).to_le_bytes());
+    }
+
+    #[test]
+    fn erased() {
+        assert_eq!(Record::decode(&[0xff; RECORD_SIZE as usize]), None);
+    }
+
+    #[test]
+    fn torn() {
+        let written = bytes(&record());
+
+        // every bit that didn't make it into the flash is still set
+        for length in 0..RECORD_SIZE as usize {
+            let mut bytes = [0xff; RECORD_SIZE as usize];
+            bytes[..length].copy_from_slice(&written[..length]);
+            assert_eq!(Record::decode(&bytes), None, "{} bytes written", length);
+        }
+
+        for index in 0..RECORD_SIZE as usize {
+            let mut bytes = written;
+            bytes[index] ^= 0x10;
+            assert_eq!(Record::decode(&bytes), None, "byte {} changed", index);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+// The ring buffer in the flash of the board, only on the board.
+
+use super::{
+    record::{Record, RECORD_SIZE},
+    resume,
+};
+use crate::{
+    clock::Instant,
+    co2_sensor::SensorData,
+    flash::{Flash, LOG_PAGES, PAGE_SIZE},
+    Error,
+};
+
+const RECORDS_PER_PAGE: u32 = PAGE_SIZE / RECORD_SIZE;
+const CAPACITY: u32 = (LOG_PAGES.end - LOG_PAGES.start) / RECORD_SIZE;
+
+pub struct Logger {
+    // slot the next record goes to, as index into the region
+    next: u32,
+    sequence: u32,
+}
+
+impl Logger {
+    /// Finds the newest record, so new records are appended after it
+    pub fn open(flash: &Flash) -> Self {
+        let (next, sequence) = resume(CAPACITY, RECORDS_PER_PAGE, |slot| read_slot(flash, slot));
+        Logger { next, sequence }
+    }
+
+    /// Appends a measurement taken at `time`. Erasing a page when a new one is started takes
+    /// up to 85 ms.
+    pub fn append(
+        &mut self,
+        flash: &mut Flash,
+        time: Instant,
+        data: &SensorData,
+    ) -> Result<(), Error> {
+        if self.next.is_multiple_of(RECORDS_PER_PAGE) {
+            flash.erase_page(slot_address(self.next))?;
+        }
+
+        let record = Record::new(self.sequence, time, data);
+        let result = flash.write(slot_address(self.next), &record.encode());
+        // a failed slot is skipped as well, so it isn't written twice
+        self.next = (self.next + 1) % CAPACITY;
+        self.sequence = self.sequence.wrapping_add(1);
+        result
+    }
+
+    /// Records from the oldest to the newest
+    pub fn records<'a>(&self, flash: &'a Flash) -> Records<'a> {
+        Records {
+            flash,
+            slot: self.next,
+            remaining: CAPACITY,
+        }
+    }
+
+    /// Prints all records over RTT, as CSV
+    pub fn dump(&self, flash: &Flash) {
+        defmt::info!("sequence,uptime_s,co2_ppm,temperature_c,humidity_percent");
+        for record in self.records(flash) {
+            let data = record.sensor_data();
+            defmt::info!(
+                "{=u32},{=u32},{=f32},{=f32},{=f32}",
+                record.sequence,
+                record.uptime,
+                data.co2,
+                data.temperature,
+                data.humidity
+            );
+        }
+    }
+
+    /// Erases all records
+    pub fn clear(&mut self, flash: &mut Flash) -> Result<(), Error> {
+        for page in (LOG_PAGES.start..LOG_PAGES.end).step_by(PAGE_SIZE as usize) {
+            flash.erase_page(page)?;
+        }
+        self.next = 0;
+        Ok(())
+    }
+}
+
+pub struct Records<'a> {
+    flash: &'a Flash,
+    slot: u32,
+    remaining: u32,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Record;
+
+    fn next(&mut self) -> Option<Record> {
+        while self.remaining > 0 {
+            let record = read_record(self.flash, self.slot);
+            self.slot = (self.slot + 1) % CAPACITY;
+            self.remaining -= 1;
+            if record.is_some() {
+                return record;
+            }
+        }
+        None
+    }
+}
+
+fn slot_address(slot: u32) -> u32 {
+    LOG_PAGES.start + slot * RECORD_SIZE
+}
+
+fn read_slot(flash: &Flash, slot: u32) -> Option<[u8; RECORD_SIZE as usize]> {
+    let mut bytes = [0u8; RECORD_SIZE as usize];
+    flash.read(slot_address(slot), &mut bytes).ok()?;
+    Some(bytes)
+}
+
+fn read_record(flash: &Flash, slot: u32) -> Option<Record> {
+    Record::decode(&read_slot(flash, slot)?)
+}
@@ -0,0 +1,331 @@
+// Modbus RTU protocol, independent of the serial port and of the registers it exposes.
+//
+// A request frame is `| slave address | function code | data | CRC-16 |`; the response repeats
+// address and function code. Supported function codes:
+// * 3 read holding registers, 4 read input registers: `| start u16 | count u16 |`
+// * 6 write single register: `| address u16 | value u16 |`
+// * 16 write multiple registers: `| start u16 | count u16 | byte count u8 | values |`
+//
+// Values are big endian, the CRC is little endian. Requests to address 0 are broadcasts: writes
+// are executed, but not answered. Errors are answered with an exception response, the function
+// code with the highest bit set followed by the exception code.
+//
+// `Slave` answers requests, the functions at the end build requests as a master and check the
+// responses, e.g. to talk to the SCD30 over its Modbus interface.
+//
+// Frames go over a `Port`; `modbus_rtu` and `scd30::modbus` are written against it, and
+// `UartePort` is the one on the UARTE. Apart from `UartePort`, the module only depends on `core`
+// and `crc_all`.
+
+use crc_all::Crc;
+
+use crate::Error;
+
+#[cfg(target_os = "none")]
+mod uarte;
+
+#[cfg(target_os = "none")]
+pub use uarte::UartePort;
+
+/// Longest RTU frame
+pub const MAX_FRAME_SIZE: usize = 256;
+/// Length of the requests a master sends, and of the response to a write
+pub const REQUEST_SIZE: usize = 8;
+/// Length of an exception response
+pub const EXCEPTION_SIZE: usize = 5;
+
+const BROADCAST_ADDRESS: u8 = 0;
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+const WRITE_SINGLE_REGISTER: u8 = 0x06;
+const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+const EXCEPTION_FLAG: u8 = 0x80;
+
+// most registers that fit into one frame
+const MAX_READ_COUNT: u16 = 125;
+const MAX_WRITE_COUNT: u16 = 123;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exception {
+    IllegalFunction = 0x01,
+    /// a register in the range isn't mapped
+    IllegalDataAddress = 0x02,
+    /// the value is out of range, or the request is malformed
+    IllegalDataValue = 0x03,
+    ServerDeviceFailure = 0x04,
+}
+
+/// What's wrong with the response to a request of a master
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResponseError {
+    /// too short to be a response at all
+    Truncated,
+    Crc,
+    /// from another slave, for another function, or with another number of registers
+    Unexpected,
+    /// the slave answered with this exception code
+    Exception(u8),
+}
+
+/// Serial line the frames are sent and received on
+pub trait Port {
+    /// Sends the bytes, and returns once the last one has left
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error>;
+
+    /// Receives bytes into `buffer` until it's full or `timeout_us` passed. Returns how many
+    /// bytes have been received, 0 if none arrived in time.
+    fn read_timeout(&mut self, buffer: &mut [u8], timeout_us: u32) -> Result<usize, Error>;
+}
+
+/// Registers a slave exposes. Addresses are the protocol addresses, starting at 0.
+pub trait RegisterMap {
+    /// Read-only register, `None` if the address isn't mapped
+    fn input_register(&self, address: u16) -> Option<u16>;
+
+    /// `None` if the address isn't mapped
+    fn holding_register(&self, address: u16) -> Option<u16>;
+
+    /// Writes consecutive holding registers starting at `start`. Either all values are written,
+    /// or none.
+    fn write_holding_registers(&mut self, start: u16, values: &[u16]) -> Result<(), Exception>;
+}
+
+pub struct Slave {
+    address: u8,
+}
+
+impl Slave {
+    /// `address` is the slave address on the bus, 1 to 247
+    pub fn new(address: u8) -> Self {
+        Slave { address }
+    }
+
+    pub fn address(&self) -> u8 {
+        self.address
+    }
+
+    /// Handles a request frame, including slave address and CRC. Returns the length of the
+    /// response written to `response`, or `None` if there is nothing to answer: the frame is
+    /// damaged, addressed to another slave, or a broadcast.
+    pub fn handle<M: RegisterMap>(
+        &self,
+        request: &[u8],
+        map: &mut M,
+        response: &mut [u8; MAX_FRAME_SIZE],
+    ) -> Option<usize> {
+        // address, function code and CRC
+        if request.len() < 4 {
+            return None;
+        }
+        let (frame, crc) = request.split_at(request.len() - 2);
+        if u16::from_le_bytes([crc[0], crc[1]]) != crc16(frame) {
+            return None;
+        }
+        let address = frame[0];
+        if address != self.address && address != BROADCAST_ADDRESS {
+            return None;
+        }
+
+        let function = frame[1];
+        let mut writer = Writer {
+            buffer: response,
+            length: 0,
+        };
+        writer.u8(self.address);
+        writer.u8(function);
+        if let Err(exception) = execute(function, &frame[2..], map, &mut writer) {
+            writer.length = 1;
+            writer.u8(function | EXCEPTION_FLAG);
+            writer.u8(exception as u8);
+        }
+
+        if address == BROADCAST_ADDRESS {
+            return None;
+        }
+        let crc = crc16(&writer.buffer[..writer.length]);
+        writer.u16_le(crc);
+        Some(writer.length)
+    }
+}
+
+fn execute<M: RegisterMap>(
+    function: u8,
+    data: &[u8],
+    map: &mut M,
+    response: &mut Writer,
+) -> Result<(), Exception> {
+    match function {
+        READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+            let (start, count) = (word(data, 0)?, word(data, 1)?);
+            if data.len() != 4 || count == 0 || count > MAX_READ_COUNT {
+                return Err(Exception::IllegalDataValue);
+            }
+            response.u8((2 * count) as u8);
+            for offset in 0..count {
+                let address = start
+                    .checked_add(offset)
+                    .ok_or(Exception::IllegalDataAddress)?;
+                let value = if function == READ_HOLDING_REGISTERS {
+                    map.holding_register(address)
+                } else {
+                    map.input_register(address)
+                };
+                response.u16(value.ok_or(Exception::IllegalDataAddress)?);
+            }
+        }
+        WRITE_SINGLE_REGISTER => {
+            let (address, value) = (word(data, 0)?, word(data, 1)?);
+            if data.len() != 4 {
+                return Err(Exception::IllegalDataValue);
+            }
+            map.write_holding_registers(address, &[value])?;
+            response.u16(address);
+            response.u16(value);
+        }
+        WRITE_MULTIPLE_REGISTERS => {
+            let (start, count) = (word(data, 0)?, word(data, 1)?);
+            let byte_count = usize::from(*data.get(4).ok_or(Exception::IllegalDataValue)?);
+            if count == 0
+                || count > MAX_WRITE_COUNT
+                || byte_count != 2 * usize::from(count)
+                || data.len() != 5 + byte_count
+            {
+                return Err(Exception::IllegalDataValue);
+            }
+            let mut values = [0u16; MAX_WRITE_COUNT as usize];
+            for (index, value) in values.iter_mut().take(usize::from(count)).enumerate() {
+                *value = u16::from_be_bytes([data[5 + 2 * index], data[6 + 2 * index]]);
+            }
+            map.write_holding_registers(start, &values[..usize::from(count)])?;
+            response.u16(start);
+            response.u16(count);
+        }
+        _ => return Err(Exception::IllegalFunction),
+    }
+    Ok(())
+}
+
+// the `index`th big endian word of the request data
+fn word(data: &[u8], index: usize) -> Result<u16, Exception> {
+    match (data.get(2 * index), data.get(2 * index + 1)) {
+        (Some(high), Some(low)) => Ok(u16::from_be_bytes([*high, *low])),
+        _ => Err(Exception::IllegalDataValue),
+    }
+}
+
+struct Writer<'a> {
+    buffer: &'a mut [u8; MAX_FRAME_SIZE],
+    length: usize,
+}
+
+impl<'a> Writer<'a> {
+    fn u8(&mut self, value: u8) {
+        self.buffer[self.length] = value;
+        self.length += 1;
+    }
+
+    fn u16(&mut self, value: u16) {
+        for byte in value.to_be_bytes().iter() {
+            self.u8(*byte);
+        }
+    }
+
+    // only the CRC is little endian
+    fn u16_le(&mut self, value: u16) {
+        for byte in value.to_le_bytes().iter() {
+            self.u8(*byte);
+        }
+    }
+}
+
+/// Request of a master to read `count` holding registers starting at `start`
+pub fn read_holding_request(slave: u8, start: u16, count: u16) -> [u8; REQUEST_SIZE] {
+    request(slave, READ_HOLDING_REGISTERS, start, count)
+}
+
+/// Request of a master to write `value` to the holding register at `address`
+pub fn write_single_request(slave: u8, address: u16, value: u16) -> [u8; REQUEST_SIZE] {
+    request(slave, WRITE_SINGLE_REGISTER, address, value)
+}
+
+/// Length of the response to a request that reads `count` registers
+pub fn read_response_size(count: u16) -> usize {
+    5 + 2 * usize::from(count)
+}
+
+/// Checks the response of `slave` to `read_holding_request` and copies the registers to
+/// `values`, which has to be as long as the number of registers requested.
+pub fn parse_read_response(
+    slave: u8,
+    response: &[u8],
+    values: &mut [u16],
+) -> Result<(), ResponseError> {
+    let data = check_response(slave, READ_HOLDING_REGISTERS, response)?;
+    if data.len() != 1 + 2 * values.len() || usize::from(data[0]) != 2 * values.len() {
+        return Err(ResponseError::Unexpected);
+    }
+    for (index, value) in values.iter_mut().enumerate() {
+        *value = u16::from_be_bytes([data[1 + 2 * index], data[2 + 2 * index]]);
+    }
+    Ok(())
+}
+
+/// Checks the response to `write_single_request`, the slave repeats the request
+pub fn check_write_response(
+    request: &[u8; REQUEST_SIZE],
+    response: &[u8],
+) -> Result<(), ResponseError> {
+    check_response(request[0], WRITE_SINGLE_REGISTER, response)?;
+    if response == &request[..] {
+        Ok(())
+    } else {
+        Err(ResponseError::Unexpected)
+    }
+}
+
+// `| slave | function | word | word | CRC |`
+fn request(slave: u8, function: u8, first: u16, second: u16) -> [u8; REQUEST_SIZE] {
+    let [first_high, first_low] = first.to_be_bytes();
+    let [second_high, second_low] = second.to_be_bytes();
+    let mut frame = [
+        slave,
+        function,
+        first_high,
+        first_low,
+        second_high,
+        second_low,
+        0,
+        0,
+    ];
+    let crc = crc16(&frame[..REQUEST_SIZE - 2]).to_le_bytes();
+    frame[REQUEST_SIZE - 2..].copy_from_slice(&crc);
+    frame
+}
+
+// returns the data between function code and CRC
+fn check_response(slave: u8, function: u8, response: &[u8]) -> Result<&[u8], ResponseError> {
+    if response.len() < EXCEPTION_SIZE {
+        return Err(ResponseError::Truncated);
+    }
+    let (frame, crc) = response.split_at(response.len() - 2);
+    if u16::from_le_bytes([crc[0], crc[1]]) != crc16(frame) {
+        return Err(ResponseError::Crc);
+    }
+    if frame[0] != slave {
+        return Err(ResponseError::Unexpected);
+    }
+    match frame[1] {
+        code if code == function => Ok(&frame[2..]),
+        code if code == function | EXCEPTION_FLAG && frame.len() == 3 => {
+            Err(ResponseError::Exception(frame[2]))
+        }
+        _ => Err(ResponseError::Unexpected),
+    }
+}
+
+/// CRC-16/MODBUS of the frame without its CRC
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = Crc::<u16>::new(0x8005, 16, 0xffff, 0x0000, true);
+    crc.update(data);
+    crc.finish()
+}
@@ -0,0 +1,97 @@
+// `Port` on a UARTE, optionally with an RS-485 transceiver.
+//
+// If the transceiver has a driver enable input instead of switching direction by itself, it's
+// driven high while a frame is sent.
+
+use embedded_hal::digital::v2::OutputPin;
+use nrf52840_hal::{
+    gpio::{Output, Pin, PushPull},
+    timer::{self, Timer},
+    uarte::{self, Baudrate, Parity, Uarte},
+};
+
+use super::Port;
+use crate::Error;
+
+/// `Port` on a UARTE. `timer` ends the reads.
+pub struct UartePort<U: uarte::Instance, T: timer::Instance> {
+    uarte: Uarte<U>,
+    timer: Timer<T>,
+    baudrate: Baudrate,
+    driver_enable: Option<Pin<Output<PushPull>>>,
+}
+
+impl<U, T> UartePort<U, T>
+where
+    U: uarte::Instance,
+    T: timer::Instance,
+{
+    /// `driver_enable` is the DE input of the transceiver, `None` if there is none or it switches
+    /// direction by itself
+    pub fn new(
+        uarte: U,
+        pins: uarte::Pins,
+        parity: Parity,
+        baudrate: Baudrate,
+        timer: Timer<T>,
+        mut driver_enable: Option<Pin<Output<PushPull>>>,
+    ) -> Self {
+        if let Some(pin) = driver_enable.as_mut() {
+            pin.set_low().ok();
+        }
+        UartePort {
+            uarte: Uarte::new(uarte, pins, parity, baudrate),
+            timer,
+            baudrate,
+            driver_enable,
+        }
+    }
+
+    pub fn bits_per_second(&self) -> u32 {
+        match self.baudrate {
+            Baudrate::BAUD1200 => 1200,
+            Baudrate::BAUD2400 => 2400,
+            Baudrate::BAUD4800 => 4800,
+            Baudrate::BAUD9600 => 9600,
+            Baudrate::BAUD14400 => 14400,
+            Baudrate::BAUD19200 => 19200,
+            Baudrate::BAUD38400 => 38400,
+            Baudrate::BAUD57600 => 57600,
+            // the Modbus frame gap is fixed at these rates
+            _ => 115_200,
+        }
+    }
+
+    /// Returns the UARTE, its pins, the timer and the driver enable pin
+    pub fn release(self) -> (U, uarte::Pins, Timer<T>, Option<Pin<Output<PushPull>>>) {
+        let (uarte, pins) = self.uarte.free();
+        (uarte, pins, self.timer, self.driver_enable)
+    }
+}
+
+impl<U, T> Port for UartePort<U, T>
+where
+    U: uarte::Instance,
+    T: timer::Instance,
+{
+    // `Uarte::write` returns once the last byte has left the shift register, then the bus is
+    // released again, also if sending failed
+    fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if let Some(pin) = self.driver_enable.as_mut() {
+            pin.set_high().ok();
+        }
+        let result = self.uarte.write(bytes);
+        if let Some(pin) = self.driver_enable.as_mut() {
+            pin.set_low().ok();
+        }
+        result.map_err(Error::from)
+    }
+
+    fn read_timeout(&mut self, buffer: &mut [u8], timeout_us: u32) -> Result<usize, Error> {
+        match self.uarte.read_timeout(buffer, &mut self.timer, timeout_us) {
+            Ok(()) => Ok(buffer.len()),
+            Err(uarte::Error::Timeout(length)) => Ok(length),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
@@ -0,0 +1,438 @@
+// Modbus RTU slave, for building management systems on an RS-485 bus.
+//
+// On the board, the port is a `modbus::UartePort` wired to an RS-485 transceiver. Modbus RTU
+// uses 8 data bits and even parity by default, which is what the port should be configured for.
+// A frame ends with a silence of 3.5 characters.
+//
+// Input registers, function code 4, read only:
+// | 0-1 | CO2 in ppm, f32                  |
+// | 2-3 | temperature in °C, f32           |
+// | 4-5 | relative humidity in %, f32      |
+// | 6   | CO2 in ppm, u16                  |
+// | 7   | temperature in 0.01 °C, i16      |
+// | 8   | relative humidity in 0.01 %, u16 |
+// | 9   | alert level, 0 for the best air  |
+// Floats are IEEE 754 with the high word first. All input registers read 0xffff until the first
+// measurement.
+//
+// Holding registers, function codes 3, 6 and 16:
+// | 0 | upper limit of good air in ppm          |
+// | 1 | upper limit before the warning in ppm   |
+// | 2 | upper limit before the alarm in ppm     |
+// | 3 | CO2 of the outdoor air in ppm           |
+// | 4 | ambient pressure in mbar, 0 is off      |
+// | 5 | altitude in m                           |
+// | 6 | time between two measurements in s      |
+// Writes change `Registers::settings`, the application applies and saves them. The thresholds
+// follow the standard until one of them is written, then all three become custom limits.
+
+use core::ops::RangeInclusive;
+
+use crate::{
+    alerts::Category,
+    co2_sensor::SensorData,
+    config::{Settings, MEASUREMENT_INTERVAL_S},
+    modbus::{Exception, Port, RegisterMap, Slave, MAX_FRAME_SIZE},
+    number_representation::round,
+    Error,
+};
+
+const VALID_PRESSURE_MBAR: RangeInclusive<u16> = 700..=1400;
+
+// bits of a character: start, 8 data, parity and stop
+const BITS_PER_CHARACTER: u32 = 11;
+// the frame gap is fixed above 19200 baud
+const MIN_FRAME_GAP_US: u32 = 1750;
+
+/// What the slave exposes: the latest measurement and the settings
+pub struct Registers {
+    /// the latest measurement and its category, `None` before the first one
+    pub measurement: Option<(SensorData, Category)>,
+    pub settings: Settings,
+}
+
+impl Registers {
+    pub fn new(settings: Settings) -> Self {
+        Registers {
+            measurement: None,
+            settings,
+        }
+    }
+}
+
+impl RegisterMap for Registers {
+    fn input_register(&self, address: u16) -> Option<u16> {
+        let (data, category) = match self.measurement {
+            Some(measurement) => measurement,
+            None => return if address <= 9 { Some(0xffff) } else { None },
+        };
+        let float_word = |value: f32, low: bool| {
+            let bits = value.to_bits();
+            if low {
+                bits as u16
+            } else {
+                (bits >> 16) as u16
+            }
+        };
+
+        let value = match address {
+            0 | 1 => float_word(data.co2, address == 1),
+            2 | 3 => float_word(data.temperature, address == 3),
+            4 | 5 => float_word(data.humidity, address == 5),
+            6 => round(data.co2) as u16,
+            7 => round(data.temperature * 100.0) as i16 as u16,
+            8 => round(data.humidity * 100.0) as u16,
+            9 => u16::from(category.level()),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    fn holding_register(&self, address: u16) -> Option<u16> {
+        let settings = &self.settings;
+        let value = match address {
+            0..=2 => settings.thresholds()[usize::from(address)],
+            3 => settings.outdoor_co2,
+            4 => settings.pressure,
+            5 => settings.altitude,
+            6 => settings.measurement_interval,
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    fn write_holding_registers(&mut self, start: u16, values: &[u16]) -> Result<(), Exception> {
+        // the thresholds are absolute, they are converted after the outdoor air is known
+        let mut settings = self.settings;
+        let mut thresholds = settings.thresholds();
+        let mut thresholds_written = false;
+
+        for (offset, value) in values.iter().copied().enumerate() {
+            // anything beyond the end of the address space is unmapped as well
+            match start.saturating_add(offset as u16) {
+                address @ 0..=2 => {
+                    thresholds[usize::from(address)] = value;
+                    thresholds_written = true;
+                }
+                3 => settings.outdoor_co2 = value,
+                4 if value == 0 || VALID_PRESSURE_MBAR.contains(&value) => {
+                    settings.pressure = value
+                }
+                5 => settings.altitude = value,
+                6 if MEASUREMENT_INTERVAL_S.contains(&value) => {
+                    settings.measurement_interval = value
+                }
+                4 | 6 => return Err(Exception::IllegalDataValue),
+                _ => return Err(Exception::IllegalDataAddress),
+            }
+        }
+        if thresholds_written {
+            settings
+                .set_thresholds(thresholds)
+                .map_err(|_| Exception::IllegalDataValue)?;
+        }
+
+        self.settings = settings;
+        Ok(())
+    }
+}
+
+/// Modbus RTU on a `Port`, e.g. a `UartePort` configured for 8E1
+pub struct RtuPort<P: Port> {
+    port: P,
+    // silence that ends a frame
+    frame_gap_us: u32,
+}
+
+impl<P: Port> RtuPort<P> {
+    /// `bits_per_second` is the baud rate of the port, the silence that ends a frame depends on it
+    pub fn new(port: P, bits_per_second: u32) -> Self {
+        let frame_gap_us =
+            (35 * BITS_PER_CHARACTER * 100_000 / bits_per_second).max(MIN_FRAME_GAP_US);
+        RtuPort { port, frame_gap_us }
+    }
+
+    /// Waits up to `timeout_us` for a request and answers it. Returns `false` if no request
+    /// arrived in time.
+    pub fn serve<M: RegisterMap>(
+        &mut self,
+        slave: &Slave,
+        map: &mut M,
+        timeout_us: u32,
+    ) -> Result<bool, Error> {
+        let mut request = [0u8; MAX_FRAME_SIZE];
+        let length = match self.receive(&mut request, timeout_us)? {
+            Some(length) => length,
+            None => return Ok(false),
+        };
+
+        let mut response = [0u8; MAX_FRAME_SIZE];
+        if let Some(length) = slave.handle(&request[..length], map, &mut response) {
+            self.port.write(&response[..length])?;
+        }
+        Ok(true)
+    }
+
+    /// Returns the port
+    pub fn release(self) -> P {
+        self.port
+    }
+
+    // `None` if no frame started before the timeout
+    fn receive(
+        &mut self,
+        frame: &mut [u8; MAX_FRAME_SIZE],
+        timeout_us: u32,
+    ) -> Result<Option<usize>, Error> {
+        if self.port.read_timeout(&mut frame[..1], timeout_us)? == 0 {
+            return Ok(None);
+        }
+
+        // bytes are read one by one, so the silence at the end of the frame can be detected
+        let mut length = 1;
+        while length < MAX_FRAME_SIZE {
+            match self
+                .port
+                .read_timeout(&mut frame[length..length + 1], self.frame_gap_us)?
+            {
+                0 => break,
+                _ => length += 1,
+            }
+        }
+        Ok(Some(length))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::modbus::crc16;
+
+    const SLAVE: u8 = 1;
+    const TIMEOUT_US: u32 = 100_000;
+
+    // hands out the queued request, and keeps what the slave sends back
+    #[derive(Default)]
+    struct MockPort {
+        incoming: VecDeque<u8>,
+        written: Vec<u8>,
+        timeouts_us: Vec<u32>,
+    }
+
+    impl Port for MockPort {
+        fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+            self.written.extend_from_slice(bytes);
+            Ok(())
+        }
+
+        fn read_timeout(&mut self, buffer: &mut [u8], timeout_us: u32) -> Result<usize, Error> {
+            self.timeouts_us.push(timeout_us);
+            let mut length = 0;
+            for byte in buffer.iter_mut() {
+                match self.incoming.pop_front() {
+                    Some(incoming) => *byte = incoming,
+                    None => break,
+                }
+                length += 1;
+            }
+            Ok(length)
+        }
+    }
+
+    // appends the CRC
+    fn frame(bytes: &[u8]) -> Vec<u8> {
+        let mut frame = bytes.to_vec();
+        frame.extend_from_slice(&crc16(bytes).to_le_bytes());
+        frame
+    }
+
+    // sends `request` to a slave serving `registers`, returns the response
+    fn exchange(registers: &mut Registers, request: &[u8]) -> Vec<u8> {
+        let mut port = RtuPort::new(MockPort::default(), 19_200);
+        port.port.incoming.extend(request.iter().copied());
+        assert_eq!(
+            port.serve(&Slave::new(SLAVE), registers, TIMEOUT_US),
+            Ok(true)
+        );
+        port.release().written
+    }
+
+    fn registers() -> Registers {
+        Registers::new(Settings::default())
+    }
+
+    fn measured() -> Registers {
+        let mut registers = registers();
+        let data = SensorData {
+            co2: 812.5,
+            temperature: 21.46,
+            humidity: 45.5,
+        };
+        registers.measurement = Some((data, Category::II));
+        registers
+    }
+
+    #[test]
+    fn crc_of_a_known_frame() {
+        assert_eq!(
+            frame(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x0a]),
+            [0x01, 0x03, 0x00, 0x00, 0x00, 0x0a, 0xc5, 0xcd]
+        );
+    }
+
+    #[test]
+    fn read_holding_registers() {
+        let response = exchange(&mut registers(), &frame(&[SLAVE, 0x03, 0, 0, 0, 7]));
+        #[rustfmt::skip]
+        let expected = frame(&[
+            SLAVE, 0x03, 14,
+            0x03, 0xca, // 970 ppm
+            0x04, 0xc4, // 1220 ppm
+            0x06, 0xea, // 1770 ppm
+            0x01, 0xa4, // 420 ppm outdoors
+            0x00, 0x00, // no pressure
+            0x00, 0x00, // at sea level
+            0x00, 0x02, // every 2 s
+        ]);
+        assert_eq!(response, expected);
+    }
+
+    #[test]
+    fn read_input_registers() {
+        let response = exchange(&mut measured(), &frame(&[SLAVE, 0x04, 0, 0, 0, 2]));
+        // 812.5 as f32, high word first
+        assert_eq!(response, frame(&[SLAVE, 0x04, 4, 0x44, 0x4b, 0x20, 0x00]));
+
+        let response = exchange(&mut measured(), &frame(&[SLAVE, 0x04, 0, 6, 0, 4]));
+        #[rustfmt::skip]
+        let expected = frame(&[
+            SLAVE, 0x04, 8,
+            0x03, 0x2d, // 813 ppm
+            0x08, 0x62, // 21.46 °C
+            0x11, 0xc6, // 45.50 %
+            0x00, 0x01, // category II
+        ]);
+        assert_eq!(response, expected);
+    }
+
+    #[test]
+    fn input_registers_before_the_first_measurement() {
+        let response = exchange(&mut registers(), &frame(&[SLAVE, 0x04, 0, 8, 0, 2]));
+        assert_eq!(response, frame(&[SLAVE, 0x04, 4, 0xff, 0xff, 0xff, 0xff]));
+    }
+
+    #[test]
+    fn write_single_register() {
+        let mut registers = registers();
+        // outdoor air
+        let request = frame(&[SLAVE, 0x06, 0, 3, 0x01, 0x90]);
+        assert_eq!(exchange(&mut registers, &request), request);
+
+        assert_eq!(registers.settings.outdoor_co2, 400);
+        // the thresholds still follow the standard
+        assert_eq!(registers.settings.limits, None);
+    }
+
+    #[test]
+    fn write_multiple_registers() {
+        let mut registers = registers();
+        #[rustfmt::skip]
+        let request = frame(&[
+            SLAVE, 0x10, 0, 0, 0, 3, 6,
+            0x03, 0x84, // 900 ppm
+            0x04, 0xb0, // 1200 ppm
+            0x06, 0x40, // 1600 ppm
+        ]);
+        let response = exchange(&mut registers, &request);
+
+        assert_eq!(response, frame(&[SLAVE, 0x10, 0, 0, 0, 3]));
+        assert_eq!(registers.settings.limits, Some([480, 780, 1180]));
+        assert_eq!(registers.settings.thresholds(), [900, 1200, 1600]);
+    }
+
+    #[test]
+    fn exceptions() {
+        let cases: [(&[u8], [u8; 2]); 6] = [
+            // unknown function code
+            (&[SLAVE, 0x2b, 0, 0, 0, 1], [0xab, 0x01]),
+            // past the last holding register
+            (&[SLAVE, 0x03, 0, 6, 0, 2], [0x83, 0x02]),
+            // no registers at all
+            (&[SLAVE, 0x04, 0, 0, 0, 0], [0x84, 0x03]),
+            // interval out of range
+            (&[SLAVE, 0x06, 0, 6, 0, 0], [0x86, 0x03]),
+            // unmapped register
+            (&[SLAVE, 0x06, 0, 7, 0, 1], [0x86, 0x02]),
+            // descending thresholds
+            (
+                &[SLAVE, 0x10, 0, 0, 0, 2, 4, 0x04, 0xb0, 0x03, 0x84],
+                [0x90, 0x03],
+            ),
+        ];
+
+        for (request, [function, exception]) in cases.iter() {
+            let mut registers = registers();
+            let response = exchange(&mut registers, &frame(request));
+            assert_eq!(response, frame(&[SLAVE, *function, *exception]));
+            assert!(registers.settings == Settings::default());
+        }
+    }
+
+    #[test]
+    fn bad_crc_is_ignored() {
+        let mut registers = registers();
+        let mut request = frame(&[SLAVE, 0x06, 0, 3, 0x01, 0x90]);
+        request[7] ^= 0x01;
+
+        assert_eq!(exchange(&mut registers, &request), []);
+        assert!(registers.settings == Settings::default());
+    }
+
+    #[test]
+    fn other_slaves_are_ignored() {
+        let mut registers = registers();
+        let request = frame(&[SLAVE + 1, 0x06, 0, 3, 0x01, 0x90]);
+
+        assert_eq!(exchange(&mut registers, &request), []);
+        assert!(registers.settings == Settings::default());
+    }
+
+    #[test]
+    fn broadcast_writes_without_answer() {
+        let mut registers = registers();
+        let request = frame(&[0, 0x06, 0, 5, 0x02, 0x08]);
+
+        assert_eq!(exchange(&mut registers, &request), []);
+        assert_eq!(registers.settings.altitude, 520);
+    }
+
+    #[test]
+    fn no_request() {
+        let mut port = RtuPort::new(MockPort::default(), 19_200);
+        assert_eq!(
+            port.serve(&Slave::new(SLAVE), &mut registers(), TIMEOUT_US),
+            Ok(false)
+        );
+        assert_eq!(port.release().written, []);
+    }
+
+    #[test]
+    fn frame_gap_follows_the_baud_rate() {
+        let request = frame(&[SLAVE, 0x03, 0, 0, 0, 1]);
+        for (bits_per_second, gap_us) in [(9_600, 4_010), (19_200, 2_005), (115_200, 1_750)].iter()
+        {
+            let mut port = RtuPort::new(MockPort::default(), *bits_per_second);
+            port.port.incoming.extend(request.iter().copied());
+            port.serve(&Slave::new(SLAVE), &mut registers(), TIMEOUT_US)
+                .unwrap();
+
+            let timeouts_us = port.release().timeouts_us;
+            assert_eq!(timeouts_us[0], TIMEOUT_US);
+            // one read per byte, and one that finds the silence
+            assert_eq!(timeouts_us.len(), request.len() + 1);
+            assert!(timeouts_us[1..].iter().all(|timeout| timeout == gap_us));
+        }
+    }
+}
@@ -0,0 +1,222 @@
+// NDEF messages with text and URI records, the data format of NFC tags.
+//
+// A message is a sequence of records. Only short records are built, their payload is at most
+// 255 bytes:
+// | flags and TNF u8 | type length u8 | payload length u8 | type | payload |
+// The flags mark the first record of the message (MB, 0x80), the last one (ME, 0x40) and short
+// records (SR, 0x10). Both record types are well-known types of the NFC Forum, TNF 1:
+// * text, type `T`: | status u8 | language code | text |, the status byte holds the length of
+//   the language code, the text is UTF-8
+// * URI, type `U`: | prefix code u8 | rest of the URI |, the prefix code abbreviates the scheme,
+//   e.g. 4 for `https://`
+//
+// The module only depends on `core`, `nfc` puts the messages on the tag.
+
+/// Longest message
+pub const MAX_MESSAGE_SIZE: usize = 128;
+
+const MESSAGE_BEGIN: u8 = 0x80;
+const MESSAGE_END: u8 = 0x40;
+const SHORT_RECORD: u8 = 0x10;
+const TNF_WELL_KNOWN: u8 = 0x01;
+
+const TEXT: u8 = b'T';
+const URI: u8 = b'U';
+
+// the language code is limited to 63 bytes, the UTF-8 flag is 0
+const LANGUAGE_LENGTH_MASK: u8 = 0x3f;
+
+// URI prefixes and their codes, more specific prefixes first
+const URI_PREFIXES: [(&str, u8); 6] = [
+    ("http://www.", 0x01),
+    ("https://www.", 0x02),
+    ("http://", 0x03),
+    ("https://", 0x04),
+    ("tel:", 0x05),
+    ("mailto:", 0x06),
+];
+// the URI is stored as is
+const NO_PREFIX: u8 = 0x00;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NdefError {
+    /// the record doesn't fit into the message, or its payload is longer than 255 bytes
+    TooLong,
+    /// the language code is empty or longer than 63 bytes
+    InvalidLanguage,
+}
+
+/// NDEF message, built without heap
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Message {
+    bytes: [u8; MAX_MESSAGE_SIZE],
+    length: usize,
+    // start of the last record, it loses its ME flag when another one is appended
+    last_record: Option<usize>,
+}
+
+impl Message {
+    pub fn new() -> Self {
+        Message {
+            bytes: [0; MAX_MESSAGE_SIZE],
+            length: 0,
+            last_record: None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.length]
+    }
+
+    /// Appends a text record. `language` is the IANA language code of the text, e.g. `en`.
+    pub fn text(&mut self, language: &str, text: &str) -> Result<(), NdefError> {
+        let language_length = language.len();
+        if language_length == 0 || language_length > usize::from(LANGUAGE_LENGTH_MASK) {
+            return Err(NdefError::InvalidLanguage);
+        }
+        let status = language_length as u8;
+        self.record(TEXT, &[&[status], language.as_bytes(), text.as_bytes()])
+    }
+
+    /// Appends a URI record, e.g. of a dashboard with the history of the readings
+    pub fn uri(&mut self, uri: &str) -> Result<(), NdefError> {
+        let (code, rest) = URI_PREFIXES
+            .iter()
+            .find(|(prefix, _)| uri.starts_with(prefix))
+            .map(|(prefix, code)| (*code, &uri[prefix.len()..]))
+            .unwrap_or((NO_PREFIX, uri));
+        self.record(URI, &[&[code], rest.as_bytes()])
+    }
+
+    // the payload is given in parts, so it doesn't have to be assembled first
+    fn record(&mut self, record_type: u8, payload: &[&[u8]]) -> Result<(), NdefError> {
+        let payload_length: usize = payload.iter().map(|part| part.len()).sum();
+        if payload_length > usize::from(u8::MAX) {
+            return Err(NdefError::TooLong);
+        }
+        // header, type length, payload length and type
+        let start = self.length;
+        let end = start + 4 + payload_length;
+        if end > MAX_MESSAGE_SIZE {
+            return Err(NdefError::TooLong);
+        }
+
+        let mut flags = MESSAGE_END | SHORT_RECORD | TNF_WELL_KNOWN;
+        match self.last_record {
+            Some(last_record) => self.bytes[last_record] &= !MESSAGE_END,
+            None => flags |= MESSAGE_BEGIN,
+        }
+        self.bytes[start..start + 4].copy_from_slice(&[
+            flags,
+            1,
+            payload_length as u8,
+            record_type,
+        ]);
+        let mut position = start + 4;
+        for part in payload {
+            self.bytes[position..position + part.len()].copy_from_slice(part);
+            position += part.len();
+        }
+
+        self.length = end;
+        self.last_record = Some(start);
+        Ok(())
+    }
+}
+
+impl Default for Message {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_text_record() {
+        let mut message = Message::new();
+        message.text("en", "CO2 812 ppm").unwrap();
+
+        let mut expected = vec![0xd1, 0x01, 0x0e, b'T', 0x02, b'e', b'n'];
+        expected.extend_from_slice(b"CO2 812 ppm");
+        assert_eq!(message.as_bytes(), &expected[..]);
+    }
+
+    #[test]
+    fn text_and_uri_records() {
+        let mut message = Message::new();
+        message.text("en", "CO2 812 ppm").unwrap();
+        message.uri("https://example.com/co2").unwrap();
+
+        // MB on the first record, ME on the last one
+        let mut expected = vec![0x91, 0x01, 0x0e, b'T', 0x02, b'e', b'n'];
+        expected.extend_from_slice(b"CO2 812 ppm");
+        expected.extend_from_slice(&[0x51, 0x01, 0x10, b'U', 0x04]);
+        expected.extend_from_slice(b"example.com/co2");
+        assert_eq!(message.as_bytes(), &expected[..]);
+    }
+
+    #[test]
+    fn uri_prefixes() {
+        let cases = [
+            ("http://www.nordicsemi.com", 0x01, "nordicsemi.com"),
+            ("https://www.nordicsemi.com", 0x02, "nordicsemi.com"),
+            ("http://example.com", 0x03, "example.com"),
+            ("https://example.com", 0x04, "example.com"),
+            ("tel:+4930123456", 0x05, "+4930123456"),
+            (
+                "mailto:facilities@example.com",
+                0x06,
+                "facilities@example.com",
+            ),
+            ("urn:nfc:sn:co2", 0x00, "urn:nfc:sn:co2"),
+        ];
+
+        for (uri, code, rest) in cases.iter() {
+            let mut message = Message::new();
+            message.uri(uri).unwrap();
+
+            let mut expected = vec![0xd1, 0x01, rest.len() as u8 + 1, b'U', *code];
+            expected.extend_from_slice(rest.as_bytes());
+            assert_eq!(message.as_bytes(), &expected[..], "{}", uri);
+        }
+    }
+
+    #[test]
+    fn invalid_language() {
+        let mut message = Message::new();
+        assert_eq!(message.text("", "text"), Err(NdefError::InvalidLanguage));
+        let language = "x".repeat(64);
+        assert_eq!(
+            message.text(&language, "text"),
+            Err(NdefError::InvalidLanguage)
+        );
+        assert_eq!(message.as_bytes(), []);
+
+        assert_eq!(message.text(&language[..63], "text"), Ok(()));
+    }
+
+    #[test]
+    fn too_long() {
+        let mut message = Message::new();
+        // longer than a short record
+        let text = "x".repeat(300);
+        assert_eq!(message.text("en", &text), Err(NdefError::TooLong));
+
+        // longer than the message
+        assert_eq!(
+            message.text("en", &text[..MAX_MESSAGE_SIZE - 6]),
+            Err(NdefError::TooLong)
+        );
+        assert_eq!(message.text("en", &text[..MAX_MESSAGE_SIZE - 7]), Ok(()));
+        assert_eq!(message.as_bytes().len(), MAX_MESSAGE_SIZE);
+
+        // a record that doesn't fit leaves the message as it is
+        let full = message;
+        assert_eq!(message.uri("https://example.com"), Err(NdefError::TooLong));
+        assert_eq!(message, full);
+        assert_eq!(message.as_bytes()[0], 0xd1);
+    }
+}
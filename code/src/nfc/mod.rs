@@ -0,0 +1,125 @@
+// NFC-A Type 2 tag on the NFCT peripheral: a phone tapping the device reads the latest
+// measurement as NDEF message.
+//
+// The tag memory lives in RAM and is read only, pages of 4 bytes:
+// | 0-2 UID and lock bytes | 3 capability container | 4- NDEF message TLV, terminator TLV |
+// The UID is the tag header Nordic programs into the FICR. The NFCT answers the anticollision
+// itself; once it's selected, the tag answers READ with four pages from the requested one and
+// goes to sleep on HLTA. Writes and anything else are ignored.
+//
+// The reader expects an answer within a few hundred µs, so `handle_interrupt` runs in the NFCT
+// interrupt at a high priority. The antenna connects to NFC1 and NFC2, P0.09 and P0.10, which
+// can't be used as GPIOs at the same time.
+
+use core::fmt::Write;
+
+use crate::{
+    alerts::{Category, Standard},
+    co2_sensor::SensorData,
+    ndef::{Message, NdefError},
+    serial_report::Line,
+};
+
+#[cfg(target_os = "none")]
+mod tag;
+#[cfg(target_os = "none")]
+pub use tag::{NfcTag, FRAME_BUFFER_SIZE};
+
+/// Message with the measurement as text, e.g.
+/// `CO2 812 ppm, 21.5 °C, 45 % humidity, air quality II`, and an optional link, e.g. to a
+/// dashboard
+pub fn reading_message(
+    data: &SensorData,
+    category: Category,
+    standard: Standard,
+    uri: Option<&str>,
+) -> Result<Message, NdefError> {
+    let mut text = Line::new();
+    write!(
+        text,
+        "CO2 {:.0} ppm, {:.1} °C, {:.0} % humidity, air quality {}",
+        data.co2,
+        data.temperature,
+        data.humidity,
+        category.name(standard)
+    )
+    .map_err(|_| NdefError::TooLong)?;
+    if category.is_alarm() {
+        text.write_str(", please ventilate")
+            .map_err(|_| NdefError::TooLong)?;
+    }
+
+    let mut message = Message::new();
+    message.text("en", text.as_str())?;
+    if let Some(uri) = uri {
+        message.uri(uri)?;
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ndef::MAX_MESSAGE_SIZE;
+
+    // the text of the first record, after the language code `en`
+    fn text(message: &Message) -> &str {
+        let bytes = message.as_bytes();
+        let length = usize::from(bytes[2]) - 3;
+        core::str::from_utf8(&bytes[7..7 + length]).unwrap()
+    }
+
+    #[test]
+    fn reading() {
+        let data = SensorData {
+            co2: 812.4,
+            temperature: 21.46,
+            humidity: 45.5,
+        };
+        let message = reading_message(&data, Category::II, Standard::En16798, None).unwrap();
+
+        assert_eq!(
+            text(&message),
+            "CO2 812 ppm, 21.5 °C, 46 % humidity, air quality II"
+        );
+    }
+
+    #[test]
+    fn longest_reading_fits() {
+        // the ends of the measurement ranges of the SCD30, with the longest category name
+        let data = SensorData {
+            co2: 40_000.0,
+            temperature: -40.0,
+            humidity: 100.0,
+        };
+        let message = reading_message(
+            &data,
+            Category::IV,
+            Standard::En13779,
+            Some("https://example.com/co2"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            text(&message),
+            "CO2 40000 ppm, -40.0 °C, 100 % humidity, air quality IDA 4, please ventilate"
+        );
+        assert!(message.as_bytes().len() <= MAX_MESSAGE_SIZE);
+        assert!(message.as_bytes().ends_with(b"example.com/co2"));
+    }
+
+    #[test]
+    fn too_long_for_the_tag() {
+        let data = SensorData {
+            co2: 40_000.0,
+            temperature: -40.0,
+            humidity: 100.0,
+        };
+        let uri = format!("https://example.com/{}", "x".repeat(60));
+
+        assert_eq!(
+            reading_message(&data, Category::IV, Standard::En13779, Some(&uri)),
+            Err(NdefError::TooLong)
+        );
+    }
+}
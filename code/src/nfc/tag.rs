@@ -0,0 +1,168 @@
+// The tag on the NFCT peripheral, only on the board.
+
+use nrf52840_hal::pac::{FICR, NFCT};
+
+use crate::ndef::{Message, MAX_MESSAGE_SIZE};
+
+/// Size of the buffer EasyDMA receives commands into and sends responses from
+pub const FRAME_BUFFER_SIZE: usize = 16;
+
+const PAGE_SIZE: usize = 4;
+// UID, lock bytes and capability container
+const HEADER_SIZE: usize = 4 * PAGE_SIZE;
+// multiple of 8 that holds NDEF TLV and terminator around the longest message
+const DATA_AREA_SIZE: usize = (MAX_MESSAGE_SIZE + 3).div_ceil(8) * 8;
+const MEMORY_SIZE: usize = HEADER_SIZE + DATA_AREA_SIZE;
+
+// Type 2 tag commands
+const READ: u8 = 0x30;
+const HALT: u8 = 0x50;
+
+// capability container: NDEF magic number, version 1.0, size of the data area in 8 bytes,
+// read only
+const CAPABILITY_CONTAINER: [u8; PAGE_SIZE] = [0xe1, 0x10, (DATA_AREA_SIZE / 8) as u8, 0x0f];
+const CASCADE_TAG: u8 = 0x88;
+// all pages locked
+const LOCK_BYTES: [u8; 2] = [0xff, 0xff];
+
+// TLV blocks of the data area
+const NDEF_TLV: u8 = 0x03;
+const TERMINATOR_TLV: u8 = 0xfe;
+
+// SHORTS: activate when a field shows up, sense again when it's gone, receive after sending
+const SHORTS: u32 = 1 << 0 | 1 << 1 | 1 << 5;
+// INTEN: RXFRAMEEND, RXERROR and SELECTED
+const INTERRUPTS: u32 = 1 << 6 | 1 << 10 | 1 << 19;
+// SENSRES: bit frame SDD 00100 and a double size UID, like other Type 2 tags
+const SENS_RES: u32 = 0x04 | 1 << 6;
+// FRAMESTATUS.RX: CRC error, parity error and overrun
+const RX_ERRORS: u32 = 1 << 0 | 1 << 2 | 1 << 3;
+// the byte count of RXD.AMOUNT and TXD.AMOUNT starts after the bit count
+const AMOUNT_BYTES_SHIFT: u32 = 3;
+// RXD.AMOUNT counts the CRC-A of a received frame, which follows the command
+const CRC_SIZE: usize = 2;
+
+pub struct NfcTag {
+    nfct: NFCT,
+    memory: [u8; MEMORY_SIZE],
+    // EasyDMA uses it whenever a reader sends a command, so it must not move
+    buffer: &'static mut [u8; FRAME_BUFFER_SIZE],
+}
+
+impl NfcTag {
+    /// Configures the tag with an empty NDEF message and starts sensing for a field. The
+    /// application unmasks the NFCT interrupt.
+    pub fn new(nfct: NFCT, buffer: &'static mut [u8; FRAME_BUFFER_SIZE]) -> Self {
+        let uid = uid();
+        let mut memory = [0u8; MEMORY_SIZE];
+        memory[..3].copy_from_slice(&uid[..3]);
+        memory[3] = CASCADE_TAG ^ uid[0] ^ uid[1] ^ uid[2];
+        memory[4..8].copy_from_slice(&uid[3..]);
+        memory[8] = uid[3] ^ uid[4] ^ uid[5] ^ uid[6];
+        memory[10..12].copy_from_slice(&LOCK_BYTES);
+        memory[12..HEADER_SIZE].copy_from_slice(&CAPABILITY_CONTAINER);
+
+        // the NFCT sends the UID during the anticollision
+        nfct.nfcid1_2nd_last
+            .write(|w| unsafe { w.bits(u32::from_be_bytes([0, uid[0], uid[1], uid[2]])) });
+        nfct.nfcid1_last
+            .write(|w| unsafe { w.bits(u32::from_be_bytes([uid[3], uid[4], uid[5], uid[6]])) });
+        nfct.sensres.write(|w| unsafe { w.bits(SENS_RES) });
+
+        nfct.packetptr
+            .write(|w| unsafe { w.bits(buffer.as_ptr() as u32) });
+        nfct.maxlen
+            .write(|w| unsafe { w.bits(FRAME_BUFFER_SIZE as u32) });
+        nfct.shorts.write(|w| unsafe { w.bits(SHORTS) });
+        nfct.intenset.write(|w| unsafe { w.bits(INTERRUPTS) });
+
+        let mut tag = NfcTag {
+            nfct,
+            memory,
+            buffer,
+        };
+        tag.set_message(&Message::new());
+        tag.nfct.tasks_sense.write(|w| unsafe { w.bits(1) });
+        tag
+    }
+
+    /// Replaces the message a reader gets
+    pub fn set_message(&mut self, message: &Message) {
+        let bytes = message.as_bytes();
+        let data_area = &mut self.memory[HEADER_SIZE..];
+        for byte in data_area.iter_mut() {
+            *byte = 0;
+        }
+        // a message is short enough for the one byte length format
+        data_area[0] = NDEF_TLV;
+        data_area[1] = bytes.len() as u8;
+        data_area[2..2 + bytes.len()].copy_from_slice(bytes);
+        data_area[2 + bytes.len()] = TERMINATOR_TLV;
+    }
+
+    /// Handles the events of the NFCT, call it from the NFCT interrupt
+    pub fn handle_interrupt(&mut self) {
+        let nfct = &self.nfct;
+
+        // after the anticollision, the first command is received
+        if nfct.events_selected.read().bits() != 0 {
+            nfct.events_selected.write(|w| unsafe { w.bits(0) });
+            nfct.tasks_enablerxdata.write(|w| unsafe { w.bits(1) });
+        }
+
+        if nfct.events_rxerror.read().bits() != 0 {
+            nfct.events_rxerror.write(|w| unsafe { w.bits(0) });
+        }
+
+        if nfct.events_rxframeend.read().bits() != 0 {
+            nfct.events_rxframeend.write(|w| unsafe { w.bits(0) });
+
+            let status = nfct.framestatus.rx.read().bits();
+            if status & RX_ERRORS != 0 {
+                nfct.framestatus.rx.write(|w| unsafe { w.bits(status) });
+                nfct.tasks_enablerxdata.write(|w| unsafe { w.bits(1) });
+                return;
+            }
+            let amount = (nfct.rxd.amount.read().bits() >> AMOUNT_BYTES_SHIFT) as usize;
+            let length = amount.saturating_sub(CRC_SIZE);
+            self.respond(length.min(FRAME_BUFFER_SIZE));
+        }
+    }
+
+    // answers the command in the buffer
+    fn respond(&mut self, length: usize) {
+        match self.buffer[..length] {
+            [READ, page] if usize::from(page) * PAGE_SIZE < MEMORY_SIZE => {
+                // reading past the end wraps around, like on other tags
+                let start = usize::from(page) * PAGE_SIZE;
+                for (index, byte) in self.buffer.iter_mut().enumerate() {
+                    *byte = self.memory[(start + index) % MEMORY_SIZE];
+                }
+                let amount = (FRAME_BUFFER_SIZE as u32) << AMOUNT_BYTES_SHIFT;
+                self.nfct.txd.amount.write(|w| unsafe { w.bits(amount) });
+                self.nfct.tasks_starttx.write(|w| unsafe { w.bits(1) });
+            }
+            // the NFCT wakes up again on the next ALL_REQ
+            [HALT, 0x00] => self.nfct.tasks_gosleep.write(|w| unsafe { w.bits(1) }),
+            _ => {
+                defmt::debug!("Ignoring NFC command {=[u8]}", &self.buffer[..length]);
+                self.nfct.tasks_enablerxdata.write(|w| unsafe { w.bits(1) });
+            }
+        }
+    }
+
+    /// Stops the tag and returns the NFCT and the buffer
+    pub fn release(self) -> (NFCT, &'static mut [u8; FRAME_BUFFER_SIZE]) {
+        self.nfct.intenclr.write(|w| unsafe { w.bits(INTERRUPTS) });
+        self.nfct.tasks_disable.write(|w| unsafe { w.bits(1) });
+        (self.nfct, self.buffer)
+    }
+}
+
+// manufacturer ID and the first six bytes of the unique ID
+fn uid() -> [u8; 7] {
+    let ficr = unsafe { &*FICR::ptr() };
+    let [manufacturer, uid_1, uid_2, uid_3] = ficr.nfc.tagheader0.read().bits().to_le_bytes();
+    let [uid_4, uid_5, uid_6, _] = ficr.nfc.tagheader1.read().bits().to_le_bytes();
+    [manufacturer, uid_1, uid_2, uid_3, uid_4, uid_5, uid_6]
+}
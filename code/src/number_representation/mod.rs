@@ -1,3 +1,13 @@
+/// Rounds half away from zero, before a conversion with `as`, which truncates towards zero
+pub fn round(value: f32) -> f32 {
+    if value < 0.0 {
+        value - 0.5
+    } else {
+        value + 0.5
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
 pub enum Unit {
     Fahrenheit,
     Celsius,
@@ -0,0 +1,237 @@
+// Estimates how many people are in a room from the way the CO2 concentration changes.
+//
+// The room is treated as one well mixed box of air. People add CO2 to it, ventilation
+// replaces room air with outdoor air:
+//
+//     V * dC/dt = N * G - Q * (C - C_outdoor)
+//
+// V: room volume, C: CO2 concentration, N: number of people, G: CO2 generated per person,
+// Q: ventilation rate. Solving for N gives the estimate below. The rise rate dC/dt is taken
+// from consecutive measurements and smoothed, as single readings of the sensor are noisy.
+
+use crate::alerts::DEFAULT_OUTDOOR_CO2;
+
+/// CO2 exhaled by an adult doing office work (1.2 met), in litres per second
+pub const DEFAULT_CO2_PER_PERSON: f32 = 0.0052;
+// weight of the newest rise rate sample, between 0.0 (never changes) and 1.0 (no smoothing)
+const DEFAULT_SMOOTHING: f32 = 0.3;
+
+pub struct OccupancyEstimator {
+    volume_m3: f32,
+    air_changes_per_hour: f32,
+    co2_per_person: f32,
+    outdoor_co2: f32,
+    smoothing: f32,
+    last_co2: Option<f32>,
+    rise_rate: f32,
+}
+
+impl OccupancyEstimator {
+    /// `volume_m3`: volume of the room in cubic meters
+    /// `air_changes_per_hour`: how often the air of the room is replaced by ventilation per hour
+    pub fn new(volume_m3: f32, air_changes_per_hour: f32) -> Self {
+        OccupancyEstimator {
+            volume_m3,
+            air_changes_per_hour,
+            co2_per_person: DEFAULT_CO2_PER_PERSON,
+            outdoor_co2: DEFAULT_OUTDOOR_CO2,
+            smoothing: DEFAULT_SMOOTHING,
+            last_co2: None,
+            rise_rate: 0.0,
+        }
+    }
+
+    /// CO2 generated per person in litres per second. Higher for physical activity.
+    pub fn set_co2_per_person(&mut self, litres_per_second: f32) {
+        self.co2_per_person = litres_per_second;
+    }
+
+    pub fn set_outdoor_co2(&mut self, co2: f32) {
+        self.outdoor_co2 = co2;
+    }
+
+    /// Smoothing of the rise rate, between 0.0 and 1.0. Lower values react slower but are less noisy.
+    pub fn set_smoothing(&mut self, smoothing: f32) {
+        self.smoothing = smoothing.clamp(0.0, 1.0);
+    }
+
+    /// Smoothed rise rate of the CO2 concentration in ppm per second
+    pub fn rise_rate(&self) -> f32 {
+        self.rise_rate
+    }
+
+    /// Feeds a new CO2 reading in ppm, taken `elapsed_s` seconds after the previous one,
+    /// and returns the estimated number of people in the room.
+    pub fn update(&mut self, co2: f32, elapsed_s: f32) -> f32 {
+        if let Some(last_co2) = self.last_co2 {
+            if elapsed_s > 0.0 {
+                let rate = (co2 - last_co2) / elapsed_s;
+                self.rise_rate += self.smoothing * (rate - self.rise_rate);
+            }
+        }
+        self.last_co2 = Some(co2);
+
+        self.estimate(co2)
+    }
+
+    /// Estimated number of people in the room for the given CO2 reading and the current rise rate.
+    pub fn estimate(&self, co2: f32) -> f32 {
+        // ventilation rate per second, as fraction of the room volume
+        let ventilation = self.air_changes_per_hour / 3600.0;
+        // increase in ppm per second one person causes in this room
+        let ppm_per_person = self.co2_per_person * 1000.0 / self.volume_m3;
+
+        let people = (self.rise_rate + ventilation * (co2 - self.outdoor_co2)) / ppm_per_person;
+        people.max(0.0)
+    }
+
+    /// Forgets previous readings, e.g. after the sensor was restarted.
+    pub fn reset(&mut self) {
+        self.last_co2 = None;
+        self.rise_rate = 0.0;
+    }
+}
+
+/// Rounds an estimate to a whole number of people.
+pub fn head_count(estimate: f32) -> u32 {
+    // estimates are never negative, so adding 0.5 before truncating rounds to the nearest person
+    (estimate + 0.5) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VOLUME_M3: f32 = 50.0;
+    const AIR_CHANGES_PER_HOUR: f32 = 1.0;
+    const STEP_S: f32 = 60.0;
+
+    // CO2 of the well mixed room, every `STEP_S` from `start_co2` on, with `people` in it
+    fn trace(people: f32, start_co2: f32, steps: usize) -> Vec<f32> {
+        let ventilation = AIR_CHANGES_PER_HOUR / 3600.0;
+        let ppm_per_person = DEFAULT_CO2_PER_PERSON * 1000.0 / VOLUME_M3;
+        let steady_co2 = DEFAULT_OUTDOOR_CO2 + people * ppm_per_person / ventilation;
+        (0..steps)
+            .map(|step| {
+                let t = step as f32 * STEP_S;
+                steady_co2 + (start_co2 - steady_co2) * (-ventilation * t).exp()
+            })
+            .collect()
+    }
+
+    // the estimates after each reading
+    fn estimates(estimator: &mut OccupancyEstimator, readings: &[f32]) -> Vec<f32> {
+        readings
+            .iter()
+            .map(|co2| estimator.update(*co2, STEP_S))
+            .collect()
+    }
+
+    #[test]
+    fn rising() {
+        let mut estimator = OccupancyEstimator::new(VOLUME_M3, AIR_CHANGES_PER_HOUR);
+        let readings = trace(4.0, DEFAULT_OUTDOOR_CO2, 30);
+        let estimates = estimates(&mut estimator, &readings);
+
+        assert!(estimator.rise_rate() > 0.0);
+        // the rise rate is known from the second reading on, the smoothing takes a few more
+        for estimate in &estimates[10..] {
+            assert_eq!(head_count(*estimate), 4);
+        }
+    }
+
+    #[test]
+    fn falling() {
+        let mut estimator = OccupancyEstimator::new(VOLUME_M3, AIR_CHANGES_PER_HOUR);
+        let occupied = trace(4.0, DEFAULT_OUTDOOR_CO2, 300);
+        estimates(&mut estimator, &occupied);
+
+        let readings = trace(0.0, occupied[occupied.len() - 1], 30);
+        let estimates = estimates(&mut estimator, &readings);
+
+        assert!(estimator.rise_rate() < 0.0);
+        for estimate in &estimates[10..] {
+            assert_eq!(head_count(*estimate), 0);
+        }
+    }
+
+    #[test]
+    fn steady_state() {
+        let mut estimator = OccupancyEstimator::new(VOLUME_M3, AIR_CHANGES_PER_HOUR);
+        let steady_co2 = trace(3.0, DEFAULT_OUTDOOR_CO2, 1000)[999];
+        let estimates = estimates(&mut estimator, &[steady_co2; 10]);
+
+        assert_eq!(estimator.rise_rate(), 0.0);
+        for estimate in &estimates {
+            assert!((estimate - 3.0).abs() < 0.01, "{}", estimate);
+        }
+    }
+
+    #[test]
+    fn empty_room() {
+        let mut estimator = OccupancyEstimator::new(VOLUME_M3, AIR_CHANGES_PER_HOUR);
+        let estimates = estimates(&mut estimator, &[DEFAULT_OUTDOOR_CO2; 10]);
+
+        for estimate in &estimates {
+            assert_eq!(*estimate, 0.0);
+        }
+        // below the outdoor air, e.g. a sensor that reads low, is still nobody
+        assert_eq!(estimator.update(DEFAULT_OUTDOOR_CO2 - 50.0, STEP_S), 0.0);
+    }
+
+    #[test]
+    fn noisy() {
+        let mut estimator = OccupancyEstimator::new(VOLUME_M3, AIR_CHANGES_PER_HOUR);
+        estimator.set_smoothing(0.05);
+        let steady_co2 = trace(3.0, DEFAULT_OUTDOOR_CO2, 1000)[999];
+
+        // deterministic noise of up to ±10 ppm, like the SCD30 in stable air
+        let mut seed: u32 = 1;
+        let readings: Vec<f32> = (0..200)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let noise = f32::from((seed >> 16) as u16) / f32::from(u16::MAX) * 20.0 - 10.0;
+                steady_co2 + noise
+            })
+            .collect();
+        let estimates = estimates(&mut estimator, &readings);
+
+        for estimate in &estimates[50..] {
+            assert!(*estimate >= 0.0);
+            assert!((estimate - 3.0).abs() <= 1.0, "{}", estimate);
+        }
+    }
+
+    #[test]
+    fn reset_forgets_the_rise_rate() {
+        let mut estimator = OccupancyEstimator::new(VOLUME_M3, AIR_CHANGES_PER_HOUR);
+        estimates(&mut estimator, &trace(4.0, DEFAULT_OUTDOOR_CO2, 10));
+        estimator.reset();
+
+        assert_eq!(estimator.rise_rate(), 0.0);
+        // the first reading after a reset has no rise rate, a jump doesn't count as one
+        estimator.update(2000.0, STEP_S);
+        assert_eq!(estimator.rise_rate(), 0.0);
+    }
+
+    #[test]
+    fn smoothing_is_clamped() {
+        let mut estimator = OccupancyEstimator::new(VOLUME_M3, AIR_CHANGES_PER_HOUR);
+        estimator.set_smoothing(2.0);
+        estimator.update(500.0, STEP_S);
+        estimator.update(560.0, STEP_S);
+        assert_eq!(estimator.rise_rate(), 1.0);
+
+        estimator.set_smoothing(-1.0);
+        estimator.update(800.0, STEP_S);
+        assert_eq!(estimator.rise_rate(), 1.0);
+    }
+
+    #[test]
+    fn rounding_to_people() {
+        assert_eq!(head_count(0.0), 0);
+        assert_eq!(head_count(0.49), 0);
+        assert_eq!(head_count(0.5), 1);
+        assert_eq!(head_count(3.7), 4);
+    }
+}
@@ -0,0 +1,299 @@
+// Ambient pressure compensation of the CO2 sensor.
+//
+// The CO2 concentration the sensor reports depends on the air pressure. The sensor compensates
+// for it if it knows the pressure, which is sent with `Co2Sensor::start`. Without
+// a barometer, the sensor can compensate for the altitude instead, which misses the changes of
+// the weather, but is better than a fixed pressure.
+//
+// Resending the pressure restarts the measurement, so it's only sent if it changed noticeably:
+//
+// let now = clock::now();
+// if compensation.is_due(now) {
+//     compensation.update(&mut sensor, barometer.read_pressure().ok(), now)?;
+// }
+
+use crate::{
+    clock::{Duration, Instant},
+    co2_sensor::Co2Sensor,
+    Error,
+};
+
+/// change of the ambient pressure in mbar that is sent to the sensor
+pub const DEFAULT_THRESHOLD_MBAR: u16 = 2;
+/// time between two barometer readings
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What the sensor currently compensates for
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum Compensation {
+    /// ambient pressure in mbar
+    Pressure(u16),
+    /// height above sea level in m, the measurement runs without pressure
+    Altitude(u16),
+}
+
+pub struct PressureCompensation {
+    altitude: u16,
+    threshold: u16,
+    interval: Duration,
+    last_update: Option<Instant>,
+    // `None` until the first successful update
+    current: Option<Compensation>,
+}
+
+impl PressureCompensation {
+    /// `altitude` in m above sea level is used as long as there is no barometer reading
+    pub fn new(altitude: u16) -> Self {
+        PressureCompensation {
+            altitude,
+            threshold: DEFAULT_THRESHOLD_MBAR,
+            interval: DEFAULT_INTERVAL,
+            last_update: None,
+            current: None,
+        }
+    }
+
+    pub fn set_threshold(&mut self, threshold_mbar: u16) {
+        self.threshold = threshold_mbar;
+    }
+
+    pub fn set_interval(&mut self, interval: Duration) {
+        self.interval = interval;
+    }
+
+    pub fn current(&self) -> Option<Compensation> {
+        self.current
+    }
+
+    /// Whether the interval has passed since the last successful `update`
+    pub fn is_due(&self, now: Instant) -> bool {
+        match self.last_update {
+            Some(last_update) => now.duration_since(last_update) >= self.interval,
+            None => true,
+        }
+    }
+
+    /// Sends the ambient pressure in hPa to the sensor, if it changed by at least the threshold.
+    /// Without a pressure, e.g. because no barometer is fitted, the sensor compensates for the
+    /// altitude. If a pressure has been sent before, it is kept instead, as it is closer to the
+    /// truth than the altitude. The first update starts the measurement. `now` is the time of
+    /// the reading, from which the next one is due.
+    ///
+    /// Returns the new compensation, `None` if nothing had to be sent.
+    pub fn update<S: Co2Sensor>(
+        &mut self,
+        sensor: &mut S,
+        ambient_pressure: Option<f32>,
+        now: Instant,
+    ) -> Result<Option<Compensation>, Error> {
+        let next = match (ambient_pressure, self.current) {
+            // rounded to the closest mbar, 1 hPa is 1 mbar
+            (Some(pressure), _) => Compensation::Pressure((pressure + 0.5) as u16),
+            (None, Some(current @ Compensation::Pressure(_))) => current,
+            (None, _) => Compensation::Altitude(self.altitude),
+        };
+
+        let is_noticeable = match (self.current, next) {
+            (Some(Compensation::Pressure(current)), Compensation::Pressure(next)) => {
+                next.abs_diff(current) >= self.threshold
+            }
+            (current, next) => current != Some(next),
+        };
+
+        if is_noticeable {
+            match next {
+                Compensation::Pressure(pressure) => sensor.start(pressure)?,
+                Compensation::Altitude(altitude) => {
+                    sensor.set_altitude(altitude)?;
+                    sensor.start(0)?;
+                }
+            }
+            self.current = Some(next);
+        }
+        self.last_update = Some(now);
+
+        Ok(if is_noticeable { Some(next) } else { None })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::co2_sensor::SensorData;
+
+    #[derive(Debug, PartialEq)]
+    enum Call {
+        Start(u16),
+        SetAltitude(u16),
+    }
+
+    #[derive(Default)]
+    struct MockSensor {
+        calls: Vec<Call>,
+        fails: bool,
+    }
+
+    impl Co2Sensor for MockSensor {
+        fn start(&mut self, pressure: u16) -> Result<(), Error> {
+            self.calls.push(Call::Start(pressure));
+            if self.fails {
+                Err(Error::NotResponding)
+            } else {
+                Ok(())
+            }
+        }
+
+        fn set_altitude(&mut self, altitude: u16) -> Result<(), Error> {
+            self.calls.push(Call::SetAltitude(altitude));
+            Ok(())
+        }
+
+        fn data_ready(&mut self) -> Result<bool, Error> {
+            unimplemented!()
+        }
+
+        fn read(&mut self) -> Result<SensorData, Error> {
+            unimplemented!()
+        }
+
+        fn calibrate(&mut self, _: u16) -> Result<(), Error> {
+            unimplemented!()
+        }
+    }
+
+    fn at(secs: u64) -> Instant {
+        Instant::from_millis(secs * 1000)
+    }
+
+    #[test]
+    fn altitude_without_barometer() {
+        let mut compensation = PressureCompensation::new(520);
+        let mut sensor = MockSensor::default();
+
+        assert_eq!(
+            compensation.update(&mut sensor, None, at(0)),
+            Ok(Some(Compensation::Altitude(520)))
+        );
+        assert_eq!(sensor.calls, [Call::SetAltitude(520), Call::Start(0)]);
+
+        // nothing changed, nothing is sent
+        assert_eq!(compensation.update(&mut sensor, None, at(60)), Ok(None));
+        assert_eq!(sensor.calls.len(), 2);
+    }
+
+    #[test]
+    fn pressure_rounded_to_mbar() {
+        let mut compensation = PressureCompensation::new(520);
+        let mut sensor = MockSensor::default();
+
+        assert_eq!(
+            compensation.update(&mut sensor, Some(1012.5), at(0)),
+            Ok(Some(Compensation::Pressure(1013)))
+        );
+        assert_eq!(sensor.calls, [Call::Start(1013)]);
+        assert_eq!(compensation.current(), Some(Compensation::Pressure(1013)));
+    }
+
+    #[test]
+    fn threshold() {
+        let mut compensation = PressureCompensation::new(520);
+        let mut sensor = MockSensor::default();
+        compensation
+            .update(&mut sensor, Some(1013.0), at(0))
+            .unwrap();
+
+        assert_eq!(
+            compensation.update(&mut sensor, Some(1014.4), at(60)),
+            Ok(None)
+        );
+        assert_eq!(
+            compensation.update(&mut sensor, Some(1011.6), at(120)),
+            Ok(None)
+        );
+        assert_eq!(
+            compensation.update(&mut sensor, Some(1015.0), at(180)),
+            Ok(Some(Compensation::Pressure(1015)))
+        );
+        assert_eq!(
+            compensation.update(&mut sensor, Some(1013.0), at(240)),
+            Ok(Some(Compensation::Pressure(1013)))
+        );
+        assert_eq!(
+            sensor.calls,
+            [Call::Start(1013), Call::Start(1015), Call::Start(1013)]
+        );
+
+        compensation.set_threshold(5);
+        assert_eq!(
+            compensation.update(&mut sensor, Some(1017.0), at(300)),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn keeps_pressure_when_the_barometer_fails() {
+        let mut compensation = PressureCompensation::new(520);
+        let mut sensor = MockSensor::default();
+        compensation
+            .update(&mut sensor, Some(1013.0), at(0))
+            .unwrap();
+
+        assert_eq!(compensation.update(&mut sensor, None, at(60)), Ok(None));
+        assert_eq!(sensor.calls, [Call::Start(1013)]);
+        assert_eq!(compensation.current(), Some(Compensation::Pressure(1013)));
+    }
+
+    #[test]
+    fn barometer_after_altitude() {
+        let mut compensation = PressureCompensation::new(520);
+        let mut sensor = MockSensor::default();
+        compensation.update(&mut sensor, None, at(0)).unwrap();
+
+        assert_eq!(
+            compensation.update(&mut sensor, Some(955.2), at(60)),
+            Ok(Some(Compensation::Pressure(955)))
+        );
+        assert_eq!(
+            sensor.calls,
+            [Call::SetAltitude(520), Call::Start(0), Call::Start(955)]
+        );
+    }
+
+    #[test]
+    fn due_after_the_interval() {
+        let mut compensation = PressureCompensation::new(520);
+        let mut sensor = MockSensor::default();
+        assert!(compensation.is_due(at(0)));
+
+        compensation
+            .update(&mut sensor, Some(1013.0), at(10))
+            .unwrap();
+        assert!(!compensation.is_due(at(69)));
+        assert!(compensation.is_due(at(70)));
+
+        compensation.set_interval(Duration::from_secs(10));
+        assert!(compensation.is_due(at(20)));
+    }
+
+    #[test]
+    fn failed_update_is_retried() {
+        let mut compensation = PressureCompensation::new(520);
+        let mut sensor = MockSensor {
+            fails: true,
+            ..MockSensor::default()
+        };
+
+        assert_eq!(
+            compensation.update(&mut sensor, Some(1013.0), at(0)),
+            Err(Error::NotResponding)
+        );
+        assert_eq!(compensation.current(), None);
+        assert!(compensation.is_due(at(1)));
+
+        sensor.fails = false;
+        assert_eq!(
+            compensation.update(&mut sensor, Some(1013.0), at(1)),
+            Ok(Some(Compensation::Pressure(1013)))
+        );
+    }
+}
@@ -0,0 +1,86 @@
+// Async variant of the rgb led with its animations.
+// Like the blocking version, it is written for common anode rgb leds: a low pin lights the channel.
+
+use embedded_hal_1::digital::OutputPin;
+use embedded_hal_async::delay::DelayNs;
+
+use crate::Error;
+
+pub struct LEDColor<P, D> {
+    r: P,
+    g: P,
+    b: P,
+    delay: D,
+}
+
+impl<P, D> LEDColor<P, D>
+where
+    P: OutputPin,
+    D: DelayNs,
+{
+    /// The pins have to be configured as outputs
+    pub fn init(led_red: P, led_blue: P, led_green: P, delay: D) -> Self {
+        LEDColor {
+            r: led_red,
+            b: led_blue,
+            g: led_green,
+            delay,
+        }
+    }
+
+    // `true` lights the channel
+    fn set(&mut self, red: bool, green: bool, blue: bool) -> Result<(), Error> {
+        self.r.set_state((!red).into()).map_err(|_| Error::Pin)?;
+        self.g.set_state((!green).into()).map_err(|_| Error::Pin)?;
+        self.b.set_state((!blue).into()).map_err(|_| Error::Pin)
+    }
+
+    pub fn off(&mut self) -> Result<(), Error> {
+        self.set(false, false, false)
+    }
+
+    pub fn blue(&mut self) -> Result<(), Error> {
+        self.set(false, false, true)
+    }
+
+    pub fn red(&mut self) -> Result<(), Error> {
+        self.set(true, false, false)
+    }
+
+    pub fn green(&mut self) -> Result<(), Error> {
+        self.set(false, true, false)
+    }
+
+    pub fn yellow(&mut self) -> Result<(), Error> {
+        self.set(true, true, false)
+    }
+
+    pub fn pink(&mut self) -> Result<(), Error> {
+        self.set(true, false, true)
+    }
+
+    pub fn light_blue(&mut self) -> Result<(), Error> {
+        self.set(false, true, true)
+    }
+
+    pub fn white(&mut self) -> Result<(), Error> {
+        self.set(true, true, true)
+    }
+
+    // blinks between two colors
+    pub async fn blinky(&mut self) -> Result<(), Error> {
+        self.red()?;
+        self.delay.delay_ms(1000).await;
+        self.blue()?;
+        self.delay.delay_ms(1000).await;
+        Ok(())
+    }
+
+    pub async fn blink_red(&mut self) -> Result<(), Error> {
+        self.red()?;
+        self.delay.delay_ms(500).await;
+        self.off()?;
+        self.delay.delay_ms(500).await;
+        Ok(())
+    }
+}
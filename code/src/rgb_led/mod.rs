@@ -1,5 +1,7 @@
-use embedded_hal::blocking::delay::DelayMs;
+#[cfg(target_os = "none")]
+use embedded_hal::{blocking::delay::DelayMs, digital::v2::PinState};
 
+#[cfg(target_os = "none")]
 use nrf52840_hal::{
     gpio::{Level, Output, Pin, PushPull},
     pac::TIMER0,
@@ -8,81 +10,112 @@ use nrf52840_hal::{
     Timer,
 };
 
-// This module is written for common anode rgb leds. For common cathode rgb leds, switch high and low.
+#[cfg(target_os = "none")]
+use crate::Error;
+
+#[cfg(feature = "async")]
+pub mod asynch;
+
+/// How the LED is wired: a common anode LED lights up if its pin is low, a common cathode LED if
+/// its pin is high
+#[derive(Clone, Copy, Debug, PartialEq, defmt::Format)]
+pub enum Polarity {
+    CommonAnode,
+    CommonCathode,
+}
+
+#[cfg(target_os = "none")]
 pub struct LEDColor {
     r: Pin<Output<PushPull>>,
     g: Pin<Output<PushPull>>,
     b: Pin<Output<PushPull>>,
+    polarity: Polarity,
 }
 
+#[cfg(target_os = "none")]
 impl LEDColor {
+    /// Common anode LED
     pub fn init<Mode>(led_red: Pin<Mode>, led_blue: Pin<Mode>, led_green: Pin<Mode>) -> Self {
+        LEDColor::init_with_polarity(led_red, led_blue, led_green, Polarity::CommonAnode)
+    }
+
+    pub fn init_with_polarity<Mode>(
+        led_red: Pin<Mode>,
+        led_blue: Pin<Mode>,
+        led_green: Pin<Mode>,
+        polarity: Polarity,
+    ) -> Self {
+        // starts with the LED off, `Level` isn't `Copy`
+        let off = || match polarity {
+            Polarity::CommonAnode => Level::High,
+            Polarity::CommonCathode => Level::Low,
+        };
         LEDColor {
-            r: led_red.into_push_pull_output(Level::High),
-            b: led_blue.into_push_pull_output(Level::High),
-            g: led_green.into_push_pull_output(Level::High),
+            r: led_red.into_push_pull_output(off()),
+            b: led_blue.into_push_pull_output(off()),
+            g: led_green.into_push_pull_output(off()),
+            polarity,
         }
     }
 
-    pub fn off(&mut self) {
-        self.r.set_high().unwrap();
-        self.b.set_high().unwrap();
-        self.g.set_high().unwrap();
+    pub fn off(&mut self) -> Result<(), Error> {
+        self.set(false, false, false)
+    }
+
+    pub fn blue(&mut self) -> Result<(), Error> {
+        self.set(false, false, true)
     }
 
-    pub fn blue(&mut self) {
-        self.r.set_high().unwrap();
-        self.b.set_low().unwrap();
-        self.g.set_high().unwrap();
+    pub fn red(&mut self) -> Result<(), Error> {
+        self.set(true, false, false)
     }
 
-    pub fn red(&mut self) {
-        self.r.set_low().unwrap();
-        self.b.set_high().unwrap();
-        self.g.set_high().unwrap();
+    pub fn green(&mut self) -> Result<(), Error> {
+        self.set(false, true, false)
     }
 
-    pub fn green(&mut self) {
-        self.r.set_high().unwrap();
-        self.b.set_high().unwrap();
-        self.g.set_low().unwrap();
+    pub fn yellow(&mut self) -> Result<(), Error> {
+        self.set(true, true, false)
     }
 
-    pub fn yellow(&mut self) {
-        self.r.set_low().unwrap();
-        self.b.set_high().unwrap();
-        self.g.set_low().unwrap();
+    pub fn pink(&mut self) -> Result<(), Error> {
+        self.set(true, false, true)
     }
 
-    pub fn pink(&mut self) {
-        self.r.set_low().unwrap();
-        self.b.set_low().unwrap();
-        self.g.set_high().unwrap();
+    pub fn light_blue(&mut self) -> Result<(), Error> {
+        self.set(false, true, true)
     }
 
-    pub fn light_blue(&mut self) {
-        self.r.set_high().unwrap();
-        self.b.set_low().unwrap();
-        self.g.set_low().unwrap();
+    pub fn white(&mut self) -> Result<(), Error> {
+        self.set(true, true, true)
     }
 
-    pub fn white(&mut self) {
-        self.r.set_low().unwrap();
-        self.b.set_low().unwrap();
-        self.g.set_low().unwrap();
+    // switches each color on or off
+    fn set(&mut self, red: bool, green: bool, blue: bool) -> Result<(), Error> {
+        let polarity = self.polarity;
+        let level = |on: bool| match (polarity, on) {
+            (Polarity::CommonAnode, true) | (Polarity::CommonCathode, false) => PinState::Low,
+            (Polarity::CommonAnode, false) | (Polarity::CommonCathode, true) => PinState::High,
+        };
+        self.r.set_state(level(red))?;
+        self.g.set_state(level(green))?;
+        self.b.set_state(level(blue))?;
+        Ok(())
     }
     // blinks between two colors
-    pub fn blinky(&mut self, timer: &mut Timer<TIMER0, OneShot>) {
-        self.red();
+    pub fn blinky(&mut self, timer: &mut Timer<TIMER0, OneShot>) -> Result<(), Error> {
+        self.red()?;
         timer.delay_ms(1000_u32);
-        self.blue();
+        self.blue()?;
         timer.delay_ms(1000_u32);
+        Ok(())
     }
 
-    pub fn blink_red(&mut self, timer: &mut Timer<TIMER0, OneShot>) {
-        self.red();
+    pub fn blink_red(&mut self, timer: &mut Timer<TIMER0, OneShot>) -> Result<(), Error> {
+        self.red()?;
         timer.delay_ms(500_u32);
-        self.off();
+        self.off()?;
         timer.delay_ms(500_u32);
+        Ok(())
     }
 }
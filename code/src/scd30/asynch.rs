@@ -0,0 +1,100 @@
+// Async variant of the SCD30 driver, for executors like embassy.
+// Generic over the `embedded-hal-async` traits, so it works with any HAL that implements them.
+
+use embedded_hal_async::{delay::DelayNs, i2c::I2c};
+
+use super::{
+    check_pressure, checked_words, command_with_argument, parse_data_ready, parse_firmware_version,
+    parse_measurement, SensorData, DEFAULT_ADDRESS, GET_DATA_READY, GET_FIRMWARE_VERSION,
+    MAX_RESPONSE_WORDS, READ_DELAY_US, READ_MEASUREMENT, START_CONTINUOUS_MEASUREMENT,
+};
+use crate::{error::from_i2c, Error};
+
+// interval between two data ready requests in `wait_data_ready`
+const DATA_READY_POLL_MS: u32 = 100;
+
+pub struct SCD30<I2C, D> {
+    i2c: I2C,
+    delay: D,
+}
+
+impl<I2C, D> SCD30<I2C, D>
+where
+    I2C: I2c,
+    D: DelayNs,
+{
+    pub fn init(i2c: I2C, delay: D) -> Self {
+        SCD30 { i2c, delay }
+    }
+
+    pub async fn get_firmware_version(&mut self) -> Result<[u8; 2], Error> {
+        let mut words = [0u16; 1];
+
+        self.request(&GET_FIRMWARE_VERSION, &mut words).await?;
+
+        Ok(parse_firmware_version(&words))
+    }
+
+    pub async fn start_continuous_measurement(&mut self, pressure: u16) -> Result<(), Error> {
+        check_pressure(pressure)?;
+        let command = command_with_argument(START_CONTINUOUS_MEASUREMENT, pressure);
+
+        self.write(&command).await
+    }
+
+    pub async fn data_ready(&mut self) -> Result<bool, Error> {
+        let mut words = [0u16; 1];
+
+        self.request(&GET_DATA_READY, &mut words).await?;
+
+        Ok(parse_data_ready(&words))
+    }
+
+    /// Returns once the sensor has a new measurement. Other tasks can run while waiting.
+    pub async fn wait_data_ready(&mut self) -> Result<(), Error> {
+        while !self.data_ready().await? {
+            self.delay.delay_ms(DATA_READY_POLL_MS).await;
+        }
+        Ok(())
+    }
+
+    pub async fn read_measurement(&mut self) -> Result<SensorData, Error> {
+        let mut words = [0u16; MAX_RESPONSE_WORDS];
+
+        self.request(&READ_MEASUREMENT, &mut words).await?;
+
+        Ok(parse_measurement(&words))
+    }
+
+    // the response has three bytes per word, the CRC included
+    async fn request(&mut self, command: &[u8], words: &mut [u16]) -> Result<(), Error> {
+        let mut rd_buffer = [0u8; 3 * MAX_RESPONSE_WORDS];
+        let rd_buffer = &mut rd_buffer[..3 * words.len()];
+
+        self.write(command).await?;
+        self.read(rd_buffer).await?;
+
+        checked_words(rd_buffer, words)
+    }
+
+    async fn write(&mut self, command: &[u8]) -> Result<(), Error> {
+        self.i2c
+            .write(DEFAULT_ADDRESS, command)
+            .await
+            .map_err(from_i2c)
+    }
+
+    async fn read(&mut self, rd_buffer: &mut [u8]) -> Result<(), Error> {
+        // the sensor needs time between command and response
+        self.delay.delay_us(READ_DELAY_US).await;
+        self.i2c
+            .read(DEFAULT_ADDRESS, rd_buffer)
+            .await
+            .map_err(from_i2c)
+    }
+
+    /// Returns the bus and the delay
+    pub fn release(self) -> (I2C, D) {
+        (self.i2c, self.delay)
+    }
+}
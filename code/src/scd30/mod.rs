@@ -1,93 +1,705 @@
+// Driver for the Sensirion SCD30.
+//
+// The sensor is reached through a `Transport`: I2C on any `Bus`, or its Modbus interface on a
+// UARTE, see `modbus`. The transport sends commands and returns the words of the responses, the
+// driver on top is the same for both.
+
+use core::ops::RangeInclusive;
+
 use crc_all::Crc;
 
-use nrf52840_hal::twim::{Error, Instance, Twim};
-pub struct SensorData {
-    pub co2: f32,
-    pub temperature: f32,
-    pub humidity: f32,
-}
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+pub use crate::co2_sensor::{Event, SensorData};
+use crate::{clock::CycleDelay, co2_sensor::Co2Sensor, error::BusError, i2c::Bus, Error};
+
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod modbus;
+
+pub use modbus::ModbusTransport;
+
 pub const DEFAULT_ADDRESS: u8 = 0x61;
-pub struct SCD30<T: Instance>(Twim<T>);
 
+// command codes, see the SCD30 interface description
+const GET_FIRMWARE_VERSION: [u8; 2] = [0xd1, 0x00];
+const START_CONTINUOUS_MEASUREMENT: [u8; 2] = [0x00, 0x10];
+const GET_DATA_READY: [u8; 2] = [0x02, 0x02];
+const READ_MEASUREMENT: [u8; 2] = [0x03, 0x00];
+const SOFT_RESET: [u8; 2] = [0xd3, 0x04];
+const SET_ALTITUDE_COMPENSATION: [u8; 2] = [0x51, 0x02];
+const SET_FORCED_RECALIBRATION: [u8; 2] = [0x52, 0x04];
+
+// longest response, the measurement
+const MAX_RESPONSE_WORDS: usize = 6;
+
+// after this many failed transfers in a row, the bus is recovered and the sensor reset
+const MAX_CONSECUTIVE_FAILURES: u8 = 3;
+
+// the sensor needs this long after a command, before the response can be read
+const READ_DELAY_US: u32 = 3_000;
+// longest the sensor may be busy, e.g. during its daily self calibration: it stretches the clock
+// or doesn't acknowledge meanwhile
+const MAX_BUSY_MS: u32 = 150;
+
+// ambient pressure compensation in mbar, 0 disables it
+const VALID_PRESSURE_MBAR: RangeInclusive<u16> = 700..=1400;
+// reference CO2 concentration for the forced recalibration in ppm
+const VALID_REFERENCE_PPM: RangeInclusive<u16> = 400..=2000;
+
+/// Commands of the SCD30 the driver uses
+#[derive(Clone, Copy, PartialEq, defmt::Format)]
+pub enum Command {
+    GetFirmwareVersion,
+    StartContinuousMeasurement,
+    GetDataReady,
+    ReadMeasurement,
+    SoftReset,
+    SetAltitudeCompensation,
+    SetForcedRecalibration,
+}
+
+impl Command {
+    /// I2C command code
+    pub fn code(self) -> [u8; 2] {
+        match self {
+            Command::GetFirmwareVersion => GET_FIRMWARE_VERSION,
+            Command::StartContinuousMeasurement => START_CONTINUOUS_MEASUREMENT,
+            Command::GetDataReady => GET_DATA_READY,
+            Command::ReadMeasurement => READ_MEASUREMENT,
+            Command::SoftReset => SOFT_RESET,
+            Command::SetAltitudeCompensation => SET_ALTITUDE_COMPENSATION,
+            Command::SetForcedRecalibration => SET_FORCED_RECALIBRATION,
+        }
+    }
+}
+
+/// How the driver talks to the sensor
+pub trait Transport {
+    /// Sends a command, with its argument if it takes one
+    fn command(&mut self, command: Command, argument: Option<u16>) -> Result<(), Error>;
+
+    /// Sends a command and reads the words of its response into `words`. `delay` waits for the
+    /// sensor between the two, if the transport needs it.
+    fn request<D: DelayUs<u32>>(
+        &mut self,
+        command: Command,
+        words: &mut [u16],
+        delay: &mut D,
+    ) -> Result<(), Error>;
+
+    /// Frees the connection after repeated failures, e.g. an I2C bus blocked by the sensor
+    fn recover(&mut self) -> Result<(), Error>;
+}
+
+// I2C: the command code, followed by the argument and its CRC. The response is read separately.
+impl<B: Bus> Transport for B {
+    fn command(&mut self, command: Command, argument: Option<u16>) -> Result<(), Error> {
+        match argument {
+            Some(argument) => self.write(
+                DEFAULT_ADDRESS,
+                &command_with_argument(command.code(), argument),
+            ),
+            None => self.write(DEFAULT_ADDRESS, &command.code()),
+        }
+    }
+
+    fn request<D: DelayUs<u32>>(
+        &mut self,
+        command: Command,
+        words: &mut [u16],
+        delay: &mut D,
+    ) -> Result<(), Error> {
+        let mut rd_buffer = [0u8; 3 * MAX_RESPONSE_WORDS];
+        let rd_buffer = &mut rd_buffer[..3 * words.len()];
+
+        self.write(DEFAULT_ADDRESS, &command.code())?;
+        delay.delay_us(READ_DELAY_US);
+        self.read(DEFAULT_ADDRESS, rd_buffer)?;
+
+        checked_words(rd_buffer, words)
+    }
+
+    fn recover(&mut self) -> Result<(), Error> {
+        Bus::recover(self)
+    }
+}
+
+/// How the driver copes with the timing of the sensor
+#[derive(Clone, Copy, PartialEq, defmt::Format)]
+pub enum Timing {
+    /// waits between command and response as the datasheet requires
+    Datasheet,
+    /// additionally retries a NACKed transfer once, after the longest time the sensor may be
+    /// busy. The clock stretching of the sensor is left to the bus master; use this if the
+    /// sensor doesn't acknowledge while it is busy.
+    RetryOnNack,
+    /// for bus masters that give up on long clock stretching, e.g. above 100 kHz: waits the
+    /// longest time the sensor may be busy between command and response, so the response is
+    /// ready and the sensor doesn't stretch the clock while it's read. Retries a NACKed transfer
+    /// like `RetryOnNack`.
+    ClockStretchTolerant,
+}
+
+pub struct SCD30<T: Transport, D = CycleDelay> {
+    transport: T,
+    delay: D,
+    timing: Timing,
+    // pressure of the last `start_continuous_measurement`, `None` if it was never started
+    pressure: Option<u16>,
+    consecutive_failures: u8,
+    restart_pending: bool,
+    event: Option<Event>,
+}
+
+#[cfg(target_os = "none")]
 impl<T> SCD30<T>
 where
-    T: Instance,
+    T: Transport,
 {
-    pub fn init(i2c2: Twim<T>) -> Self {
-        SCD30(i2c2)
+    /// Driver that waits by counting CPU cycles. `transport` is an I2C `Bus`, or a
+    /// `ModbusTransport`.
+    pub fn init(transport: T) -> Self {
+        SCD30::init_with_delay(transport, CycleDelay)
     }
+}
 
-    pub fn get_firmware_version(&mut self) -> Result<[u8; 2], Error> {
-        let command: [u8; 2] = [0xd1, 0x00];
-        let mut rd_buffer = [0u8; 2];
+impl<T, D> SCD30<T, D>
+where
+    T: Transport,
+    D: DelayUs<u32> + DelayMs<u32>,
+{
+    /// Driver that waits with the given delay provider, e.g. a `Timer`
+    pub fn init_with_delay(transport: T, delay: D) -> Self {
+        SCD30 {
+            transport,
+            delay,
+            timing: Timing::Datasheet,
+            pressure: None,
+            consecutive_failures: 0,
+            restart_pending: false,
+            event: None,
+        }
+    }
 
-        self.0.write(DEFAULT_ADDRESS, &command)?;
-        self.0.read(DEFAULT_ADDRESS, &mut rd_buffer)?;
+    pub fn get_firmware_version(&mut self) -> Result<[u8; 2], Error> {
+        let mut words = [0u16; 1];
 
-        let major = u8::from_be(rd_buffer[0]);
-        let minor = u8::from_be(rd_buffer[1]);
+        self.request(Command::GetFirmwareVersion, &mut words)?;
 
-        Ok([major, minor])
+        Ok(parse_firmware_version(&words))
     }
 
     pub fn start_continuous_measurement(&mut self, pressure: u16) -> Result<(), Error> {
-        // command bytes
-        let mut command: [u8; 5] = [0x00, 0x10, 0x00, 0x00, 0x00];
-        let argument_bytes = &pressure.to_be_bytes();
+        check_pressure(pressure)?;
+        defmt::info!("Starting the measurement at {=u16} mbar", pressure);
+
+        self.command(Command::StartContinuousMeasurement, Some(pressure))?;
+        self.pressure = Some(pressure);
+        self.restart_pending = false;
+
+        Ok(())
+    }
+
+    /// Height above sea level in m, used instead of the ambient pressure while the measurement
+    /// runs with a pressure of 0. The sensor keeps the value across restarts.
+    pub fn set_altitude_compensation(&mut self, altitude: u16) -> Result<(), Error> {
+        self.command(Command::SetAltitudeCompensation, Some(altitude))
+    }
 
-        command[2] = argument_bytes[0];
-        command[3] = argument_bytes[1];
+    pub fn data_ready(&mut self) -> Result<bool, Error> {
+        let mut words = [0u16; 1];
 
-        let mut crc = Crc::<u8>::new(0x31, 8, 0xff, 0x00, false);
+        self.restart_if_pending()?;
+        self.request(Command::GetDataReady, &mut words)?;
 
-        defmt::info!("{:?}", command);
+        Ok(parse_data_ready(&words))
+    }
 
-        crc.update(&pressure.to_be_bytes());
-        command[4] = crc.finish();
-        defmt::info!("{:?}", command);
+    pub fn read_measurement(&mut self) -> Result<SensorData, Error> {
+        let mut words = [0u16; MAX_RESPONSE_WORDS];
 
-        self.0.write(DEFAULT_ADDRESS, &command)?;
+        self.restart_if_pending()?;
+        self.request(Command::ReadMeasurement, &mut words)?;
 
+        Ok(parse_measurement(&words))
+    }
+
+    /// Sets the calibration so that the current measurement is `reference` ppm.
+    /// The sensor has to run continuously for at least 2 minutes before.
+    pub fn set_forced_recalibration(&mut self, reference: u16) -> Result<(), Error> {
+        if !VALID_REFERENCE_PPM.contains(&reference) {
+            return Err(Error::InvalidArgument);
+        }
+        self.command(Command::SetForcedRecalibration, Some(reference))
+    }
+
+    /// Restarts the sensor. A running measurement stops.
+    pub fn soft_reset(&mut self) -> Result<(), Error> {
+        self.command(Command::SoftReset, None)?;
+        self.pressure = None;
         Ok(())
     }
 
-    pub fn data_ready(&mut self) -> Result<bool, Error> {
-        let command: [u8; 2] = [0x02, 0x02];
-        let mut rd_buffer = [0u8; 3];
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+    }
 
-        self.0.write(DEFAULT_ADDRESS, &command)?;
-        self.0.read(DEFAULT_ADDRESS, &mut rd_buffer)?;
+    /// Returns the transport and the delay provider
+    pub fn release(self) -> (T, D) {
+        (self.transport, self.delay)
+    }
 
-        Ok(u16::from_be_bytes([rd_buffer[0], rd_buffer[1]]) == 1)
+    fn command(&mut self, command: Command, argument: Option<u16>) -> Result<(), Error> {
+        let mut result = self.transport.command(command, argument);
+        if self.should_retry(&result) {
+            self.delay.delay_ms(MAX_BUSY_MS);
+            result = self.transport.command(command, argument);
+        }
+        self.track(result)
     }
 
-    pub fn read_measurement(&mut self) -> Result<SensorData, Error> {
-        let command: [u8; 2] = [0x03, 0x00];
-        let mut rd_buffer = [0u8; 18];
-
-        self.0.write(DEFAULT_ADDRESS, &command)?;
-        self.0.read(DEFAULT_ADDRESS, &mut rd_buffer)?;
-
-        let data = SensorData {
-            co2: f32::from_bits(u32::from_be_bytes([
-                rd_buffer[0],
-                rd_buffer[1],
-                rd_buffer[3],
-                rd_buffer[4],
-            ])),
-            temperature: f32::from_bits(u32::from_be_bytes([
-                rd_buffer[6],
-                rd_buffer[7],
-                rd_buffer[9],
-                rd_buffer[10],
-            ])),
-            humidity: f32::from_bits(u32::from_be_bytes([
-                rd_buffer[12],
-                rd_buffer[13],
-                rd_buffer[15],
-                rd_buffer[16],
-            ])),
-        };
-        Ok(data)
+    fn request(&mut self, command: Command, words: &mut [u16]) -> Result<(), Error> {
+        let mut result = self.exchange(command, words);
+        if self.should_retry(&result) {
+            self.delay.delay_ms(MAX_BUSY_MS);
+            result = self.exchange(command, words);
+        }
+        self.track(result)
+    }
+
+    fn exchange(&mut self, command: Command, words: &mut [u16]) -> Result<(), Error> {
+        match self.timing {
+            Timing::ClockStretchTolerant => {
+                let mut delay = UntilReady(&mut self.delay);
+                self.transport.request(command, words, &mut delay)
+            }
+            Timing::Datasheet | Timing::RetryOnNack => {
+                self.transport.request(command, words, &mut self.delay)
+            }
+        }
+    }
+
+    // a sensor that is busy doesn't acknowledge
+    fn should_retry(&self, result: &Result<(), Error>) -> bool {
+        self.timing != Timing::Datasheet
+            && matches!(
+                result,
+                Err(Error::Bus(BusError::AddressNack | BusError::DataNack))
+            )
+    }
+
+    // counts failed transfers, and recovers once there have been too many in a row
+    fn track(&mut self, result: Result<(), Error>) -> Result<(), Error> {
+        match result {
+            Ok(()) => self.consecutive_failures = 0,
+            Err(Error::Bus(_) | Error::NotResponding | Error::Serial) => {
+                self.consecutive_failures += 1;
+                if self.consecutive_failures >= MAX_CONSECUTIVE_FAILURES {
+                    self.recover();
+                }
+            }
+            Err(_) => {}
+        }
+        result
+    }
+
+    // A sensor that was unplugged or browned out may hold SDA low. Once the bus is free, the
+    // sensor is reset, and the measurement is restarted with the next request. This gives the
+    // sensor time to boot.
+    fn recover(&mut self) {
+        self.consecutive_failures = 0;
+        if self.transport.recover().is_err() {
+            return;
+        }
+        // if the sensor doesn't answer, it is still unplugged: there is nothing to reset
+        self.transport.command(Command::SoftReset, None).ok();
+        self.restart_pending = self.pressure.is_some();
+        self.event = Some(Event::BusRecovered);
+    }
+
+    fn restart_if_pending(&mut self) -> Result<(), Error> {
+        if let (true, Some(pressure)) = (self.restart_pending, self.pressure) {
+            self.command(Command::StartContinuousMeasurement, Some(pressure))?;
+            self.restart_pending = false;
+            self.event = Some(Event::MeasurementRestarted);
+        }
+        Ok(())
+    }
+}
+
+// stretches the delay between command and response to the longest time the sensor may be busy
+struct UntilReady<'a, D>(&'a mut D);
+
+impl<D: DelayUs<u32> + DelayMs<u32>> DelayUs<u32> for UntilReady<'_, D> {
+    fn delay_us(&mut self, us: u32) {
+        if us > MAX_BUSY_MS * 1000 {
+            self.0.delay_us(us);
+        } else {
+            self.0.delay_ms(MAX_BUSY_MS);
+        }
+    }
+}
+
+impl<T, D> Co2Sensor for SCD30<T, D>
+where
+    T: Transport,
+    D: DelayUs<u32> + DelayMs<u32>,
+{
+    // resending the start command is how the SCD30 takes a new pressure
+    fn start(&mut self, pressure: u16) -> Result<(), Error> {
+        self.start_continuous_measurement(pressure)
+    }
+
+    fn set_altitude(&mut self, altitude: u16) -> Result<(), Error> {
+        self.set_altitude_compensation(altitude)
+    }
+
+    fn data_ready(&mut self) -> Result<bool, Error> {
+        SCD30::data_ready(self)
+    }
+
+    fn read(&mut self) -> Result<SensorData, Error> {
+        self.read_measurement()
+    }
+
+    fn calibrate(&mut self, reference: u16) -> Result<(), Error> {
+        self.set_forced_recalibration(reference)
+    }
+
+    fn firmware_version(&mut self) -> Result<Option<[u8; 2]>, Error> {
+        self.get_firmware_version().map(Some)
+    }
+
+    fn poll_event(&mut self) -> Option<Event> {
+        self.event.take()
+    }
+}
+
+// helper functions, shared by the blocking and the async driver, and the SCD4x driver
+
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = Crc::<u8>::new(0x31, 8, 0xff, 0x00, false);
+    crc.update(data);
+    crc.finish()
+}
+
+// command followed by a 16 bit argument and its CRC
+pub(crate) fn command_with_argument(command: [u8; 2], argument: u16) -> [u8; 5] {
+    let argument_bytes = argument.to_be_bytes();
+    [
+        command[0],
+        command[1],
+        argument_bytes[0],
+        argument_bytes[1],
+        crc8(&argument_bytes),
+    ]
+}
+
+// ambient pressure for `start_continuous_measurement`, 0 disables the compensation
+fn check_pressure(pressure: u16) -> Result<(), Error> {
+    if pressure != 0 && !VALID_PRESSURE_MBAR.contains(&pressure) {
+        return Err(Error::InvalidArgument);
+    }
+    Ok(())
+}
+
+// The sensor sends data in words of two bytes, each followed by their CRC.
+// Returns the word at `index`, if its CRC is correct.
+pub(crate) fn checked_word(rd_buffer: &[u8], index: usize) -> Result<[u8; 2], Error> {
+    let start = index * 3;
+    let word = [rd_buffer[start], rd_buffer[start + 1]];
+    if crc8(&word) == rd_buffer[start + 2] {
+        Ok(word)
+    } else {
+        Err(Error::Crc)
+    }
+}
+
+// all words of an I2C response, `rd_buffer` holds three bytes per word
+fn checked_words(rd_buffer: &[u8], words: &mut [u16]) -> Result<(), Error> {
+    for (index, word) in words.iter_mut().enumerate() {
+        *word = u16::from_be_bytes(checked_word(rd_buffer, index)?);
+    }
+    Ok(())
+}
+
+fn parse_firmware_version(words: &[u16; 1]) -> [u8; 2] {
+    // major and minor version
+    words[0].to_be_bytes()
+}
+
+fn parse_data_ready(words: &[u16; 1]) -> bool {
+    words[0] == 1
+}
+
+// floats are sent as two words, most significant word first
+fn float(words: &[u16], index: usize) -> f32 {
+    f32::from_bits(u32::from(words[index]) << 16 | u32::from(words[index + 1]))
+}
+
+fn parse_measurement(words: &[u16; MAX_RESPONSE_WORDS]) -> SensorData {
+    SensorData {
+        co2: float(words, 0),
+        temperature: float(words, 2),
+        humidity: float(words, 4),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Call {
+        Write(Vec<u8>),
+        Read,
+        Recover,
+    }
+
+    // answers every read with words of 1, after the first `failures` transfers failed
+    #[derive(Default)]
+    struct MockBus {
+        failures: usize,
+        recover_fails: bool,
+        calls: Vec<Call>,
+    }
+
+    impl MockBus {
+        fn transfer(&mut self) -> Result<(), Error> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                Err(Error::Bus(BusError::AddressNack))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    impl Bus for MockBus {
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+            assert_eq!(address, DEFAULT_ADDRESS);
+            self.calls.push(Call::Write(bytes.to_vec()));
+            self.transfer()
+        }
+
+        fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+            assert_eq!(address, DEFAULT_ADDRESS);
+            self.calls.push(Call::Read);
+            for word in buffer.chunks_mut(3) {
+                word.copy_from_slice(&[0x00, 0x01, crc8(&[0x00, 0x01])]);
+            }
+            self.transfer()
+        }
+
+        fn recover(&mut self) -> Result<(), Error> {
+            self.calls.push(Call::Recover);
+            if self.recover_fails {
+                Err(Error::Bus(BusError::Other))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    // also used by the tests of `modbus`
+    pub(super) struct NoDelay;
+
+    impl DelayUs<u32> for NoDelay {
+        fn delay_us(&mut self, _: u32) {}
+    }
+
+    impl DelayMs<u32> for NoDelay {
+        fn delay_ms(&mut self, _: u32) {}
+    }
+
+    // records the delays in µs
+    #[derive(Default)]
+    struct RecordingDelay(Vec<u32>);
+
+    impl DelayUs<u32> for RecordingDelay {
+        fn delay_us(&mut self, us: u32) {
+            self.0.push(us);
+        }
+    }
+
+    impl DelayMs<u32> for RecordingDelay {
+        fn delay_ms(&mut self, ms: u32) {
+            self.0.push(ms * 1000);
+        }
+    }
+
+    // a driver that measures at 1013 mbar, with an empty log of calls
+    fn started() -> SCD30<MockBus, NoDelay> {
+        let mut sensor = SCD30::init_with_delay(MockBus::default(), NoDelay);
+        sensor.start_continuous_measurement(1013).unwrap();
+        sensor.transport.calls.clear();
+        sensor
+    }
+
+    fn fail_next(sensor: &mut SCD30<MockBus, NoDelay>, failures: usize) {
+        sensor.transport.failures = failures;
+    }
+
+    fn take_calls(sensor: &mut SCD30<MockBus, NoDelay>) -> Vec<Call> {
+        core::mem::take(&mut sensor.transport.calls)
+    }
+
+    fn data_ready_calls() -> Vec<Call> {
+        vec![Call::Write(GET_DATA_READY.to_vec()), Call::Read]
+    }
+
+    #[test]
+    fn retries_once_on_nack() {
+        let mut sensor = started();
+        sensor.set_timing(Timing::RetryOnNack);
+        fail_next(&mut sensor, 1);
+
+        assert_eq!(sensor.data_ready(), Ok(true));
+        let mut calls = data_ready_calls();
+        calls.insert(0, Call::Write(GET_DATA_READY.to_vec()));
+        assert_eq!(take_calls(&mut sensor), calls);
+
+        fail_next(&mut sensor, 2);
+        assert_eq!(sensor.data_ready(), Err(Error::Bus(BusError::AddressNack)));
+        assert_eq!(
+            take_calls(&mut sensor),
+            vec![
+                Call::Write(GET_DATA_READY.to_vec()),
+                Call::Write(GET_DATA_READY.to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn clock_stretch_tolerant_waits_until_ready() {
+        let mut sensor = SCD30::init_with_delay(MockBus::default(), RecordingDelay::default());
+        assert_eq!(sensor.data_ready(), Ok(true));
+
+        sensor.set_timing(Timing::ClockStretchTolerant);
+        sensor.transport.failures = 1;
+        assert_eq!(sensor.data_ready(), Ok(true));
+
+        let (bus, delay) = sensor.release();
+        // the retry, then the wait between command and response
+        assert_eq!(delay.0, [READ_DELAY_US, 150_000, 150_000]);
+        let mut calls = data_ready_calls();
+        calls.push(Call::Write(GET_DATA_READY.to_vec()));
+        calls.extend(data_ready_calls());
+        assert_eq!(bus.calls, calls);
+    }
+
+    #[test]
+    fn no_retry_by_default() {
+        let mut sensor = started();
+        fail_next(&mut sensor, 1);
+
+        assert_eq!(sensor.data_ready(), Err(Error::Bus(BusError::AddressNack)));
+        assert_eq!(
+            take_calls(&mut sensor),
+            vec![Call::Write(GET_DATA_READY.to_vec())]
+        );
+    }
+
+    #[test]
+    fn recovers_after_repeated_failures() {
+        let mut sensor = started();
+        fail_next(&mut sensor, 3);
+        for _ in 0..3 {
+            assert_eq!(sensor.data_ready(), Err(Error::Bus(BusError::AddressNack)));
+        }
+
+        assert_eq!(
+            take_calls(&mut sensor),
+            vec![
+                Call::Write(GET_DATA_READY.to_vec()),
+                Call::Write(GET_DATA_READY.to_vec()),
+                Call::Write(GET_DATA_READY.to_vec()),
+                Call::Recover,
+                Call::Write(SOFT_RESET.to_vec()),
+            ]
+        );
+        assert_eq!(sensor.poll_event(), Some(Event::BusRecovered));
+        assert_eq!(sensor.poll_event(), None);
+
+        // the measurement is restarted with the last pressure before the next request
+        assert_eq!(sensor.data_ready(), Ok(true));
+        let mut calls = vec![Call::Write(
+            command_with_argument(START_CONTINUOUS_MEASUREMENT, 1013).to_vec(),
+        )];
+        calls.extend(data_ready_calls());
+        assert_eq!(take_calls(&mut sensor), calls);
+        assert_eq!(sensor.poll_event(), Some(Event::MeasurementRestarted));
+
+        // only once
+        assert_eq!(sensor.data_ready(), Ok(true));
+        assert_eq!(take_calls(&mut sensor), data_ready_calls());
+        assert_eq!(sensor.poll_event(), None);
+    }
+
+    #[test]
+    fn a_success_resets_the_count() {
+        let mut sensor = started();
+        for _ in 0..2 {
+            fail_next(&mut sensor, 2);
+            assert!(sensor.data_ready().is_err());
+            assert!(sensor.data_ready().is_err());
+            assert_eq!(sensor.data_ready(), Ok(true));
+        }
+
+        assert!(!take_calls(&mut sensor).contains(&Call::Recover));
+        assert_eq!(sensor.poll_event(), None);
+    }
+
+    #[test]
+    fn failing_recovery_leaves_the_sensor_alone() {
+        let mut sensor = started();
+        sensor.transport.recover_fails = true;
+        fail_next(&mut sensor, 6);
+        for _ in 0..3 {
+            assert!(sensor.read_measurement().is_err());
+        }
+
+        let calls = take_calls(&mut sensor);
+        assert_eq!(calls.last(), Some(&Call::Recover));
+        assert!(!calls.contains(&Call::Write(SOFT_RESET.to_vec())));
+        assert_eq!(sensor.poll_event(), None);
+
+        // the count starts over, the next failures try again
+        for _ in 0..3 {
+            assert!(sensor.read_measurement().is_err());
+        }
+        assert_eq!(take_calls(&mut sensor).last(), Some(&Call::Recover));
+    }
+
+    #[test]
+    fn recovery_without_measurement_doesnt_start_one() {
+        let mut sensor = SCD30::init_with_delay(MockBus::default(), NoDelay);
+        fail_next(&mut sensor, 3);
+        for _ in 0..3 {
+            assert!(sensor.get_firmware_version().is_err());
+        }
+        assert_eq!(sensor.poll_event(), Some(Event::BusRecovered));
+        take_calls(&mut sensor);
+
+        assert_eq!(sensor.data_ready(), Ok(true));
+        assert_eq!(take_calls(&mut sensor), data_ready_calls());
+        assert_eq!(sensor.poll_event(), None);
+    }
+
+    #[test]
+    fn other_errors_dont_count() {
+        let mut sensor = started();
+        for _ in 0..3 {
+            assert_eq!(
+                sensor.set_forced_recalibration(100),
+                Err(Error::InvalidArgument)
+            );
+        }
+        fail_next(&mut sensor, 2);
+        assert!(sensor.data_ready().is_err());
+        assert!(sensor.data_ready().is_err());
+
+        assert!(!take_calls(&mut sensor).contains(&Call::Recover));
     }
 }
@@ -0,0 +1,272 @@
+// The SCD30's Modbus interface, which works over longer cables than I2C.
+//
+// The sensor talks Modbus if its SEL pin is high when it powers up. It's slave 0x61 at 19200 baud
+// with 8 data bits, no parity and one stop bit; TX and RX go to the UARTE directly, or through
+// RS-485 transceivers that switch direction by themselves. Every command is a holding register:
+// reading it sends the request, writing it sends the command with its argument. The framing is
+// in `crate::modbus`.
+
+use embedded_hal::blocking::delay::DelayUs;
+#[cfg(target_os = "none")]
+use nrf52840_hal::{
+    timer::{self, Timer},
+    uarte::{self, Baudrate, Parity},
+};
+
+use super::{Command, Transport};
+#[cfg(target_os = "none")]
+use crate::modbus::UartePort;
+use crate::{
+    modbus::{self, Port, ResponseError, MAX_FRAME_SIZE, REQUEST_SIZE},
+    Error,
+};
+
+pub const SLAVE_ADDRESS: u8 = 0x61;
+#[cfg(target_os = "none")]
+const BAUDRATE: Baudrate = Baudrate::BAUD19200;
+
+// longest time the sensor takes to answer, e.g. during its daily self calibration
+const RESPONSE_TIMEOUT_US: u32 = 200_000;
+// commands without an argument are sent by writing this to their register
+const NO_ARGUMENT: u16 = 0x0001;
+
+/// Port on a UARTE, configured the way the sensor expects. `timer` ends the wait for a response.
+#[cfg(target_os = "none")]
+pub fn uarte_port<U, T>(uarte: U, pins: uarte::Pins, timer: Timer<T>) -> UartePort<U, T>
+where
+    U: uarte::Instance,
+    T: timer::Instance,
+{
+    UartePort::new(uarte, pins, Parity::EXCLUDED, BAUDRATE, timer, None)
+}
+
+/// `Transport` on a `Port` at 19200 baud, 8N1, e.g. the one of `uarte_port`
+pub struct ModbusTransport<P: Port> {
+    port: P,
+}
+
+impl<P: Port> ModbusTransport<P> {
+    pub fn new(port: P) -> Self {
+        ModbusTransport { port }
+    }
+
+    /// Returns the port
+    pub fn release(self) -> P {
+        self.port
+    }
+
+    // Sends the request and returns how much of `response` has been received. An exception
+    // response is shorter than expected, it's complete once the sensor stops sending.
+    fn exchange(&mut self, request: &[u8], response: &mut [u8]) -> Result<usize, Error> {
+        self.port.write(request)?;
+        match self.port.read_timeout(response, RESPONSE_TIMEOUT_US)? {
+            0 => Err(Error::NotResponding),
+            length => Ok(length),
+        }
+    }
+}
+
+impl<P: Port> Transport for ModbusTransport<P> {
+    fn command(&mut self, command: Command, argument: Option<u16>) -> Result<(), Error> {
+        let request = modbus::write_single_request(
+            SLAVE_ADDRESS,
+            register(command),
+            argument.unwrap_or(NO_ARGUMENT),
+        );
+        let mut response = [0u8; REQUEST_SIZE];
+        let length = self.exchange(&request, &mut response)?;
+
+        modbus::check_write_response(&request, &response[..length]).map_err(from_response)
+    }
+
+    // the sensor answers as soon as it can, there is nothing to wait for
+    fn request<D: DelayUs<u32>>(
+        &mut self,
+        command: Command,
+        words: &mut [u16],
+        _delay: &mut D,
+    ) -> Result<(), Error> {
+        let count = words.len() as u16;
+        let request = modbus::read_holding_request(SLAVE_ADDRESS, register(command), count);
+        let mut response = [0u8; MAX_FRAME_SIZE];
+        let response = &mut response[..modbus::read_response_size(count)];
+        let length = self.exchange(&request, response)?;
+
+        modbus::parse_read_response(SLAVE_ADDRESS, &response[..length], words)
+            .map_err(from_response)
+    }
+
+    // a UART can't be blocked by the sensor, there is nothing to free
+    fn recover(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// register addresses, see the SCD30 interface description
+fn register(command: Command) -> u16 {
+    match command {
+        Command::GetFirmwareVersion => 0x0020,
+        Command::StartContinuousMeasurement => 0x0036,
+        Command::GetDataReady => 0x0027,
+        Command::ReadMeasurement => 0x0028,
+        Command::SoftReset => 0x0034,
+        Command::SetAltitudeCompensation => 0x0038,
+        Command::SetForcedRecalibration => 0x0039,
+    }
+}
+
+fn from_response(error: ResponseError) -> Error {
+    match error {
+        ResponseError::Crc => Error::Crc,
+        // illegal data value
+        ResponseError::Exception(0x03) => Error::InvalidArgument,
+        ResponseError::Truncated | ResponseError::Unexpected | ResponseError::Exception(_) => {
+            Error::Serial
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::{
+        co2_sensor::Co2Sensor,
+        scd30::{tests::NoDelay, SCD30},
+    };
+
+    // answers every request with the next response, and keeps the requests
+    #[derive(Default)]
+    struct MockPort {
+        responses: VecDeque<Vec<u8>>,
+        requests: Vec<Vec<u8>>,
+    }
+
+    impl Port for MockPort {
+        fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+            self.requests.push(bytes.to_vec());
+            Ok(())
+        }
+
+        fn read_timeout(&mut self, buffer: &mut [u8], timeout_us: u32) -> Result<usize, Error> {
+            assert_eq!(timeout_us, RESPONSE_TIMEOUT_US);
+            let response = self.responses.pop_front().unwrap_or_default();
+            let length = response.len().min(buffer.len());
+            buffer[..length].copy_from_slice(&response[..length]);
+            Ok(length)
+        }
+    }
+
+    type Sensor = SCD30<ModbusTransport<MockPort>, NoDelay>;
+
+    fn sensor(responses: &[&[u8]]) -> Sensor {
+        let port = MockPort {
+            responses: responses.iter().map(|response| response.to_vec()).collect(),
+            requests: Vec::new(),
+        };
+        SCD30::init_with_delay(ModbusTransport::new(port), NoDelay)
+    }
+
+    fn requests(sensor: Sensor) -> Vec<Vec<u8>> {
+        sensor.release().0.release().requests
+    }
+
+    #[test]
+    fn read_measurement() {
+        #[rustfmt::skip]
+        let response: &[u8] = &[
+            0x61, 0x03, 0x0c,
+            0x44, 0x4b, 0x20, 0x00, // 812.5 ppm
+            0x41, 0xac, 0x00, 0x00, // 21.5 °C
+            0x42, 0x36, 0x00, 0x00, // 45.5 %
+            0x2f, 0xa7,
+        ];
+        let mut sensor = sensor(&[response]);
+
+        let data = sensor.read_measurement().unwrap();
+        assert_eq!(data.co2, 812.5);
+        assert_eq!(data.temperature, 21.5);
+        assert_eq!(data.humidity, 45.5);
+        assert_eq!(
+            requests(sensor),
+            [[0x61, 0x03, 0x00, 0x28, 0x00, 0x06, 0x4c, 0x60]]
+        );
+    }
+
+    #[test]
+    fn data_ready() {
+        let mut sensor = sensor(&[
+            &[0x61, 0x03, 0x02, 0x00, 0x01, 0xf9, 0x8c],
+            &[0x61, 0x03, 0x02, 0x00, 0x00, 0x38, 0x4c],
+        ]);
+
+        assert_eq!(sensor.data_ready(), Ok(true));
+        assert_eq!(sensor.data_ready(), Ok(false));
+        let request = [0x61, 0x03, 0x00, 0x27, 0x00, 0x01, 0x3d, 0xa1];
+        assert_eq!(requests(sensor), [request, request]);
+    }
+
+    #[test]
+    fn firmware_version() {
+        let mut sensor = sensor(&[&[0x61, 0x03, 0x02, 0x03, 0x42, 0xb8, 0x8d]]);
+
+        assert_eq!(sensor.get_firmware_version(), Ok([3, 66]));
+        assert_eq!(
+            requests(sensor),
+            [[0x61, 0x03, 0x00, 0x20, 0x00, 0x01, 0x8c, 0x60]]
+        );
+    }
+
+    // the sensor repeats every write
+    #[test]
+    fn writes() {
+        type Write = fn(&mut Sensor) -> Result<(), Error>;
+        let cases: [(Write, [u8; 8]); 5] = [
+            (
+                |sensor| sensor.start_continuous_measurement(1013),
+                [0x61, 0x06, 0x00, 0x36, 0x03, 0xf5, 0xa0, 0xd3],
+            ),
+            (
+                |sensor| sensor.start_continuous_measurement(0),
+                [0x61, 0x06, 0x00, 0x36, 0x00, 0x00, 0x60, 0x64],
+            ),
+            (
+                |sensor| sensor.set_altitude_compensation(520),
+                [0x61, 0x06, 0x00, 0x38, 0x02, 0x08, 0x01, 0x01],
+            ),
+            (
+                |sensor| sensor.set_forced_recalibration(400),
+                [0x61, 0x06, 0x00, 0x39, 0x01, 0x90, 0x51, 0x9b],
+            ),
+            (
+                |sensor| sensor.soft_reset(),
+                [0x61, 0x06, 0x00, 0x34, 0x00, 0x01, 0x00, 0x64],
+            ),
+        ];
+
+        for (write, request) in cases.iter() {
+            let mut sensor = sensor(&[request]);
+            assert_eq!(write(&mut sensor), Ok(()));
+            assert_eq!(requests(sensor), [request]);
+        }
+    }
+
+    #[test]
+    fn exception() {
+        let mut sensor = sensor(&[&[0x61, 0x86, 0x03, 0x02, 0x7f]]);
+        assert_eq!(sensor.calibrate(400), Err(Error::InvalidArgument));
+    }
+
+    #[test]
+    fn damaged_response() {
+        let mut sensor = sensor(&[&[0x61, 0x03, 0x02, 0x00, 0x01, 0xf9, 0x8d]]);
+        assert_eq!(sensor.data_ready(), Err(Error::Crc));
+    }
+
+    #[test]
+    fn no_response() {
+        let mut sensor = sensor(&[]);
+        assert_eq!(sensor.data_ready(), Err(Error::NotResponding));
+    }
+}
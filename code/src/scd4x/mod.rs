@@ -0,0 +1,459 @@
+// Driver for the Sensirion SCD40 and SCD41.
+//
+// Like the SCD30, the sensors talk I2C with 16 bit commands and words protected by a CRC, but
+// the command set differs. While a periodic measurement runs, the sensor only accepts the
+// commands to read data, to set the ambient pressure and to stop; everything else needs the
+// sensor to be idle. The driver keeps track of that and stops and restarts the measurement
+// where necessary.
+
+use core::ops::RangeInclusive;
+
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+
+use crate::{
+    clock::CycleDelay,
+    co2_sensor::{Co2Sensor, SensorData},
+    i2c::Bus,
+    scd30::{checked_word, command_with_argument},
+    Error,
+};
+
+pub const DEFAULT_ADDRESS: u8 = 0x62;
+
+// command codes, see the SCD4x datasheet
+const START_PERIODIC_MEASUREMENT: [u8; 2] = [0x21, 0xb1];
+const READ_MEASUREMENT: [u8; 2] = [0xec, 0x05];
+const STOP_PERIODIC_MEASUREMENT: [u8; 2] = [0x3f, 0x86];
+const GET_DATA_READY_STATUS: [u8; 2] = [0xe4, 0xb8];
+const SET_AMBIENT_PRESSURE: [u8; 2] = [0xe0, 0x00];
+const SET_SENSOR_ALTITUDE: [u8; 2] = [0x24, 0x27];
+const PERFORM_FORCED_RECALIBRATION: [u8; 2] = [0x36, 0x2f];
+const GET_SERIAL_NUMBER: [u8; 2] = [0x36, 0x82];
+const MEASURE_SINGLE_SHOT: [u8; 2] = [0x21, 0x9d];
+const REINIT: [u8; 2] = [0x36, 0x46];
+
+// execution times in ms
+const COMMAND_DELAY_MS: u32 = 1;
+const STOP_DELAY_MS: u32 = 500;
+const RECALIBRATION_DELAY_MS: u32 = 400;
+const REINIT_DELAY_MS: u32 = 30;
+
+// ambient pressure compensation in mbar
+const VALID_PRESSURE_MBAR: RangeInclusive<u16> = 700..=1200;
+// response of the forced recalibration if it failed
+const RECALIBRATION_FAILED: u16 = 0xffff;
+// the lower 11 bits of the data ready status are 0 while no data is ready
+const DATA_READY_MASK: u16 = 0x07ff;
+
+/// What the sensor is doing, some commands are only accepted while it is idle
+#[derive(Clone, Copy, PartialEq, defmt::Format)]
+pub enum Mode {
+    Idle,
+    /// measures every 5 s
+    Periodic,
+    /// a single measurement is running, SCD41 only
+    SingleShot,
+}
+
+pub struct SCD4x<B: Bus, D = CycleDelay> {
+    bus: B,
+    delay: D,
+    mode: Mode,
+}
+
+#[cfg(target_os = "none")]
+impl<B> SCD4x<B>
+where
+    B: Bus,
+{
+    /// Driver that waits by counting CPU cycles
+    pub fn init(bus: B) -> Self {
+        SCD4x::init_with_delay(bus, CycleDelay)
+    }
+}
+
+impl<B, D> SCD4x<B, D>
+where
+    B: Bus,
+    D: DelayUs<u32> + DelayMs<u32>,
+{
+    /// Driver that waits with the given delay provider, e.g. a `Timer`
+    pub fn init_with_delay(bus: B, delay: D) -> Self {
+        SCD4x {
+            bus,
+            delay,
+            mode: Mode::Idle,
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Unique 48 bit serial number of the sensor
+    pub fn get_serial_number(&mut self) -> Result<u64, Error> {
+        let mut rd_buffer = [0u8; 9];
+        self.request(&GET_SERIAL_NUMBER, &mut rd_buffer)?;
+
+        let mut serial_number = 0;
+        for index in 0..3 {
+            let word = u16::from_be_bytes(checked_word(&rd_buffer, index)?);
+            serial_number = (serial_number << 16) | u64::from(word);
+        }
+        Ok(serial_number)
+    }
+
+    pub fn start_periodic_measurement(&mut self) -> Result<(), Error> {
+        self.command(&START_PERIODIC_MEASUREMENT)?;
+        self.mode = Mode::Periodic;
+        Ok(())
+    }
+
+    pub fn stop_periodic_measurement(&mut self) -> Result<(), Error> {
+        self.command(&STOP_PERIODIC_MEASUREMENT)?;
+        self.delay.delay_ms(STOP_DELAY_MS);
+        self.mode = Mode::Idle;
+        Ok(())
+    }
+
+    /// Starts one measurement, which is ready after 5 s. Only the SCD41 supports this.
+    pub fn measure_single_shot(&mut self) -> Result<(), Error> {
+        self.idle()?;
+        self.command(&MEASURE_SINGLE_SHOT)?;
+        self.mode = Mode::SingleShot;
+        Ok(())
+    }
+
+    /// Ambient pressure in mbar, also accepted while measuring
+    pub fn set_ambient_pressure(&mut self, pressure: u16) -> Result<(), Error> {
+        if !VALID_PRESSURE_MBAR.contains(&pressure) {
+            return Err(Error::InvalidArgument);
+        }
+        self.command(&command_with_argument(SET_AMBIENT_PRESSURE, pressure))
+    }
+
+    /// Height above sea level in m, used as long as no ambient pressure is set
+    pub fn set_sensor_altitude(&mut self, altitude: u16) -> Result<(), Error> {
+        self.while_idle(|sensor| {
+            sensor.command(&command_with_argument(SET_SENSOR_ALTITUDE, altitude))
+        })
+    }
+
+    pub fn data_ready(&mut self) -> Result<bool, Error> {
+        let mut rd_buffer = [0u8; 3];
+        self.request(&GET_DATA_READY_STATUS, &mut rd_buffer)?;
+
+        let status = u16::from_be_bytes(checked_word(&rd_buffer, 0)?);
+        Ok(status & DATA_READY_MASK != 0)
+    }
+
+    pub fn read_measurement(&mut self) -> Result<SensorData, Error> {
+        let mut rd_buffer = [0u8; 9];
+        self.request(&READ_MEASUREMENT, &mut rd_buffer)?;
+        if self.mode == Mode::SingleShot {
+            self.mode = Mode::Idle;
+        }
+
+        let co2 = u16::from_be_bytes(checked_word(&rd_buffer, 0)?);
+        let temperature = u16::from_be_bytes(checked_word(&rd_buffer, 1)?);
+        let humidity = u16::from_be_bytes(checked_word(&rd_buffer, 2)?);
+
+        Ok(SensorData {
+            co2: f32::from(co2),
+            temperature: -45.0 + 175.0 * f32::from(temperature) / 65535.0,
+            humidity: 100.0 * f32::from(humidity) / 65535.0,
+        })
+    }
+
+    /// Sets the calibration so that the current measurement is `reference` ppm, and returns the
+    /// correction in ppm. The sensor has to measure periodically for at least 3 minutes before.
+    pub fn perform_forced_recalibration(&mut self, reference: u16) -> Result<i16, Error> {
+        self.while_idle(|sensor| {
+            let mut rd_buffer = [0u8; 3];
+            let command = command_with_argument(PERFORM_FORCED_RECALIBRATION, reference);
+            sensor.bus.write(DEFAULT_ADDRESS, &command)?;
+            sensor.delay.delay_ms(RECALIBRATION_DELAY_MS);
+            sensor.bus.read(DEFAULT_ADDRESS, &mut rd_buffer)?;
+
+            match u16::from_be_bytes(checked_word(&rd_buffer, 0)?) {
+                RECALIBRATION_FAILED => Err(Error::CalibrationFailed),
+                // the correction is sent with an offset of 0x8000
+                correction => Ok(correction.wrapping_sub(0x8000) as i16),
+            }
+        })
+    }
+
+    /// Reloads the settings from the EEPROM of the sensor, which has to be idle
+    pub fn reinit(&mut self) -> Result<(), Error> {
+        self.idle()?;
+        self.command(&REINIT)?;
+        self.delay.delay_ms(REINIT_DELAY_MS);
+        Ok(())
+    }
+
+    /// Returns the bus and the delay provider
+    pub fn release(self) -> (B, D) {
+        (self.bus, self.delay)
+    }
+
+    // the sensor doesn't accept the next command, or the response, before the execution time
+    fn command(&mut self, command: &[u8]) -> Result<(), Error> {
+        self.bus.write(DEFAULT_ADDRESS, command)?;
+        self.delay.delay_ms(COMMAND_DELAY_MS);
+        Ok(())
+    }
+
+    fn request(&mut self, command: &[u8], rd_buffer: &mut [u8]) -> Result<(), Error> {
+        self.command(command)?;
+        self.bus.read(DEFAULT_ADDRESS, rd_buffer)
+    }
+
+    fn idle(&mut self) -> Result<(), Error> {
+        if self.mode == Mode::Periodic {
+            self.stop_periodic_measurement()?;
+        }
+        Ok(())
+    }
+
+    // runs a command that needs the sensor to be idle, and resumes a periodic measurement after
+    fn while_idle<T>(
+        &mut self,
+        command: impl FnOnce(&mut Self) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let was_measuring = self.mode == Mode::Periodic;
+        self.idle()?;
+        let result = command(self);
+        if was_measuring {
+            self.start_periodic_measurement()?;
+        }
+        result
+    }
+}
+
+impl<B, D> Co2Sensor for SCD4x<B, D>
+where
+    B: Bus,
+    D: DelayUs<u32> + DelayMs<u32>,
+{
+    // the SCD4x takes a new pressure while measuring, but can't stop compensating for it
+    fn start(&mut self, pressure: u16) -> Result<(), Error> {
+        if pressure != 0 {
+            self.set_ambient_pressure(pressure)?;
+        }
+        if self.mode != Mode::Periodic {
+            self.start_periodic_measurement()?;
+        }
+        Ok(())
+    }
+
+    fn set_altitude(&mut self, altitude: u16) -> Result<(), Error> {
+        self.set_sensor_altitude(altitude)
+    }
+
+    fn data_ready(&mut self) -> Result<bool, Error> {
+        SCD4x::data_ready(self)
+    }
+
+    fn read(&mut self) -> Result<SensorData, Error> {
+        self.read_measurement()
+    }
+
+    fn calibrate(&mut self, reference: u16) -> Result<(), Error> {
+        let correction = self.perform_forced_recalibration(reference)?;
+        defmt::info!("Forced recalibration corrected by {=i16} ppm", correction);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::VecDeque, rc::Rc};
+
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Call {
+        Write(Vec<u8>),
+        Read,
+        DelayMs(u32),
+    }
+
+    // the bus and the delay log into the same list, so the order of the calls can be checked
+    type Log = Rc<RefCell<Vec<Call>>>;
+
+    // answers the reads with the queued responses, a word and its CRC each
+    struct MockBus {
+        log: Log,
+        responses: VecDeque<Vec<u8>>,
+    }
+
+    impl Bus for MockBus {
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Error> {
+            assert_eq!(address, DEFAULT_ADDRESS);
+            self.log.borrow_mut().push(Call::Write(bytes.to_vec()));
+            Ok(())
+        }
+
+        fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<(), Error> {
+            assert_eq!(address, DEFAULT_ADDRESS);
+            self.log.borrow_mut().push(Call::Read);
+            buffer.copy_from_slice(&self.responses.pop_front().unwrap());
+            Ok(())
+        }
+
+        fn recover(&mut self) -> Result<(), Error> {
+            unimplemented!()
+        }
+    }
+
+    struct MockDelay(Log);
+
+    impl DelayUs<u32> for MockDelay {
+        fn delay_us(&mut self, _: u32) {
+            unimplemented!()
+        }
+    }
+
+    impl DelayMs<u32> for MockDelay {
+        fn delay_ms(&mut self, ms: u32) {
+            self.0.borrow_mut().push(Call::DelayMs(ms));
+        }
+    }
+
+    fn sensor(responses: &[&[u8]]) -> (SCD4x<MockBus, MockDelay>, Log) {
+        let log = Log::default();
+        let bus = MockBus {
+            log: log.clone(),
+            responses: responses.iter().map(|response| response.to_vec()).collect(),
+        };
+        (SCD4x::init_with_delay(bus, MockDelay(log.clone())), log)
+    }
+
+    fn write(bytes: &[u8]) -> Call {
+        Call::Write(bytes.to_vec())
+    }
+
+    #[test]
+    fn start_with_pressure() {
+        let (mut sensor, log) = sensor(&[]);
+
+        assert_eq!(Co2Sensor::start(&mut sensor, 1013), Ok(()));
+        assert_eq!(
+            *log.borrow(),
+            [
+                write(&[0xe0, 0x00, 0x03, 0xf5, 0xdb]),
+                Call::DelayMs(1),
+                write(&[0x21, 0xb1]),
+                Call::DelayMs(1),
+            ]
+        );
+        assert!(sensor.mode() == Mode::Periodic);
+
+        // a running measurement only takes the new pressure
+        log.borrow_mut().clear();
+        assert_eq!(Co2Sensor::start(&mut sensor, 1200), Ok(()));
+        assert_eq!(
+            *log.borrow(),
+            [write(&[0xe0, 0x00, 0x04, 0xb0, 0xbd]), Call::DelayMs(1)]
+        );
+    }
+
+    #[test]
+    fn start_without_pressure() {
+        let (mut sensor, log) = sensor(&[]);
+
+        assert_eq!(Co2Sensor::start(&mut sensor, 0), Ok(()));
+        assert_eq!(*log.borrow(), [write(&[0x21, 0xb1]), Call::DelayMs(1)]);
+    }
+
+    #[test]
+    fn pressure_out_of_range() {
+        let (mut sensor, log) = sensor(&[]);
+
+        assert_eq!(
+            sensor.set_ambient_pressure(699),
+            Err(Error::InvalidArgument)
+        );
+        assert_eq!(
+            sensor.set_ambient_pressure(1201),
+            Err(Error::InvalidArgument)
+        );
+        assert!(log.borrow().is_empty());
+    }
+
+    #[test]
+    fn altitude_stops_the_measurement() {
+        let (mut sensor, log) = sensor(&[]);
+        sensor.start_periodic_measurement().unwrap();
+        log.borrow_mut().clear();
+
+        assert_eq!(sensor.set_sensor_altitude(520), Ok(()));
+        assert_eq!(
+            *log.borrow(),
+            [
+                write(&[0x3f, 0x86]),
+                Call::DelayMs(1),
+                Call::DelayMs(500),
+                write(&[0x24, 0x27, 0x02, 0x08, 0xe1]),
+                Call::DelayMs(1),
+                write(&[0x21, 0xb1]),
+                Call::DelayMs(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn data_ready() {
+        let (mut sensor, log) = sensor(&[&[0x80, 0x06, 0x04], &[0x80, 0x00, 0xa2]]);
+
+        assert_eq!(sensor.data_ready(), Ok(true));
+        assert_eq!(sensor.data_ready(), Ok(false));
+        assert_eq!(
+            log.borrow()[..3],
+            [write(&[0xe4, 0xb8]), Call::DelayMs(1), Call::Read]
+        );
+    }
+
+    #[test]
+    fn read_measurement() {
+        let (mut sensor, _) = sensor(&[&[0x01, 0xf4, 0x33, 0x66, 0x67, 0xa2, 0x5e, 0xb9, 0x3c]]);
+
+        let data = sensor.read_measurement().unwrap();
+        assert_eq!(data.co2, 500.0);
+        assert!((data.temperature - 25.0).abs() < 0.01);
+        assert!((data.humidity - 37.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn crc_error() {
+        let (mut sensor, _) = sensor(&[&[0x01, 0xf4, 0x33, 0x66, 0x67, 0xa3, 0x5e, 0xb9, 0x3c]]);
+
+        assert!(matches!(sensor.read_measurement(), Err(Error::Crc)));
+    }
+
+    #[test]
+    fn serial_number() {
+        let (mut sensor, _) = sensor(&[&[0xf8, 0x96, 0x31, 0x9f, 0x07, 0xc2, 0x3b, 0xb3, 0xc5]]);
+
+        assert_eq!(sensor.get_serial_number(), Ok(0xf896_9f07_3bb3));
+    }
+
+    #[test]
+    fn forced_recalibration() {
+        let (mut sensor, log) = sensor(&[&[0x7f, 0xce, 0x7b], &[0xff, 0xff, 0xac]]);
+
+        assert_eq!(sensor.perform_forced_recalibration(420), Ok(-50));
+        assert_eq!(
+            *log.borrow(),
+            [
+                write(&[0x36, 0x2f, 0x01, 0xa4, 0x4d]),
+                Call::DelayMs(400),
+                Call::Read,
+            ]
+        );
+        assert_eq!(
+            sensor.perform_forced_recalibration(420),
+            Err(Error::CalibrationFailed)
+        );
+    }
+}
@@ -0,0 +1,125 @@
+// Cooperative scheduler for periodic tasks.
+//
+// Tasks are registered with a period and get a `TaskId`. The main loop asks the scheduler which
+// task is due, runs it, and asks again. In contrast to `if (millis % 1000) == 0`, a task whose
+// time has passed while the loop was busy is still run, and the delay is reported.
+//
+// loop {
+//     while let Some(due) = scheduler.poll(clock::now()) {
+//         if due.task == button_task { ... }
+//     }
+//     scheduler.sleep();
+// }
+
+use crate::clock::{self, Duration, Instant};
+
+/// What happens if a task missed one or more of its periods
+#[derive(Clone, Copy, PartialEq, defmt::Format)]
+pub enum Policy {
+    /// the task is run once for every missed period, back to back
+    CatchUp,
+    /// the task is run once, missed periods are dropped and reported in `Due::missed`
+    Skip,
+}
+
+#[derive(Clone, Copy, PartialEq, defmt::Format)]
+pub struct TaskId(usize);
+
+/// A task that is due to run
+#[derive(Clone, Copy, defmt::Format)]
+pub struct Due {
+    pub task: TaskId,
+    /// how long after its deadline the task is run
+    pub late: Duration,
+    /// number of periods that passed without the task being run
+    pub missed: u32,
+}
+
+#[derive(Clone, Copy)]
+struct Task {
+    period: Duration,
+    policy: Policy,
+    // `None` until the task has run for the first time
+    next_run: Option<Instant>,
+}
+
+/// Scheduler with room for `N` tasks
+pub struct Scheduler<const N: usize> {
+    tasks: [Option<Task>; N],
+}
+
+impl<const N: usize> Scheduler<N> {
+    pub fn new() -> Self {
+        Scheduler { tasks: [None; N] }
+    }
+
+    /// Registers a task that is due every `period`, starting with the next `poll`.
+    /// Returns `None` if all `N` slots are taken.
+    pub fn add(&mut self, period: Duration, policy: Policy) -> Option<TaskId> {
+        let index = self.tasks.iter().position(|task| task.is_none())?;
+        self.tasks[index] = Some(Task {
+            period,
+            policy,
+            next_run: None,
+        });
+        Some(TaskId(index))
+    }
+
+    /// Returns the task that has been due for the longest time, if any.
+    /// Call it repeatedly until it returns `None`, to run all due tasks.
+    pub fn poll(&mut self, now: Instant) -> Option<Due> {
+        let mut most_urgent: Option<(usize, Instant)> = None;
+        for (index, task) in self.tasks.iter().enumerate() {
+            if let Some(task) = task {
+                let deadline = task.next_run.unwrap_or(now);
+                let is_more_urgent = match most_urgent {
+                    Some((_, other_deadline)) => deadline < other_deadline,
+                    None => true,
+                };
+                if deadline <= now && is_more_urgent {
+                    most_urgent = Some((index, deadline));
+                }
+            }
+        }
+
+        let (index, deadline) = most_urgent?;
+        let task = self.tasks[index].as_mut()?;
+        let late = now.duration_since(deadline);
+        // a period of 0 would make the task due forever
+        let period = task.period.as_millis().max(1);
+        let missed = (late.as_millis() / period) as u32;
+
+        task.next_run = Some(match task.policy {
+            Policy::CatchUp => deadline + Duration::from_millis(period),
+            Policy::Skip => deadline + Duration::from_millis(period * (u64::from(missed) + 1)),
+        });
+
+        Some(Due {
+            task: TaskId(index),
+            late,
+            missed,
+        })
+    }
+
+    /// Deadline of the task that is due next, `None` if no task is registered
+    pub fn next_deadline(&self, now: Instant) -> Option<Instant> {
+        self.tasks
+            .iter()
+            .flatten()
+            .map(|task| task.next_run.unwrap_or(now))
+            .min()
+    }
+
+    /// Puts the CPU to sleep until the next task is due
+    pub fn sleep(&self) {
+        if let Some(deadline) = self.next_deadline(clock::now()) {
+            clock::sleep_until(deadline);
+        }
+    }
+}
+
+impl<const N: usize> Default for Scheduler<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
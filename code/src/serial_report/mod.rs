@@ -0,0 +1,314 @@
+// Measurements as text lines over the UARTE, for data loggers that can read a UART but not RTT.
+//
+// On the DK, UARTE0 is wired to the virtual COM port of the onboard debugger, so the lines show
+// up on the USB serial port of the host as well. Every measurement is formatted into a buffer on
+// the stack, no heap is needed.
+//
+// Formats, one line per measurement:
+// * CSV: `12,812.4,21.46,45.50,1`, after the header `uptime_s,co2_ppm,...`
+// * JSON: `{"uptime_s":12,"co2_ppm":812.4,...}`
+// * InfluxDB line protocol: `air_quality co2=812.4,temperature=21.46,humidity=45.50,alert_level=1i`,
+//   without a timestamp, so the receiver adds its own
+//
+// The alert level is the index of the air quality category, 0 for the best air. A value the
+// sensor returned as NaN or infinity is left empty in CSV, `null` in JSON and left out in the
+// line protocol, which has no way to write it.
+
+use core::fmt::{self, Write};
+
+#[cfg(target_os = "none")]
+use nrf52840_hal::{
+    pac::UARTE0,
+    uarte::{self, Baudrate, Parity, Uarte},
+};
+
+#[cfg(target_os = "none")]
+use crate::Error;
+use crate::{alerts::Category, clock::Instant, co2_sensor::SensorData};
+
+/// Longest line, including the line break: a JSON line with the largest uptime and every value
+/// at the limit of `f32`, which has 39 digits before the point
+pub const LINE_CAPACITY: usize = 256;
+
+#[derive(Clone, Copy, PartialEq, defmt::Format)]
+pub enum Format {
+    Csv,
+    Json,
+    Influx,
+}
+
+/// Text of one line, formatted without heap
+pub struct Line {
+    buffer: [u8; LINE_CAPACITY],
+    length: usize,
+}
+
+impl Line {
+    pub fn new() -> Self {
+        Line {
+            buffer: [0; LINE_CAPACITY],
+            length: 0,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer[..self.length]
+    }
+
+    pub fn as_str(&self) -> &str {
+        // only whole strings are written
+        core::str::from_utf8(self.as_bytes()).unwrap_or("")
+    }
+}
+
+impl Default for Line {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// fails instead of truncating when the line doesn't fit
+impl Write for Line {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        let end = self.length + text.len();
+        if end > LINE_CAPACITY {
+            return Err(fmt::Error);
+        }
+        self.buffer[self.length..end].copy_from_slice(text.as_bytes());
+        self.length = end;
+        Ok(())
+    }
+}
+
+/// Header line of the format, only CSV has one
+pub fn format_header(format: Format) -> Option<&'static str> {
+    match format {
+        Format::Csv => Some("uptime_s,co2_ppm,temperature_c,humidity_percent,alert_level\n"),
+        Format::Json | Format::Influx => None,
+    }
+}
+
+// a measured value with `precision` digits after the point, `missing` if it isn't finite
+struct Value {
+    value: f32,
+    precision: usize,
+    missing: &'static str,
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.value.is_finite() {
+            write!(f, "{:.*}", self.precision, self.value)
+        } else {
+            f.write_str(self.missing)
+        }
+    }
+}
+
+/// Formats a measurement taken at `time` as one line
+pub fn format_line(
+    format: Format,
+    time: Instant,
+    data: &SensorData,
+    category: Category,
+) -> Result<Line, fmt::Error> {
+    let mut line = Line::new();
+    let uptime = time.as_millis() / 1000;
+    let level = category.level();
+    let values = |missing| {
+        [
+            Value {
+                value: data.co2,
+                precision: 1,
+                missing,
+            },
+            Value {
+                value: data.temperature,
+                precision: 2,
+                missing,
+            },
+            Value {
+                value: data.humidity,
+                precision: 2,
+                missing,
+            },
+        ]
+    };
+    match format {
+        Format::Csv => {
+            let [co2, temperature, humidity] = values("");
+            writeln!(
+                line,
+                "{},{},{},{},{}",
+                uptime, co2, temperature, humidity, level
+            )?
+        }
+        Format::Json => {
+            let [co2, temperature, humidity] = values("null");
+            writeln!(
+                line,
+                "{{\"uptime_s\":{},\"co2_ppm\":{},\"temperature_c\":{},\"humidity_percent\":{},\"alert_level\":{}}}",
+                uptime, co2, temperature, humidity, level
+            )?
+        }
+        Format::Influx => {
+            line.write_str("air_quality ")?;
+            let names = ["co2", "temperature", "humidity"];
+            for (name, value) in names.iter().zip(values("").iter()) {
+                if value.value.is_finite() {
+                    write!(line, "{}={},", name, value)?;
+                }
+            }
+            writeln!(line, "alert_level={}i", level)?
+        }
+    }
+    Ok(line)
+}
+
+#[cfg(target_os = "none")]
+pub struct SerialReport {
+    uarte: Uarte<UARTE0>,
+    format: Format,
+}
+
+#[cfg(target_os = "none")]
+impl SerialReport {
+    /// Configures the UARTE for 8N1 at the baud rate, without flow control
+    pub fn new(uarte: UARTE0, pins: uarte::Pins, baudrate: Baudrate, format: Format) -> Self {
+        SerialReport {
+            uarte: Uarte::new(uarte, pins, Parity::EXCLUDED, baudrate),
+            format,
+        }
+    }
+
+    pub fn set_format(&mut self, format: Format) {
+        self.format = format;
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Sends the header line of the format, if it has one, e.g. after the logger connected
+    pub fn header(&mut self) -> Result<(), Error> {
+        match format_header(self.format) {
+            // `Uarte` can only send from RAM, the header is copied out of the flash first
+            Some(header) => {
+                let mut line = Line::new();
+                line.write_str(header).map_err(|_| Error::InvalidArgument)?;
+                self.write(&line)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Sends a measurement taken at `time`
+    pub fn report(
+        &mut self,
+        time: Instant,
+        data: &SensorData,
+        category: Category,
+    ) -> Result<(), Error> {
+        // `LINE_CAPACITY` holds the longest line any `SensorData` can give
+        let line =
+            format_line(self.format, time, data, category).map_err(|_| Error::InvalidArgument)?;
+        self.write(&line)
+    }
+
+    /// Returns the UARTE and its pins
+    pub fn release(self) -> (UARTE0, uarte::Pins) {
+        self.uarte.free()
+    }
+
+    fn write(&mut self, line: &Line) -> Result<(), Error> {
+        self.uarte.write(line.as_bytes()).map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data() -> SensorData {
+        SensorData {
+            co2: 812.44,
+            temperature: 21.456,
+            humidity: 45.5,
+        }
+    }
+
+    fn format(format: Format, data: &SensorData) -> String {
+        let line = format_line(format, Instant::from_millis(12_300), data, Category::II).unwrap();
+        line.as_str().to_string()
+    }
+
+    #[test]
+    fn csv() {
+        assert_eq!(
+            format_header(Format::Csv),
+            Some("uptime_s,co2_ppm,temperature_c,humidity_percent,alert_level\n")
+        );
+        assert_eq!(format(Format::Csv, &data()), "12,812.4,21.46,45.50,1\n");
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(format_header(Format::Json), None);
+        assert_eq!(
+            format(Format::Json, &data()),
+            "{\"uptime_s\":12,\"co2_ppm\":812.4,\"temperature_c\":21.46,\"humidity_percent\":45.50,\"alert_level\":1}\n"
+        );
+    }
+
+    #[test]
+    fn influx() {
+        assert_eq!(format_header(Format::Influx), None);
+        assert_eq!(
+            format(Format::Influx, &data()),
+            "air_quality co2=812.4,temperature=21.46,humidity=45.50,alert_level=1i\n"
+        );
+    }
+
+    #[test]
+    fn not_finite() {
+        let data = SensorData {
+            co2: f32::NAN,
+            temperature: f32::INFINITY,
+            humidity: 45.5,
+        };
+        assert_eq!(format(Format::Csv, &data), "12,,,45.50,1\n");
+        assert_eq!(
+            format(Format::Json, &data),
+            "{\"uptime_s\":12,\"co2_ppm\":null,\"temperature_c\":null,\"humidity_percent\":45.50,\"alert_level\":1}\n"
+        );
+        assert_eq!(
+            format(Format::Influx, &data),
+            "air_quality humidity=45.50,alert_level=1i\n"
+        );
+    }
+
+    #[test]
+    fn longest_line_fits() {
+        let data = SensorData {
+            co2: f32::MIN,
+            temperature: f32::MIN,
+            humidity: f32::MIN,
+        };
+        let time = Instant::from_millis(u64::MAX);
+        for &format in &[Format::Csv, Format::Json, Format::Influx] {
+            let line = format_line(format, time, &data, Category::IV).unwrap();
+            assert!(line
+                .as_str()
+                .contains("-340282346638528859811704183484516925440.00"));
+            assert!(line.as_str().ends_with('\n'));
+        }
+    }
+
+    #[test]
+    fn overflow_fails() {
+        let mut line = Line::new();
+        assert!(line.write_str(&"x".repeat(LINE_CAPACITY)).is_ok());
+        assert!(line.write_str("x").is_err());
+        assert_eq!(line.as_bytes().len(), LINE_CAPACITY);
+    }
+}
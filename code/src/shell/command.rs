@@ -0,0 +1,265 @@
+// Parsing of shell commands. It only depends on `core` and the limits of the settings, so it can
+// be checked on the host.
+
+use crate::config::MEASUREMENT_INTERVAL_S;
+
+pub const HELP: &str = "commands:
+  read                       latest measurement
+  status                     settings and uptime
+  fw                         firmware versions
+  set threshold <name> <ppm> upper limit of good, warn or alarm air
+  set pressure <mbar>        ambient pressure, 0 compensates for the altitude
+  set altitude <m>           height above sea level
+  calibrate [<ppm>]          forced recalibration to the reference
+  interval <s>               time between two measurements, 1 to 3600
+  log dump                   all records of the log
+  log clear                  erases the log
+";
+
+/// Upper limit of an air quality category as absolute CO2 concentration.
+/// Above `Warn` the LED turns yellow, above `Alarm` red and the buzzer sounds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Threshold {
+    /// upper limit of category I
+    Good,
+    /// upper limit of category II
+    Warn,
+    /// upper limit of category III
+    Alarm,
+}
+
+impl Threshold {
+    /// Index into the limits of `alerts::Classifier`
+    pub fn index(&self) -> usize {
+        match self {
+            Threshold::Good => 0,
+            Threshold::Warn => 1,
+            Threshold::Alarm => 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Read,
+    Status,
+    Firmware,
+    /// in ppm
+    SetThreshold(Threshold, u16),
+    /// in mbar, 0 compensates for the altitude
+    SetPressure(u16),
+    /// in m
+    SetAltitude(u16),
+    /// reference in ppm, `None` uses the one in the settings
+    Calibrate(Option<u16>),
+    /// in s
+    Interval(u16),
+    LogDump,
+    LogClear,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ParseError {
+    Empty,
+    UnknownCommand,
+    MissingArgument,
+    /// more arguments than the command takes
+    UnexpectedArgument,
+    /// not a number, or out of range
+    InvalidNumber,
+}
+
+impl ParseError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            ParseError::Empty => "empty command",
+            ParseError::UnknownCommand => "unknown command, try help",
+            ParseError::MissingArgument => "missing argument",
+            ParseError::UnexpectedArgument => "too many arguments",
+            ParseError::InvalidNumber => "invalid number",
+        }
+    }
+}
+
+/// Parses one line. Words are separated by any amount of whitespace.
+pub fn parse(line: &str) -> Result<Command, ParseError> {
+    let mut words = line.split_whitespace();
+    let command = match words.next().ok_or(ParseError::Empty)? {
+        "help" => Command::Help,
+        "read" => Command::Read,
+        "status" => Command::Status,
+        "fw" => Command::Firmware,
+        "set" => match words.next().ok_or(ParseError::MissingArgument)? {
+            "threshold" => {
+                let threshold = match words.next().ok_or(ParseError::MissingArgument)? {
+                    "good" => Threshold::Good,
+                    "warn" => Threshold::Warn,
+                    "alarm" => Threshold::Alarm,
+                    _ => return Err(ParseError::UnknownCommand),
+                };
+                Command::SetThreshold(threshold, number(words.next())?)
+            }
+            "pressure" => Command::SetPressure(number(words.next())?),
+            "altitude" => Command::SetAltitude(number(words.next())?),
+            _ => return Err(ParseError::UnknownCommand),
+        },
+        "calibrate" => match words.next() {
+            Some(reference) => Command::Calibrate(Some(number(Some(reference))?)),
+            None => Command::Calibrate(None),
+        },
+        "interval" => match number(words.next())? {
+            interval if MEASUREMENT_INTERVAL_S.contains(&interval) => Command::Interval(interval),
+            _ => return Err(ParseError::InvalidNumber),
+        },
+        "log" => match words.next().ok_or(ParseError::MissingArgument)? {
+            "dump" => Command::LogDump,
+            "clear" => Command::LogClear,
+            _ => return Err(ParseError::UnknownCommand),
+        },
+        _ => return Err(ParseError::UnknownCommand),
+    };
+
+    match words.next() {
+        Some(_) => Err(ParseError::UnexpectedArgument),
+        None => Ok(command),
+    }
+}
+
+fn number(word: Option<&str>) -> Result<u16, ParseError> {
+    word.ok_or(ParseError::MissingArgument)?
+        .parse()
+        .map_err(|_| ParseError::InvalidNumber)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_without_arguments() {
+        assert_eq!(parse("help"), Ok(Command::Help));
+        assert_eq!(parse("read"), Ok(Command::Read));
+        assert_eq!(parse("status"), Ok(Command::Status));
+        assert_eq!(parse("fw"), Ok(Command::Firmware));
+        assert_eq!(parse("log dump"), Ok(Command::LogDump));
+        assert_eq!(parse("log clear"), Ok(Command::LogClear));
+    }
+
+    #[test]
+    fn set_commands() {
+        assert_eq!(
+            parse("set threshold good 970"),
+            Ok(Command::SetThreshold(Threshold::Good, 970))
+        );
+        assert_eq!(
+            parse("set threshold warn 1220"),
+            Ok(Command::SetThreshold(Threshold::Warn, 1220))
+        );
+        assert_eq!(
+            parse("set threshold alarm 1770"),
+            Ok(Command::SetThreshold(Threshold::Alarm, 1770))
+        );
+        assert_eq!(parse("set pressure 1013"), Ok(Command::SetPressure(1013)));
+        assert_eq!(parse("set pressure 0"), Ok(Command::SetPressure(0)));
+        assert_eq!(parse("set altitude 520"), Ok(Command::SetAltitude(520)));
+    }
+
+    #[test]
+    fn calibrate_with_and_without_reference() {
+        assert_eq!(parse("calibrate"), Ok(Command::Calibrate(None)));
+        assert_eq!(parse("calibrate 400"), Ok(Command::Calibrate(Some(400))));
+        assert_eq!(parse("calibrate ppm"), Err(ParseError::InvalidNumber));
+    }
+
+    #[test]
+    fn interval_bounds() {
+        assert_eq!(parse("interval 0"), Err(ParseError::InvalidNumber));
+        assert_eq!(parse("interval 1"), Ok(Command::Interval(1)));
+        assert_eq!(parse("interval 3600"), Ok(Command::Interval(3600)));
+        assert_eq!(parse("interval 3601"), Err(ParseError::InvalidNumber));
+    }
+
+    #[test]
+    fn whitespace() {
+        assert_eq!(
+            parse("  set\tthreshold   warn 1220 \r\n"),
+            Ok(Command::SetThreshold(Threshold::Warn, 1220))
+        );
+        assert_eq!(parse("read\n"), Ok(Command::Read));
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(parse(""), Err(ParseError::Empty));
+        assert_eq!(parse(" \t\r\n"), Err(ParseError::Empty));
+    }
+
+    #[test]
+    fn unknown_command() {
+        assert_eq!(parse("reboot"), Err(ParseError::UnknownCommand));
+        assert_eq!(parse("READ"), Err(ParseError::UnknownCommand));
+        assert_eq!(parse("set unit celsius"), Err(ParseError::UnknownCommand));
+        assert_eq!(
+            parse("set threshold bad 1000"),
+            Err(ParseError::UnknownCommand)
+        );
+        assert_eq!(parse("log print"), Err(ParseError::UnknownCommand));
+    }
+
+    #[test]
+    fn missing_argument() {
+        assert_eq!(parse("set"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("set threshold"), Err(ParseError::MissingArgument));
+        assert_eq!(
+            parse("set threshold good"),
+            Err(ParseError::MissingArgument)
+        );
+        assert_eq!(parse("set pressure"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("set altitude"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("interval"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("log"), Err(ParseError::MissingArgument));
+    }
+
+    #[test]
+    fn unexpected_argument() {
+        assert_eq!(parse("read now"), Err(ParseError::UnexpectedArgument));
+        assert_eq!(parse("help me"), Err(ParseError::UnexpectedArgument));
+        assert_eq!(
+            parse("set altitude 520 m"),
+            Err(ParseError::UnexpectedArgument)
+        );
+        assert_eq!(
+            parse("calibrate 400 ppm"),
+            Err(ParseError::UnexpectedArgument)
+        );
+        assert_eq!(parse("log clear all"), Err(ParseError::UnexpectedArgument));
+    }
+
+    #[test]
+    fn invalid_number() {
+        assert_eq!(parse("set pressure high"), Err(ParseError::InvalidNumber));
+        assert_eq!(parse("set pressure -1"), Err(ParseError::InvalidNumber));
+        assert_eq!(parse("set altitude 65536"), Err(ParseError::InvalidNumber));
+        assert_eq!(
+            parse("set threshold good 9.5"),
+            Err(ParseError::InvalidNumber)
+        );
+        assert_eq!(parse("interval 2s"), Err(ParseError::InvalidNumber));
+    }
+
+    #[test]
+    fn every_error_has_a_message() {
+        for error in [
+            ParseError::Empty,
+            ParseError::UnknownCommand,
+            ParseError::MissingArgument,
+            ParseError::UnexpectedArgument,
+            ParseError::InvalidNumber,
+        ]
+        .iter()
+        {
+            assert!(!error.message().is_empty());
+        }
+    }
+}
@@ -0,0 +1,186 @@
+// Execution of the parsed commands, only on the board.
+
+use core::fmt::{self, Write};
+
+use super::{parse, Command, HELP};
+use crate::{
+    alerts::Standard,
+    clock::{self, Instant},
+    co2_sensor::{Co2Sensor, SensorData},
+    config::{ConfigStore, Settings},
+    flash::Flash,
+    logger::Logger,
+    Error,
+};
+
+/// Everything the commands read or change
+pub struct Context<'a, S: Co2Sensor> {
+    pub sensor: &'a mut S,
+    pub settings: &'a mut Settings,
+    pub config: &'a mut ConfigStore,
+    pub flash: &'a mut Flash,
+    pub logger: &'a mut Logger,
+    /// the latest measurement and when it was taken, `None` before the first one
+    pub latest: Option<(SensorData, Instant)>,
+}
+
+// a failing command is reported to the user, a failing output ends the command
+enum Failure {
+    Device(Error),
+    Output,
+}
+
+impl From<Error> for Failure {
+    fn from(error: Error) -> Self {
+        Failure::Device(error)
+    }
+}
+
+impl From<fmt::Error> for Failure {
+    fn from(_: fmt::Error) -> Self {
+        Failure::Output
+    }
+}
+
+/// Parses and executes one line, and writes the reply to `out`. Invalid commands and failures
+/// of the devices are reported in the reply; an error is only returned if writing failed.
+pub fn run<S: Co2Sensor, W: Write>(
+    line: &str,
+    context: &mut Context<S>,
+    out: &mut W,
+) -> fmt::Result {
+    let command = match parse(line) {
+        Ok(command) => command,
+        Err(error) => return writeln!(out, "error: {}", error.message()),
+    };
+
+    match execute(command, context, out) {
+        Ok(()) => Ok(()),
+        Err(Failure::Device(error)) => {
+            defmt::warn!("Command failed: {:?}", error);
+            writeln!(out, "error: {}", describe(error))
+        }
+        Err(Failure::Output) => Err(fmt::Error),
+    }
+}
+
+fn execute<S: Co2Sensor, W: Write>(
+    command: Command,
+    context: &mut Context<S>,
+    out: &mut W,
+) -> Result<(), Failure> {
+    match command {
+        Command::Help => out.write_str(HELP)?,
+        Command::Read => match context.latest {
+            Some((data, time)) => writeln!(
+                out,
+                "co2 {:.1} ppm, temperature {:.2} C, humidity {:.2} %, {} s ago",
+                data.co2,
+                data.temperature,
+                data.humidity,
+                time.elapsed().as_millis() / 1000
+            )?,
+            None => writeln!(out, "no measurement yet")?,
+        },
+        Command::Status => status(context.settings, out)?,
+        Command::Firmware => {
+            writeln!(out, "application {}", env!("CARGO_PKG_VERSION"))?;
+            match context.sensor.firmware_version()? {
+                Some([major, minor]) => writeln!(out, "sensor {}.{}", major, minor)?,
+                None => writeln!(out, "sensor doesn't report a version")?,
+            }
+        }
+        Command::SetThreshold(threshold, ppm) => {
+            let mut thresholds = context.settings.thresholds();
+            thresholds[threshold.index()] = ppm;
+            context.settings.set_thresholds(thresholds)?;
+            save(context)?;
+            writeln!(out, "ok")?;
+        }
+        Command::SetPressure(pressure) => {
+            context.sensor.start(pressure)?;
+            context.settings.pressure = pressure;
+            save(context)?;
+            writeln!(out, "ok")?;
+        }
+        Command::SetAltitude(altitude) => {
+            context.sensor.set_altitude(altitude)?;
+            context.settings.altitude = altitude;
+            save(context)?;
+            writeln!(out, "ok")?;
+        }
+        Command::Calibrate(reference) => {
+            let reference = reference.unwrap_or(context.settings.calibration_reference);
+            context.sensor.calibrate(reference)?;
+            writeln!(out, "calibrated to {} ppm", reference)?;
+        }
+        Command::Interval(interval) => {
+            context.settings.measurement_interval = interval;
+            save(context)?;
+            writeln!(out, "ok")?;
+        }
+        Command::LogDump => {
+            writeln!(
+                out,
+                "sequence,uptime_s,co2_ppm,temperature_c,humidity_percent"
+            )?;
+            for record in context.logger.records(context.flash) {
+                let data = record.sensor_data();
+                writeln!(
+                    out,
+                    "{},{},{:.0},{:.2},{:.2}",
+                    record.sequence, record.uptime, data.co2, data.temperature, data.humidity
+                )?;
+            }
+        }
+        Command::LogClear => {
+            context.logger.clear(context.flash)?;
+            writeln!(out, "ok")?;
+        }
+    }
+    Ok(())
+}
+
+fn status<W: Write>(settings: &Settings, out: &mut W) -> fmt::Result {
+    writeln!(out, "uptime {} s", clock::now().as_millis() / 1000)?;
+    let standard = match settings.standard {
+        Standard::En16798 => "EN 16798-1",
+        Standard::En13779 => "EN 13779",
+    };
+    writeln!(out, "standard {}", standard)?;
+    let thresholds = settings.thresholds();
+    writeln!(
+        out,
+        "thresholds good {} warn {} alarm {} ppm, outdoor {} ppm",
+        thresholds[0], thresholds[1], thresholds[2], settings.outdoor_co2
+    )?;
+    match settings.pressure {
+        0 => writeln!(out, "pressure off, altitude {} m", settings.altitude)?,
+        pressure => writeln!(out, "pressure {} mbar", pressure)?,
+    }
+    writeln!(out, "interval {} s", settings.measurement_interval)?;
+    writeln!(
+        out,
+        "calibration reference {} ppm",
+        settings.calibration_reference
+    )
+}
+
+fn save<S: Co2Sensor>(context: &mut Context<S>) -> Result<(), Error> {
+    context.config.save(context.flash, context.settings)
+}
+
+fn describe(error: Error) -> &'static str {
+    match error {
+        Error::Bus(_) => "sensor not reachable",
+        Error::Crc => "corrupted data from the sensor",
+        Error::Timeout => "the sensor timed out",
+        Error::NotResponding => "the sensor doesn't respond",
+        Error::InvalidArgument => "value out of range",
+        Error::CalibrationFailed => "the sensor rejected the calibration",
+        Error::UnknownDevice => "unknown device",
+        Error::Pin => "setting a pin failed",
+        Error::Flash => "saving to the flash failed",
+        Error::Serial => "serial transfer failed",
+    }
+}
@@ -0,0 +1,20 @@
+// Line-oriented command shell for configuration and diagnostics.
+//
+// The shell doesn't know where lines come from: the application reads them from the UART, the
+// USB serial port or the RTT down-channel, and passes each one to `run` together with the drivers
+// the commands work on. Replies go to any `core::fmt::Write`. See `HELP` for the commands.
+//
+// Changed settings are saved to the flash right away. Settings the application applies itself,
+// such as the measurement interval or the thresholds of its classifier, are picked up by comparing
+// `Context::settings` before and after `run`.
+//
+// Parsing is in `command`, which builds on the host as well. Executing the commands, in `execute`,
+// needs the flash and the clock of the board.
+
+mod command;
+#[cfg(target_os = "none")]
+mod execute;
+
+pub use command::{parse, Command, ParseError, Threshold, HELP};
+#[cfg(target_os = "none")]
+pub use execute::{run, Context};
@@ -0,0 +1,193 @@
+// Virtual serial port over the native USB of the nRF52840, so the monitor can be plugged into
+// any PC without a debug probe. Readings are streamed as text lines, and the host can send
+// commands, one per line.
+//
+// The USB peripheral needs the external high frequency crystal, which `board::Board` starts
+// when the `usb` feature is enabled. The clocks and the bus allocator have to outlive the device,
+// e.g. as local resources of RTIC's `init`:
+//
+// let clocks = cx.local.clocks.insert(board.usb.clocks);
+// let bus = cx.local.usb_bus.insert(usb_serial::bus_allocator(board.usb.usbd, clocks));
+// let usb_serial = UsbSerial::new(bus);
+//
+// `poll` has to be called from the USBD interrupt, or at least every 10 ms; otherwise the host
+// gives up on the device.
+
+use core::fmt;
+
+use nrf52840_hal::{
+    clocks::{Clocks, ExternalOscillator, Internal, LfOscStarted},
+    pac::USBD,
+    usbd::{UsbPeripheral, Usbd},
+};
+use usb_device::{
+    bus::UsbBusAllocator,
+    device::{UsbDevice, UsbDeviceBuilder, UsbVidPid},
+    UsbError,
+};
+use usbd_serial::{SerialPort, USB_CLASS_CDC};
+
+use crate::{
+    clock::{self, Duration},
+    Error,
+};
+
+pub type UsbBus = Usbd<UsbPeripheral<'static>>;
+
+/// Longest command, longer lines are dropped
+pub const COMMAND_CAPACITY: usize = 64;
+
+// test VID/PID pair of pid.codes, for a device that is never sold
+const VID_PID: UsbVidPid = UsbVidPid(0x1209, 0x0001);
+// the largest packet of a full speed bulk endpoint
+const PACKET_SIZE: usize = 64;
+// how long `write` waits for the host to read, before the rest is dropped
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub fn bus_allocator(
+    usbd: USBD,
+    clocks: &'static Clocks<ExternalOscillator, Internal, LfOscStarted>,
+) -> UsbBusAllocator<UsbBus> {
+    Usbd::new(UsbPeripheral::new(usbd, clocks))
+}
+
+/// One line received from the host, without the line break
+pub struct CommandLine {
+    bytes: [u8; COMMAND_CAPACITY],
+    length: usize,
+}
+
+impl CommandLine {
+    /// `None` if the line isn't valid UTF-8
+    pub fn as_str(&self) -> Option<&str> {
+        core::str::from_utf8(&self.bytes[..self.length]).ok()
+    }
+}
+
+pub struct UsbSerial {
+    device: UsbDevice<'static, UsbBus>,
+    serial: SerialPort<'static, UsbBus>,
+    // the last packet from the host, bytes from `start` on aren't handled yet
+    received: [u8; PACKET_SIZE],
+    start: usize,
+    end: usize,
+    line: CommandLine,
+    // the line is longer than `COMMAND_CAPACITY` and is dropped at its end
+    overflow: bool,
+}
+
+impl UsbSerial {
+    pub fn new(bus: &'static UsbBusAllocator<UsbBus>) -> Self {
+        let serial = SerialPort::new(bus);
+        let device = UsbDeviceBuilder::new(bus, VID_PID)
+            .manufacturer("Knurling")
+            .product("CO2 monitor")
+            .serial_number("0001")
+            .device_class(USB_CLASS_CDC)
+            .max_packet_size_0(PACKET_SIZE as u8)
+            .build();
+
+        UsbSerial {
+            device,
+            serial,
+            received: [0; PACKET_SIZE],
+            start: 0,
+            end: 0,
+            line: CommandLine {
+                bytes: [0; COMMAND_CAPACITY],
+                length: 0,
+            },
+            overflow: false,
+        }
+    }
+
+    /// Handles USB events, and returns the next complete command. Received characters are
+    /// echoed, as terminals don't show what is typed. Call it until it returns `None`.
+    pub fn poll(&mut self) -> Option<CommandLine> {
+        if self.device.poll(&mut [&mut self.serial]) && self.start == self.end {
+            if let Ok(count) = self.serial.read(&mut self.received) {
+                self.start = 0;
+                self.end = count;
+            }
+        }
+
+        while self.start < self.end {
+            let byte = self.received[self.start];
+            self.start += 1;
+
+            match byte {
+                b'\r' | b'\n' => {
+                    if self.line.length == 0 && !self.overflow {
+                        // the second byte of "\r\n"
+                        continue;
+                    }
+                    self.echo(b"\r\n");
+                    let line = core::mem::replace(
+                        &mut self.line,
+                        CommandLine {
+                            bytes: [0; COMMAND_CAPACITY],
+                            length: 0,
+                        },
+                    );
+                    if core::mem::replace(&mut self.overflow, false) {
+                        defmt::warn!(
+                            "Dropping a command longer than {=usize} bytes",
+                            COMMAND_CAPACITY
+                        );
+                    } else {
+                        return Some(line);
+                    }
+                }
+                byte => {
+                    self.echo(&[byte]);
+                    if self.line.length < COMMAND_CAPACITY {
+                        self.line.bytes[self.line.length] = byte;
+                        self.line.length += 1;
+                    } else {
+                        self.overflow = true;
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// `true` while a program on the host has the port open
+    pub fn is_connected(&self) -> bool {
+        self.serial.dtr()
+    }
+
+    /// Queues the bytes for sending. They are dropped if no program has the port open, and
+    /// `Error::Serial` is returned if the host doesn't read fast enough. While the buffer is full,
+    /// the device is polled, so long replies can be written from the USBD interrupt as well.
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if !self.is_connected() {
+            return Ok(());
+        }
+
+        let deadline = clock::now() + WRITE_TIMEOUT;
+        let mut written = 0;
+        while written < bytes.len() {
+            match self.serial.write(&bytes[written..]) {
+                Ok(count) => written += count,
+                Err(UsbError::WouldBlock) if clock::now() < deadline => {
+                    self.device.poll(&mut [&mut self.serial]);
+                }
+                Err(_) => return Err(Error::Serial),
+            }
+        }
+        Ok(())
+    }
+
+    // a lost echo doesn't matter
+    fn echo(&mut self, bytes: &[u8]) {
+        self.write(bytes).ok();
+    }
+}
+
+// replies of `shell::run`
+impl fmt::Write for UsbSerial {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        self.write(text.as_bytes()).map_err(|_| fmt::Error)
+    }
+}